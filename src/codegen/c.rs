@@ -0,0 +1,259 @@
+//! Generating a C/C++ header with a packed struct for every block
+
+use super::{Codegen, Enum, Member, MemberKind, Struct, build_name, escape_keyword, one_line, pascal_case, sanitize};
+
+/// Words that can't be used as member names in C or C++
+const KEYWORDS: &[&str] = &[
+    "alignas", "alignof", "and", "asm", "auto", "bool", "break", "case", "catch", "char", "class", "const", "constexpr",
+    "continue", "decltype", "default", "delete", "do", "double", "else", "enum", "explicit", "export", "extern", "false",
+    "float", "for", "friend", "goto", "if", "inline", "int", "long", "mutable", "namespace", "new", "not", "nullptr",
+    "operator", "or", "private", "protected", "public", "register", "restrict", "return", "short", "signed", "sizeof",
+    "static", "static_assert", "struct", "switch", "template", "this", "throw", "true", "try", "typedef", "typename",
+    "union", "unsigned", "using", "virtual", "void", "volatile", "while", "xor"
+];
+
+/// Types used by the generated structs, as laid out in tag files
+const PRELUDE: &str = "\
+typedef char TagString[32];
+typedef uint32_t TagFourCC;
+typedef float Angle;
+typedef float Fraction;
+typedef uint32_t ColorRGBInt;
+typedef uint32_t ColorARGBInt;
+
+typedef struct Point2DInt { int16_t x, y; } Point2DInt;
+typedef struct Rectangle2D { int16_t top, left, bottom, right; } Rectangle2D;
+typedef struct Point2D { float x, y; } Point2D;
+typedef struct Point3D { float x, y, z; } Point3D;
+typedef struct Vector2D { float i, j; } Vector2D;
+typedef struct Vector3D { float i, j, k; } Vector3D;
+typedef struct Quaternion { float i, j, k, w; } Quaternion;
+typedef struct Euler2D { float yaw, pitch; } Euler2D;
+typedef struct Euler3D { float yaw, pitch, roll; } Euler3D;
+typedef struct Plane2D { Vector2D normal; float d; } Plane2D;
+typedef struct Plane3D { Vector3D normal; float d; } Plane3D;
+typedef struct ColorRGB { float red, green, blue; } ColorRGB;
+typedef struct ColorARGB { float alpha, red, green, blue; } ColorARGB;
+typedef struct ColorHSV { float hue, saturation, value; } ColorHSV;
+typedef struct ColorAHSV { float alpha, hue, saturation, value; } ColorAHSV;
+
+/* A reference to another tag; the path follows the struct in tag files */
+typedef struct TagReference {
+    TagFourCC tag_group;
+    uint32_t path_pointer;
+    uint32_t path_size;
+    uint32_t tag_id;
+} TagReference;
+static_assert(sizeof(TagReference) == 0x10, \"TagReference should be 0x10 bytes\");
+
+/* A block; its elements follow the struct in tag files */
+typedef struct TagBlock {
+    uint32_t count;
+    uint32_t pointer;
+    uint32_t definition;
+} TagBlock;
+static_assert(sizeof(TagBlock) == 0xC, \"TagBlock should be 0xC bytes\");
+
+/* Tag data; the data follows the struct in tag files */
+typedef struct TagData {
+    uint32_t size;
+    uint32_t flags;
+    uint32_t file_offset;
+    uint32_t pointer;
+    uint32_t definition;
+} TagData;
+static_assert(sizeof(TagData) == 0x14, \"TagData should be 0x14 bytes\");
+";
+
+/// Get the C type of a primitive
+fn primitive_type(type_name: &str) -> &'static str {
+    match type_name {
+        "string" => "TagString",
+        "int8" => "int8_t",
+        "int16" => "int16_t",
+        "int32" => "int32_t",
+        "float_angle" => "Angle",
+        "fourcc" => "TagFourCC",
+        "point2d_int" => "Point2DInt",
+        "rectangle" => "Rectangle2D",
+        "color_rgb_int" => "ColorRGBInt",
+        "color_argb_int" => "ColorARGBInt",
+        "float_clamped" => "Fraction",
+        "point2d" => "Point2D",
+        "point3d" => "Point3D",
+        "vector2d" => "Vector2D",
+        "vector3d" => "Vector3D",
+        "quaternion" => "Quaternion",
+        "euler2d" => "Euler2D",
+        "euler3d" => "Euler3D",
+        "plane2d" => "Plane2D",
+        "plane3d" => "Plane3D",
+        "color_rgb" => "ColorRGB",
+        "color_argb" => "ColorARGB",
+        "color_hsv" => "ColorHSV",
+        "color_ahsv" => "ColorAHSV",
+        _ => "float"
+    }
+}
+
+/// Get the unsigned type a bitfield is stored as
+fn flags_type(size: &str) -> &'static str {
+    match size {
+        "int8" => "uint8_t",
+        "int16" => "uint16_t",
+        _ => "uint32_t"
+    }
+}
+
+fn write_enum(output: &mut String, e: &Enum) {
+    let type_name = pascal_case(&e.name);
+    let prefix = e.name.to_ascii_uppercase();
+    if let Some(name) = &e.field.name {
+        *output += &format!("/* {} */\n", comment_text(&name.name));
+    }
+
+    if e.flags {
+        *output += &format!("typedef {} {};\n", flags_type(e.size), type_name);
+        for o in &e.options {
+            *output += &format!("#define {}_{} 0x{:X}u\n", prefix, o.name.to_ascii_uppercase(), o.value);
+        }
+    }
+    else {
+        *output += &format!("typedef int16_t {};\n", type_name);
+        if !e.options.is_empty() {
+            *output += "enum {\n";
+            for o in &e.options {
+                *output += &format!("    {}_{} = {},\n", prefix, o.name.to_ascii_uppercase(), o.value);
+            }
+            *output += "};\n";
+        }
+    }
+    *output += "\n";
+}
+
+/// Get the declaration of a member, without the semicolon
+fn declaration(member: &Member, name: &str) -> String {
+    match &member.kind {
+        MemberKind::Primitive(type_name) => format!("{} {}", primitive_type(type_name), name),
+        MemberKind::Range(type_name) => format!("{} {}[2]", primitive_type(type_name), name),
+        MemberKind::Padding => format!("uint8_t {}[0x{:X}]", name, member.size),
+        MemberKind::Index(size) => format!("{} {}", primitive_type(size), name),
+        MemberKind::Enum(e) | MemberKind::Flags(e, _) => format!("{} {}", pascal_case(e), name),
+        MemberKind::Reference => format!("TagReference {}", name),
+        MemberKind::Block(_) => format!("TagBlock {}", name),
+        MemberKind::TagData => format!("TagData {}", name),
+        MemberKind::Array(s, count) => format!("{} {}[{}]", pascal_case(s), name, count)
+    }
+}
+
+/// Get text to put in a comment, on one line and without `*/`, which would end a block comment early
+fn comment_text(text: &str) -> String {
+    one_line(text).replace("*/", "* /")
+}
+
+/// Describe a member in a line comment
+fn comment(member: &Member) -> String {
+    let mut comment = format!("0x{:X}", member.offset);
+    if let Some(name) = member.field.and_then(|f| f.name.as_ref()) {
+        comment += &format!(" {}", comment_text(&name.name));
+        if let Some(unit) = &name.unit {
+            comment += &format!(" ({})", comment_text(unit));
+        }
+        if let Some(description) = &name.description {
+            comment += &format!(" - {}", comment_text(description));
+        }
+    }
+    if let MemberKind::Block(s) = &member.kind {
+        comment += &format!(" [{}]", pascal_case(s));
+    }
+
+    // A backslash at the end of a line comment continues it onto the next line, even if there's whitespace after it
+    comment.trim_end_matches(|c: char| c == '\\' || c.is_whitespace()).to_owned()
+}
+
+fn write_struct(output: &mut String, s: &Struct) {
+    let type_name = pascal_case(&s.name);
+    match s.block {
+        Some(block) => *output += &format!("/* {} (maximum {}) */\n", comment_text(block.name.as_deref().unwrap_or(&s.name)), block.maximum),
+        None => *output += "/* array element */\n"
+    }
+
+    // C doesn't allow empty structs, but nothing needs the size of one since blocks are only used through TagBlock
+    if s.members.is_empty() {
+        *output += &format!("typedef struct {} {};\n\n", type_name, type_name);
+        return
+    }
+
+    *output += &format!("typedef struct {} {{\n", type_name);
+    for m in &s.members {
+        let name = escape_keyword(m.name.clone(), KEYWORDS);
+        *output += &format!("    {}; // {}\n", declaration(m, &name), comment(m));
+    }
+    *output += &format!("}} {};\n", type_name);
+    *output += &format!("static_assert(sizeof({}) == 0x{:X}, \"{} should be 0x{:X} bytes\");\n\n", type_name, s.size, type_name, s.size);
+}
+
+/// Generate a header with a packed struct for every block and array, along with enums and bitfield masks
+///
+/// The structs are laid out as in tag files, where everything is big endian.
+pub fn header(codegen: &Codegen) -> String {
+    let mut output = format!(
+        "// Generated by gorilla from the definitions of guerilla {}\n//\n// Structs are laid out as in tag files, where everything is big endian.\n\n",
+        build_name(codegen.definitions)
+    );
+    output += "#ifndef GORILLA_DEFINITIONS_H\n#define GORILLA_DEFINITIONS_H\n\n#include <assert.h>\n#include <stdint.h>\n\n#pragma pack(push, 1)\n\n";
+    output += PRELUDE;
+
+    output += "\n/* Groups */\n";
+    for g in &codegen.groups {
+        output += &format!("#define {}_GROUP 0x{:08X}u // {}\n", sanitize(g.name).to_ascii_uppercase(), g.group.fourcc, pascal_case(&g.struct_name));
+    }
+    output += "\n";
+
+    for e in &codegen.enums {
+        write_enum(&mut output, e);
+    }
+    for s in &codegen.structs {
+        write_struct(&mut output, s);
+    }
+
+    output += "#pragma pack(pop)\n\n#endif\n";
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use tag_file::tests::{definitions, temporary_directory};
+
+    /// Checks the header against offsets and values in a weapon tag
+    const MAIN: &str = r#"
+#include <stddef.h>
+#include "definitions.h"
+
+static_assert(offsetof(WeaponBlock, model) == 0x2C, "model");
+static_assert(offsetof(WeaponBlock, triggers) == 0x3C, "triggers");
+static_assert(offsetof(WeaponBlock, vals[1].y) == 0x68, "vals");
+static_assert(offsetof(WeaponBlock, trigger_index) == 0x80, "trigger_index");
+static_assert(WEAPON_GROUP == 0x77656970u, "group");
+static_assert(WEAPON_BLOCK_FLAGS_B_C == 2 && WEAPON_BLOCK_TYPE_PISTOL == 1, "options");
+
+int main(void) { return 0; }
+"#;
+
+    #[test]
+    fn compile_header() {
+        let definitions = definitions();
+        let directory = temporary_directory("c_compile_header");
+        std::fs::write(directory.join("definitions.h"), header(&Codegen::new(&definitions))).unwrap();
+        std::fs::write(directory.join("main.c"), MAIN).unwrap();
+        std::fs::write(directory.join("main.cpp"), MAIN).unwrap();
+
+        for (compiler, standard, source) in [("cc", "-std=c11", "main.c"), ("c++", "-std=c++11", "main.cpp")] {
+            let output = Command::new(compiler).args([standard, "-Wall", "-Werror", "-fsyntax-only", source]).current_dir(&directory).output().unwrap();
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Generating a 010 Editor binary template (`.bt`) for tag files
+//!
+//! The template reads the tag header, then the main block of whichever group the header says the tag is. Each block is
+//! read as a `<Struct>Elements` with the element count as an argument, which reads the elements and then what each of
+//! them points to as a `<Struct>Children`, which takes its element as an argument.
+
+extern crate serde_json;
+
+use super::{Codegen, Enum, Member, MemberKind, Struct, build_name, escape_keyword, one_line, pascal_case};
+
+/// Words that can't be used as member names in 010 Editor templates
+const KEYWORDS: &[&str] = &[
+    "break", "byte", "case", "char", "const", "continue", "default", "do", "double", "else", "enum", "false", "float",
+    "for", "hfloat", "if", "int", "int16", "int32", "int64", "local", "long", "quad", "return", "short", "signed",
+    "sizeof", "static", "string", "struct", "switch", "true", "typedef", "ubyte", "uchar", "uint", "uint16", "uint32",
+    "uint64", "ulong", "union", "unsigned", "uquad", "ushort", "void", "while", "wchar_t", "wstring", "fixed"
+];
+
+/// Types used by the generated structs
+const PRELUDE: &str = "\
+BigEndian();
+BitfieldRightToLeft();
+
+typedef struct {
+    ubyte unused[0x24] <hidden=true>;
+    uint32 group <format=hex, comment=\"FourCC of the tag's group\">;
+    uint32 crc32 <format=hex>;
+    uint32 header_size;
+    ubyte unused_2[8] <hidden=true>;
+    uint16 version;
+    uint16 endianness <format=hex>;
+    char magic[4];
+} TagHeader;
+
+typedef struct {
+    char group[4];
+    uint32 path_pointer;
+    uint32 path_size;
+    uint32 tag_id <format=hex>;
+} TagReference;
+
+typedef struct {
+    uint32 count;
+    uint32 pointer;
+    uint32 definition;
+} TagBlock;
+
+typedef struct {
+    uint32 size;
+    uint32 flags;
+    uint32 file_offset;
+    uint32 pointer;
+    uint32 definition;
+} TagData;
+
+typedef struct { int16 x; int16 y; } Point2DInt;
+typedef struct { int16 top; int16 left; int16 bottom; int16 right; } Rectangle2D;
+typedef struct { float x; float y; } Point2D;
+typedef struct { float x; float y; float z; } Point3D;
+typedef struct { float i; float j; } Vector2D;
+typedef struct { float i; float j; float k; } Vector3D;
+typedef struct { float i; float j; float k; float w; } Quaternion;
+typedef struct { float yaw; float pitch; } Euler2D;
+typedef struct { float yaw; float pitch; float roll; } Euler3D;
+typedef struct { Vector2D normal; float d; } Plane2D;
+typedef struct { Vector3D normal; float d; } Plane3D;
+typedef struct { float red; float green; float blue; } ColorRGB;
+typedef struct { float alpha; float red; float green; float blue; } ColorARGB;
+typedef struct { float hue; float saturation; float value; } ColorHSV;
+typedef struct { float alpha; float hue; float saturation; float value; } ColorAHSV;
+";
+
+/// Get the 010 Editor type of a primitive
+fn primitive_type(type_name: &str) -> &'static str {
+    match type_name {
+        "int8" => "byte",
+        "int16" => "int16",
+        "int32" => "int32",
+        "fourcc" => "char",
+        "color_rgb_int" | "color_argb_int" => "uint32",
+        "point2d_int" => "Point2DInt",
+        "rectangle" => "Rectangle2D",
+        "point2d" => "Point2D",
+        "point3d" => "Point3D",
+        "vector2d" => "Vector2D",
+        "vector3d" => "Vector3D",
+        "quaternion" => "Quaternion",
+        "euler2d" => "Euler2D",
+        "euler3d" => "Euler3D",
+        "plane2d" => "Plane2D",
+        "plane3d" => "Plane3D",
+        "color_rgb" => "ColorRGB",
+        "color_argb" => "ColorARGB",
+        "color_hsv" => "ColorHSV",
+        "color_ahsv" => "ColorAHSV",
+        _ => "float"
+    }
+}
+
+/// Get the unsigned type a bitfield is stored as
+fn flags_type(size: &str) -> &'static str {
+    match size {
+        "int8" => "ubyte",
+        "int16" => "uint16",
+        _ => "uint32"
+    }
+}
+
+/// Quote text for an attribute
+fn quote(text: &str) -> String {
+    serde_json::Value::from(one_line(text)).to_string()
+}
+
+/// Get the attributes of a member: its guerilla name, and its description and unit as a comment
+fn attributes(member: &Member) -> String {
+    let name = match member.field.and_then(|f| f.name.as_ref()) {
+        Some(n) => n,
+        None => return String::new()
+    };
+    let mut attributes = vec![format!("name={}", quote(&name.name))];
+    let comment: Vec<&str> = name.description.iter().chain(name.unit.iter()).map(|s| s.as_str()).collect();
+    if !comment.is_empty() {
+        attributes.push(format!("comment={}", quote(&comment.join(" - "))));
+    }
+    format!(" <{}>", attributes.join(", "))
+}
+
+fn write_enum(output: &mut String, e: &Enum) {
+    let type_name = pascal_case(&e.name);
+    if let Some(name) = &e.field.name {
+        *output += &format!("// {}\n", one_line(&name.name));
+    }
+
+    if e.flags {
+        let storage = flags_type(e.size);
+        *output += "typedef struct {\n";
+        for o in &e.options {
+            *output += &format!("    {} {} : 1;\n", storage, escape_keyword(o.name.clone(), KEYWORDS));
+        }
+        // Make sure the whole bitfield is read even if it has no bits
+        if e.options.is_empty() {
+            *output += &format!("    {} unused;\n", storage);
+        }
+        *output += &format!("}} {};\n\n", type_name);
+    }
+    // Enums need at least one option
+    else if e.options.is_empty() {
+        *output += &format!("typedef int16 {};\n\n", type_name);
+    }
+    else {
+        // Enum constants share one namespace, so they're prefixed with the enum's name
+        let prefix = e.name.to_ascii_uppercase();
+        *output += "typedef enum <int16> {\n";
+        let options: Vec<String> = e.options.iter().map(|o| format!("    {}_{} = {}", prefix, o.name.to_ascii_uppercase(), o.value)).collect();
+        *output += &options.join(",\n");
+        *output += &format!("\n}} {};\n\n", type_name);
+    }
+}
+
+fn write_struct(output: &mut String, s: &Struct) {
+    match s.block {
+        Some(block) => *output += &format!("// {} (maximum {})\n", one_line(block.name.as_deref().unwrap_or(&s.name)), block.maximum),
+        None => *output += "// array element\n"
+    }
+    *output += "typedef struct {\n";
+    for m in &s.members {
+        let name = escape_keyword(m.name.clone(), KEYWORDS);
+        let declaration = match &m.kind {
+            MemberKind::Primitive("string") => format!("char {}[32]", name),
+            MemberKind::Primitive("fourcc") => format!("char {}[4]", name),
+            MemberKind::Primitive(type_name) => format!("{} {}", primitive_type(type_name), name),
+            MemberKind::Range(type_name) => format!("{} {}[2]", primitive_type(type_name), name),
+            MemberKind::Padding => format!("ubyte {}[0x{:X}] <hidden=true>", name, m.size),
+            MemberKind::Index(size) => format!("{} {}", primitive_type(size), name),
+            MemberKind::Enum(e) | MemberKind::Flags(e, _) => format!("{} {}", pascal_case(e), name),
+            MemberKind::Reference => format!("TagReference {}", name),
+            MemberKind::Block(_) => format!("TagBlock {}", name),
+            MemberKind::TagData => format!("TagData {}", name),
+            MemberKind::Array(n, count) => format!("{} {}[{}]", pascal_case(n), name, count)
+        };
+        *output += &format!("    {}{};\n", declaration, attributes(m));
+    }
+    *output += &format!("}} {};\n\n", pascal_case(&s.name));
+}
+
+/// Write the struct for what follows each element of a struct, which takes the element as an argument
+fn write_children(output: &mut String, codegen: &Codegen, s: &Struct) {
+    let arrays: Vec<(&Member, &Struct)> = s.members.iter().filter_map(|m| match &m.kind {
+        MemberKind::Array(n, _) => codegen.find_struct(n).filter(|n| codegen.has_children(n)).map(|n| (m, n)),
+        _ => None
+    }).collect();
+
+    // Children of arrays are read in the children of what they're in, so they have to come first
+    for (_, n) in &arrays {
+        write_children(output, codegen, n);
+    }
+
+    let type_name = pascal_case(&s.name);
+    *output += &format!("typedef struct ({} &fixed) {{\n", type_name);
+    if !arrays.is_empty() {
+        *output += "    local uint32 _i;\n";
+    }
+    for m in &s.members {
+        let name = escape_keyword(m.name.clone(), KEYWORDS);
+        match &m.kind {
+            MemberKind::Reference => *output += &format!(
+                "    if (fixed.{}.path_size > 0) char {}[fixed.{}.path_size + 1]{};\n", name, name, name, attributes(m)
+            ),
+            MemberKind::Block(n) => *output += &format!(
+                "    if (fixed.{}.count > 0) {}Elements {}(fixed.{}.count){};\n", name, pascal_case(n), name, name, attributes(m)
+            ),
+            MemberKind::TagData => *output += &format!("    if (fixed.{}.size > 0) ubyte {}[fixed.{}.size]{};\n", name, name, name, attributes(m)),
+            MemberKind::Array(n, count) if arrays.iter().any(|(a, _)| a.name == m.name) => *output += &format!(
+                "    for (_i = 0; _i < {}; _i++) {}Children {}(fixed.{}[_i]){};\n", count, pascal_case(n), name, name, attributes(m)
+            ),
+            _ => ()
+        }
+    }
+    *output += &format!("}} {}Children;\n\n", type_name);
+}
+
+fn write_elements(output: &mut String, codegen: &Codegen, s: &Struct) {
+    let type_name = pascal_case(&s.name);
+    *output += &format!("typedef struct (uint32 count) {{\n    {} elements[count];\n", type_name);
+    if codegen.has_children(s) {
+        *output += &format!("    local uint32 _i;\n    for (_i = 0; _i < count; _i++) {}Children children(elements[_i]);\n", type_name);
+    }
+    *output += &format!("}} {}Elements;\n\n", type_name);
+}
+
+/// Generate a 010 Editor binary template for tag files of every group
+pub fn template(codegen: &Codegen) -> String {
+    let mut output = format!(
+        "//------------------------------------------------\n//--- 010 Editor Binary Template\n//\n//   File: Halo: Combat Evolved tag file (guerilla {})\n//   Authors: Generated by gorilla\n//------------------------------------------------\n\n",
+        build_name(codegen.definitions)
+    );
+    output += PRELUDE;
+    output += "\n";
+
+    for e in &codegen.enums {
+        write_enum(&mut output, e);
+    }
+    for s in &codegen.structs {
+        write_struct(&mut output, s);
+    }
+    for s in codegen.structs.iter().filter(|s| s.block.is_some()) {
+        if codegen.has_children(s) {
+            write_children(&mut output, codegen, s);
+        }
+        write_elements(&mut output, codegen, s);
+    }
+
+    output += "TagHeader header;\nswitch (header.group) {\n";
+    for g in &codegen.groups {
+        output += &format!(
+            "    case 0x{:08X}: {}Elements {}(1) <name=\"{}\">; break;\n",
+            g.group.fourcc, pascal_case(&g.struct_name), escape_keyword(super::sanitize(g.name), KEYWORDS), g.name
+        );
+    }
+    output += "    default: Warning(\"Unknown tag group\"); break;\n}\n";
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use tag_file::tests::definitions;
+
+    /// Get the size of each type declared in a template, along with the names of each struct's visible members
+    fn declared_types(template: &str) -> (BTreeMap<String, usize>, BTreeMap<String, Vec<String>>) {
+        let mut sizes: BTreeMap<String, usize> = [("ubyte", 1), ("byte", 1), ("char", 1), ("uint16", 2), ("int16", 2), ("uint32", 4), ("int32", 4), ("float", 4)]
+            .iter().map(|(t, s)| (t.to_string(), *s)).collect();
+        let mut members = BTreeMap::new();
+
+        // Put each declaration on its own line
+        let template = template.replace("{ ", "{\n").replace("; ", ";\n").replace("; }", ";\n}");
+        let mut lines = template.lines().map(str::trim);
+        while let Some(line) = lines.next() {
+            if let Some(underlying) = line.strip_prefix("typedef enum <") {
+                let name = lines.by_ref().find_map(|l| l.strip_prefix("} ")).unwrap().trim_end_matches(';');
+                sizes.insert(name.to_owned(), sizes[underlying.trim_end_matches("> {")]);
+                continue
+            }
+
+            // Structs with parameters are read after the elements and depend on them, so they aren't a fixed size
+            if line != "typedef struct {" {
+                continue
+            }
+            let mut size = 0;
+            let mut names = Vec::new();
+            let name = loop {
+                let member = lines.next().unwrap();
+                if let Some(name) = member.strip_prefix("} ") {
+                    break name.trim_end_matches(';')
+                }
+                let declaration = member.split(" <").next().unwrap().trim_end_matches(';');
+                let (type_name, member_name) = declaration.split_once(' ').unwrap();
+
+                // Bits all go in one value of their type
+                if let Some((member_name, _)) = member_name.split_once(" : ") {
+                    size = sizes[type_name];
+                    names.push(member_name.to_owned());
+                    continue
+                }
+                let (member_name, count) = match member_name.split_once('[') {
+                    Some((n, count)) => {
+                        let count = count.trim_end_matches(']');
+                        (n, count.strip_prefix("0x").map_or_else(|| count.parse().unwrap(), |c| usize::from_str_radix(c, 16).unwrap()))
+                    },
+                    None => (member_name, 1)
+                };
+                size += sizes[type_name] * count;
+                if !member.contains("<hidden=true>") {
+                    names.push(member_name.to_owned());
+                }
+            };
+            sizes.insert(name.to_owned(), size);
+            members.insert(name.to_owned(), names);
+        }
+        (sizes, members)
+    }
+
+    #[test]
+    fn structs_match_layout() {
+        let definitions = definitions();
+        let codegen = Codegen::new(&definitions);
+        let template = template(&codegen);
+        let (sizes, members) = declared_types(&template);
+
+        assert_eq!(sizes["TagHeader"], 0x40);
+        for s in &codegen.structs {
+            let name = pascal_case(&s.name);
+            assert_eq!(sizes[&name], s.size, "{}", name);
+            let names: Vec<String> = s.members.iter().filter(|m| !matches!(m.kind, MemberKind::Padding)).map(|m| m.name.clone()).collect();
+            assert_eq!(members[&name], names);
+        }
+        assert!(template.contains("    case 0x77656970: WeaponBlockElements weapon(1) <name=\"weapon\">; break;\n"));
+    }
+}
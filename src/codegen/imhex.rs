@@ -0,0 +1,329 @@
+//! Generating an ImHex pattern (`.hexpat`) for tag files
+//!
+//! The pattern reads the tag header, then the main block of whichever group the header says the tag is. Each block is
+//! read as a `<Struct>Elements` with the element count as a template parameter, which reads the elements and then what
+//! each of them points to as a `<Struct>Children`. Children find their element by placing it again, hidden, at its
+//! address.
+
+extern crate serde_json;
+
+use super::{Codegen, Enum, Member, MemberKind, Struct, build_name, escape_keyword, one_line, pascal_case};
+
+/// Words that can't be used as member names in ImHex patterns
+const KEYWORDS: &[&str] = &[
+    "addressof", "auto", "be", "bitfield", "bool", "break", "char", "char16", "const", "continue", "double", "else",
+    "enum", "false", "float", "fn", "for", "if", "import", "in", "le", "match", "namespace", "null", "out", "padding",
+    "parent", "return", "s8", "s16", "s32", "s64", "s128", "sizeof", "str", "struct", "this", "true", "try", "u8",
+    "u16", "u32", "u64", "u128", "union", "using", "while", "fixed"
+];
+
+/// Types used by the generated structs
+const PRELUDE: &str = "\
+#pragma endian big
+
+import std.core;
+
+struct TagHeader {
+    padding[0x24];
+    u32 group [[comment(\"FourCC of the tag's group\")]];
+    u32 crc32;
+    u32 header_size;
+    padding[8];
+    u16 version;
+    u16 endianness;
+    char magic[4];
+};
+
+struct TagReference {
+    char group[4];
+    u32 path_pointer;
+    u32 path_size;
+    u32 tag_id;
+};
+
+struct TagBlock {
+    u32 count;
+    u32 pointer;
+    u32 definition;
+};
+
+struct TagData {
+    u32 size;
+    u32 flags;
+    u32 file_offset;
+    u32 pointer;
+    u32 definition;
+};
+
+struct Point2DInt { s16 x; s16 y; };
+struct Rectangle2D { s16 top; s16 left; s16 bottom; s16 right; };
+struct Point2D { float x; float y; };
+struct Point3D { float x; float y; float z; };
+struct Vector2D { float i; float j; };
+struct Vector3D { float i; float j; float k; };
+struct Quaternion { float i; float j; float k; float w; };
+struct Euler2D { float yaw; float pitch; };
+struct Euler3D { float yaw; float pitch; float roll; };
+struct Plane2D { Vector2D normal; float d; };
+struct Plane3D { Vector3D normal; float d; };
+struct ColorRGB { float red; float green; float blue; };
+struct ColorARGB { float alpha; float red; float green; float blue; };
+struct ColorHSV { float hue; float saturation; float value; };
+struct ColorAHSV { float alpha; float hue; float saturation; float value; };
+";
+
+/// Get the ImHex type of a primitive
+fn primitive_type(type_name: &str) -> &'static str {
+    match type_name {
+        "int8" => "s8",
+        "int16" => "s16",
+        "int32" => "s32",
+        "fourcc" => "char",
+        "color_rgb_int" | "color_argb_int" => "u32",
+        "point2d_int" => "Point2DInt",
+        "rectangle" => "Rectangle2D",
+        "point2d" => "Point2D",
+        "point3d" => "Point3D",
+        "vector2d" => "Vector2D",
+        "vector3d" => "Vector3D",
+        "quaternion" => "Quaternion",
+        "euler2d" => "Euler2D",
+        "euler3d" => "Euler3D",
+        "plane2d" => "Plane2D",
+        "plane3d" => "Plane3D",
+        "color_rgb" => "ColorRGB",
+        "color_argb" => "ColorARGB",
+        "color_hsv" => "ColorHSV",
+        "color_ahsv" => "ColorAHSV",
+        _ => "float"
+    }
+}
+
+/// Quote text for an attribute
+fn quote(text: &str) -> String {
+    serde_json::Value::from(one_line(text)).to_string()
+}
+
+/// Get the attributes of a member: its guerilla name, and its description and unit as a comment
+fn attributes(member: &Member) -> String {
+    let name = match member.field.and_then(|f| f.name.as_ref()) {
+        Some(n) => n,
+        None => return String::new()
+    };
+    let mut attributes = vec![format!("name({})", quote(&name.name))];
+    let comment: Vec<&str> = name.description.iter().chain(name.unit.iter()).map(|s| s.as_str()).collect();
+    if !comment.is_empty() {
+        attributes.push(format!("comment({})", quote(&comment.join(" - "))));
+    }
+    format!(" [[{}]]", attributes.join(", "))
+}
+
+fn write_enum(output: &mut String, e: &Enum) {
+    let type_name = pascal_case(&e.name);
+    if let Some(name) = &e.field.name {
+        *output += &format!("// {}\n", one_line(&name.name));
+    }
+
+    if e.flags {
+        let bits = super::primitive_size(e.size) * 8;
+        *output += &format!("bitfield {} {{\n", type_name);
+        for o in &e.options {
+            *output += &format!("    {} : 1;\n", escape_keyword(o.name.clone(), KEYWORDS));
+        }
+        if e.options.len() < bits {
+            *output += &format!("    padding : {};\n", bits - e.options.len());
+        }
+        *output += &format!("}} [[bitfield_order(std::core::BitfieldOrder::LeastToMostSignificant, {})]];\n\n", bits);
+    }
+    // Enums need at least one option
+    else if e.options.is_empty() {
+        *output += &format!("using {} = s16;\n\n", type_name);
+    }
+    else {
+        *output += &format!("enum {} : s16 {{\n", type_name);
+        let options: Vec<String> = e.options.iter().map(|o| format!("    {} = {}", escape_keyword(o.name.clone(), KEYWORDS), o.value)).collect();
+        *output += &options.join(",\n");
+        *output += "\n};\n\n";
+    }
+}
+
+fn write_struct(output: &mut String, s: &Struct) {
+    match s.block {
+        Some(block) => *output += &format!("// {} (maximum {})\n", one_line(block.name.as_deref().unwrap_or(&s.name)), block.maximum),
+        None => *output += "// array element\n"
+    }
+    *output += &format!("struct {} {{\n", pascal_case(&s.name));
+    for m in &s.members {
+        let name = escape_keyword(m.name.clone(), KEYWORDS);
+        let declaration = match &m.kind {
+            MemberKind::Primitive("string") => format!("char {}[32]", name),
+            MemberKind::Primitive("fourcc") => format!("char {}[4]", name),
+            MemberKind::Primitive(type_name) => format!("{} {}", primitive_type(type_name), name),
+            MemberKind::Range(type_name) => format!("{} {}[2]", primitive_type(type_name), name),
+            MemberKind::Padding => format!("padding[0x{:X}]", m.size),
+            MemberKind::Index(size) => format!("{} {}", primitive_type(size), name),
+            MemberKind::Enum(e) | MemberKind::Flags(e, _) => format!("{} {}", pascal_case(e), name),
+            MemberKind::Reference => format!("TagReference {}", name),
+            MemberKind::Block(_) => format!("TagBlock {}", name),
+            MemberKind::TagData => format!("TagData {}", name),
+            MemberKind::Array(n, count) => format!("{} {}[{}]", pascal_case(n), name, count)
+        };
+        *output += &format!("    {}{};\n", declaration, attributes(m));
+    }
+    *output += "};\n\n";
+}
+
+/// Write the struct for what follows each element of a struct, given how to find the element from the children
+fn write_children(output: &mut String, codegen: &Codegen, s: &Struct, element: &str) {
+    // Arrays are read in the children of what they're in, so their elements are found through it
+    for m in &s.members {
+        if let MemberKind::Array(n, _) = &m.kind {
+            if let Some(n) = codegen.find_struct(n).filter(|n| codegen.has_children(n)) {
+                write_children(output, codegen, n, &format!("parent.fixed.{}[std::core::array_index()]", escape_keyword(m.name.clone(), KEYWORDS)));
+            }
+        }
+    }
+
+    let type_name = pascal_case(&s.name);
+    *output += &format!("struct {}Children {{\n", type_name);
+    *output += &format!("    {} fixed @ addressof({}) [[hidden]];\n", type_name, element);
+    for m in &s.members {
+        let name = escape_keyword(m.name.clone(), KEYWORDS);
+        match &m.kind {
+            MemberKind::Reference => *output += &format!(
+                "    if (fixed.{}.path_size > 0) char {}[fixed.{}.path_size + 1]{};\n", name, name, name, attributes(m)
+            ),
+            MemberKind::Block(n) => *output += &format!("    {}Elements<fixed.{}.count> {}{};\n", pascal_case(n), name, name, attributes(m)),
+            MemberKind::TagData => *output += &format!("    u8 {}[fixed.{}.size]{};\n", name, name, attributes(m)),
+            MemberKind::Array(n, count) if codegen.find_struct(n).map(|n| codegen.has_children(n)).unwrap_or(false) => {
+                *output += &format!("    {}Children {}[{}]{};\n", pascal_case(n), name, count, attributes(m));
+            },
+            _ => ()
+        }
+    }
+    *output += "};\n\n";
+}
+
+fn write_elements(output: &mut String, codegen: &Codegen, s: &Struct) {
+    let type_name = pascal_case(&s.name);
+    *output += &format!("struct {}Elements<auto count> {{\n    {} elements[count];\n", type_name, type_name);
+    if codegen.has_children(s) {
+        *output += &format!("    {}Children children[count];\n", type_name);
+    }
+    *output += "};\n\n";
+}
+
+/// Generate an ImHex pattern for tag files of every group
+pub fn pattern(codegen: &Codegen) -> String {
+    let mut output = format!("// Generated by gorilla from the definitions of guerilla {}\n\n", build_name(codegen.definitions));
+    output += &format!("#pragma description \"Halo: Combat Evolved tag file (guerilla {})\"\n", build_name(codegen.definitions));
+    output += PRELUDE;
+    output += "\n";
+
+    for e in &codegen.enums {
+        write_enum(&mut output, e);
+    }
+
+    for s in &codegen.structs {
+        write_struct(&mut output, s);
+    }
+    for s in codegen.structs.iter().filter(|s| s.block.is_some()) {
+        if codegen.has_children(s) {
+            write_children(&mut output, codegen, s, "parent.elements[std::core::array_index()]");
+        }
+        write_elements(&mut output, codegen, s);
+    }
+
+    output += "struct Tag {\n    TagHeader header;\n";
+    for (i, g) in codegen.groups.iter().enumerate() {
+        output += &format!(
+            "    {}if (header.group == 0x{:08X}) {}Elements<1> {} [[name(\"{}\")]];\n",
+            if i == 0 { "" } else { "else " }, g.group.fourcc, pascal_case(&g.struct_name), escape_keyword(super::sanitize(g.name), KEYWORDS), g.name
+        );
+    }
+    output += "};\n\nTag tag @ 0x00;\n";
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use tag_file::tests::definitions;
+
+    /// Get the size of each type declared in a pattern, along with the names of each struct's members
+    fn declared_types(pattern: &str) -> (BTreeMap<String, usize>, BTreeMap<String, Vec<String>>) {
+        let mut sizes: BTreeMap<String, usize> = [("u8", 1), ("s8", 1), ("char", 1), ("u16", 2), ("s16", 2), ("u32", 4), ("s32", 4), ("float", 4)]
+            .iter().map(|(t, s)| (t.to_string(), *s)).collect();
+        let mut members = BTreeMap::new();
+
+        // Put each declaration on its own line
+        let pattern = pattern.replace("{ ", "{\n").replace("; ", ";\n").replace("; }", ";\n}");
+        let mut lines = pattern.lines();
+        while let Some(line) = lines.next() {
+            if let Some(rest) = line.strip_prefix("enum ") {
+                let (name, underlying) = rest.trim_end_matches(" {").split_once(" : ").unwrap();
+                sizes.insert(name.to_owned(), sizes[underlying]);
+                continue
+            }
+            let (name, bitfield) = match (line.strip_prefix("struct "), line.strip_prefix("bitfield ")) {
+                (Some(rest), _) if rest.ends_with(" {") => (rest.trim_end_matches(" {"), false),
+                (_, Some(rest)) => (rest.trim_end_matches(" {"), true),
+                _ => continue
+            };
+
+            // Structs read after the elements, which depend on the elements, aren't a fixed size
+            let mut size = 0;
+            let mut names = Vec::new();
+            let mut fixed = !name.contains('<');
+            for member in lines.by_ref().map(str::trim).take_while(|l| !l.starts_with('}')) {
+                let declaration = member.split(" [[").next().unwrap().trim_end_matches(';');
+                if !fixed || declaration.starts_with("if ") || declaration.contains(" @ ") {
+                    fixed = false;
+                    continue
+                }
+                if bitfield {
+                    size += declaration.split(" : ").nth(1).unwrap().parse::<usize>().unwrap();
+                    continue
+                }
+                let (declaration, count) = match declaration.split_once('[') {
+                    Some((d, count)) => {
+                        let count = count.trim_end_matches(']');
+                        (d, count.strip_prefix("0x").map_or_else(|| count.parse().unwrap(), |c| usize::from_str_radix(c, 16).unwrap()))
+                    },
+                    None => (declaration, 1)
+                };
+                match declaration.split_once(' ') {
+                    Some((type_name, member_name)) => {
+                        size += sizes[type_name] * count;
+                        names.push(member_name.to_owned());
+                    },
+                    None => size += count
+                }
+            }
+            if fixed {
+                sizes.insert(name.to_owned(), if bitfield { size / 8 } else { size });
+                members.insert(name.to_owned(), names);
+            }
+        }
+        (sizes, members)
+    }
+
+    #[test]
+    fn structs_match_layout() {
+        let definitions = definitions();
+        let codegen = Codegen::new(&definitions);
+        let pattern = pattern(&codegen);
+        let (sizes, members) = declared_types(&pattern);
+
+        assert_eq!(sizes["TagHeader"], 0x40);
+        for s in &codegen.structs {
+            let name = pascal_case(&s.name);
+            assert_eq!(sizes[&name], s.size, "{}", name);
+            let names: Vec<String> = s.members.iter().filter(|m| !matches!(m.kind, MemberKind::Padding)).map(|m| m.name.clone()).collect();
+            assert_eq!(members[&name], names);
+        }
+        assert!(pattern.contains("    else if (header.group == 0x77656970) WeaponBlockElements<1> weapon [[name(\"weapon\")]];\n"));
+    }
+}
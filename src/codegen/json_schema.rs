@@ -0,0 +1,193 @@
+//! Generating a JSON Schema for each group's tags converted to JSON
+//!
+//! The schemas accept what [`apply_json`](crate::tag_file::json::apply_json) accepts, so no field is required, since
+//! only the fields present are changed. Every struct reachable from the group goes in `$defs`.
+
+extern crate serde_json;
+use self::serde_json::{Map, Value};
+
+use def_dumper::BlockFieldType;
+use def_dumper::schema::{SCHEMA_DRAFT, object};
+use super::{Codegen, GroupStruct, Member, MemberKind, Struct, one_line};
+
+fn integer(minimum: i64, maximum: i64) -> Value {
+    object(vec![("type", Value::from("integer")), ("minimum", Value::from(minimum)), ("maximum", Value::from(maximum))])
+}
+
+/// Get the schema of an integer stored as the given primitive
+fn integer_of(type_name: &str) -> Value {
+    match type_name {
+        "int8" => integer(i8::MIN as i64, i8::MAX as i64),
+        "int16" => integer(i16::MIN as i64, i16::MAX as i64),
+        _ => integer(i32::MIN as i64, i32::MAX as i64)
+    }
+}
+
+fn fixed_array(items: Value, count: usize) -> Value {
+    object(vec![("type", Value::from("array")), ("items", items), ("minItems", Value::from(count)), ("maxItems", Value::from(count))])
+}
+
+/// Get the schema of a primitive, or the bounds of a range of a primitive
+fn primitive_schema(type_name: &str, bounds: bool) -> Value {
+    let (components, item) = match type_name {
+        "string" => return object(vec![("type", Value::from("string")), ("maxLength", Value::from(31))]),
+        "fourcc" => return object(vec![("type", Value::from("string")), ("minLength", Value::from(4)), ("maxLength", Value::from(4))]),
+        "int8" | "int16" | "int32" => (1, integer_of(type_name)),
+        "color_rgb_int" | "color_argb_int" => (1, object(vec![("type", Value::from("integer"))])),
+        "point2d_int" => (2, integer_of("int16")),
+        "rectangle" => (4, integer_of("int16")),
+        "point2d" | "vector2d" | "euler2d" => (2, object(vec![("type", Value::from("number"))])),
+        "point3d" | "vector3d" | "euler3d" | "plane2d" | "color_rgb" | "color_hsv" => (3, object(vec![("type", Value::from("number"))])),
+        "quaternion" | "plane3d" | "color_argb" | "color_ahsv" => (4, object(vec![("type", Value::from("number"))])),
+        _ => (1, object(vec![("type", Value::from("number"))]))
+    };
+    let count = if bounds { components * 2 } else { components };
+    if count == 1 { item } else { fixed_array(item, count) }
+}
+
+fn struct_reference(name: &str) -> Value {
+    object(vec![("$ref", Value::from(format!("#/$defs/{}", name)))])
+}
+
+/// Get the schema of a member
+fn member_schema(codegen: &Codegen, member: &Member) -> Value {
+    let mut schema = match (&member.kind, member.field.map(|f| &f.block_type)) {
+        (MemberKind::Primitive(type_name), _) => primitive_schema(type_name, false),
+        (MemberKind::Range(type_name), _) => primitive_schema(type_name, true),
+        (MemberKind::Index(size), _) => {
+            let mut schema = integer_of(size);
+            schema["minimum"] = Value::from(-1);
+            schema
+        },
+        (MemberKind::Enum(e), _) => {
+            let options: Vec<&str> = codegen.find_enum(e).map(|n| n.options.iter().map(|o| o.identifier.as_str()).collect()).unwrap_or_default();
+            object(vec![("anyOf", Value::from(vec![object(vec![("enum", Value::from(options))]), integer_of("int16")]))])
+        },
+        (MemberKind::Flags(e, size), _) => {
+            let bits: Map<String, Value> = codegen.find_enum(e)
+                .map(|n| n.options.iter().map(|o| (o.identifier.clone(), object(vec![("type", Value::from("boolean"))]))).collect())
+                .unwrap_or_default();
+            let bits = object(vec![
+                ("type", Value::from("object")),
+                ("properties", Value::Object(bits)),
+                ("patternProperties", object(vec![("^bit_[0-9]+$", object(vec![("type", Value::from("boolean"))]))])),
+                ("additionalProperties", Value::from(false))
+            ]);
+            object(vec![("anyOf", Value::from(vec![bits, integer_of(size)]))])
+        },
+        (MemberKind::Reference, _) => {
+            let groups: Vec<Value> = codegen.definitions.groups.keys().map(|g| Value::from(g.as_str())).chain(std::iter::once(Value::Null)).collect();
+            let reference = object(vec![
+                ("type", Value::from("object")),
+                ("properties", object(vec![
+                    ("group", object(vec![("enum", Value::from(groups))])),
+                    ("path", object(vec![("type", Value::from("string"))]))
+                ])),
+                ("additionalProperties", Value::from(false))
+            ]);
+            object(vec![("anyOf", Value::from(vec![reference, object(vec![("type", Value::from("null"))])]))])
+        },
+        (MemberKind::Block(s), Some(BlockFieldType::Block(block))) => object(vec![
+            ("type", Value::from("array")),
+            ("items", struct_reference(s)),
+            ("maxItems", Value::from(block.maximum))
+        ]),
+        (MemberKind::Block(s), _) => object(vec![("type", Value::from("array")), ("items", struct_reference(s))]),
+        (MemberKind::Array(s, count), _) => fixed_array(struct_reference(s), *count),
+        (MemberKind::TagData, Some(BlockFieldType::TagData(_, max_length))) => object(vec![
+            ("type", Value::from("string")),
+            ("pattern", Value::from("^([0-9A-Fa-f]{2})*$")),
+            ("maxLength", Value::from(max_length.saturating_mul(2)))
+        ]),
+        (MemberKind::TagData, _) => object(vec![("type", Value::from("string")), ("pattern", Value::from("^([0-9A-Fa-f]{2})*$"))]),
+        (MemberKind::Padding, _) => Value::Bool(false)
+    };
+
+    if let (Some(name), Value::Object(map)) = (member.field.and_then(|f| f.name.as_ref()), &mut schema) {
+        map.insert("title".to_owned(), Value::from(one_line(&name.name)));
+        let mut description = Vec::new();
+        if let Some(d) = &name.description {
+            description.push(one_line(d));
+        }
+        if let Some(u) = &name.unit {
+            description.push(format!("Unit: {}", one_line(u)));
+        }
+        if let Some(BlockFieldType::Reference(groups)) = member.field.map(|f| &f.block_type) {
+            if !groups.is_empty() {
+                description.push(format!("Allowed groups: {}", groups.join(", ")));
+            }
+        }
+        if !description.is_empty() {
+            map.insert("description".to_owned(), Value::from(description.join("\n")));
+        }
+        if name.deprecated {
+            map.insert("deprecated".to_owned(), Value::from(true));
+        }
+        if name.read_only {
+            map.insert("readOnly".to_owned(), Value::from(true));
+        }
+    }
+    schema
+}
+
+fn struct_schema(codegen: &Codegen, s: &Struct) -> Value {
+    let properties: Map<String, Value> = s.members.iter()
+        .filter(|m| !matches!(m.kind, MemberKind::Padding))
+        .map(|m| (m.identifier.clone(), member_schema(codegen, m)))
+        .collect();
+    let mut schema = vec![("type", Value::from("object")), ("properties", Value::Object(properties)), ("additionalProperties", Value::from(false))];
+    if let Some(name) = s.block.and_then(|b| b.name.as_ref()) {
+        schema.insert(0, ("title", Value::from(name.as_str())));
+    }
+    object(schema)
+}
+
+/// Get a JSON Schema for a group's tags converted to JSON
+pub fn tag_schema(codegen: &Codegen, group: &GroupStruct) -> Value {
+    let defs: Map<String, Value> = codegen.structs_used_by(&group.struct_name).into_iter()
+        .map(|s| (s.name.clone(), struct_schema(codegen, s)))
+        .collect();
+    object(vec![
+        ("$schema", Value::from(SCHEMA_DRAFT)),
+        ("title", Value::from(format!("{} tag", group.name))),
+        ("$ref", struct_reference(&group.struct_name)["$ref"].clone()),
+        ("$defs", Value::Object(defs))
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use def_dumper::schema::tests::is_valid;
+    use tag_file::{StructLayout, json};
+    use tag_file::tests::{definitions, tag_with};
+
+    #[test]
+    fn validate_tag_json() {
+        let definitions = definitions();
+        let codegen = Codegen::new(&definitions);
+        let group = codegen.groups.iter().find(|g| g.name == "weapon").unwrap();
+        let schema = tag_schema(&codegen, group);
+
+        let tag = tag_with(&definitions, "weapon", serde_json::json!({
+            "name": "gun",
+            "flags": {"b_c": true},
+            "type": "pistol",
+            "model": {"group": "model", "path": "weapons\\gun\\gun"},
+            "triggers": [{"rounds_per_second": 8, "count": 3}],
+            "data": "DEADBEEF",
+            "trigger_index": 0
+        }));
+        let mut value = json::struct_to_json(&definitions, &StructLayout::of(&definitions.groups["weapon"].block), &tag.data);
+        assert!(is_valid(&schema, &value, "tag_schema_valid"));
+
+        // Only some fields can be given
+        assert!(is_valid(&schema, &serde_json::json!({"rate": 2.5}), "tag_schema_partial"));
+
+        value["triggers"][0]["count"] = Value::from(40000);
+        assert!(!is_valid(&schema, &value, "tag_schema_out_of_range"));
+        value["triggers"][0]["count"] = Value::from(3);
+        value["type"] = Value::from("shotgun");
+        assert!(!is_valid(&schema, &value, "tag_schema_bad_option"));
+    }
+}
@@ -0,0 +1,370 @@
+//! Generating Kaitai Struct specifications (`.ksy`) for tag files
+//!
+//! Each group gets its own spec with the group's block as the root. Since the elements of a block are followed by what
+//! each element points to (blocks, tag reference paths and tag data) in order, every struct with anything after it gets
+//! a `<struct>_children` type which takes the element it belongs to as a parameter, and every block gets a
+//! `<struct>_block` type which reads the elements and then their children.
+
+extern crate serde_json;
+
+use super::{Codegen, Enum, GroupStruct, Member, MemberKind, Struct, build_name, escape_keyword, one_line};
+
+/// Words that can't be used as identifiers in Kaitai Struct expressions
+const KEYWORDS: &[&str] = &["and", "or", "not", "true", "false", "as", "sizeof", "bitsizeof", "raw", "fixed"];
+
+/// Types used by the generated specs
+const PRELUDE: &str = "  tag_header:
+    params:
+      - id: expected_group
+        type: u4
+    seq:
+      - id: unused
+        size: 0x24
+      - id: group
+        type: u4
+        valid: expected_group
+      - id: crc32
+        type: u4
+      - id: header_size
+        type: u4
+      - id: unused_2
+        size: 8
+      - id: version
+        type: u2
+      - id: endianness
+        contents: [0x00, 0xFF]
+      - id: magic
+        contents: blam
+  tag_reference:
+    doc: A reference to another tag; the path follows the parent's elements
+    seq:
+      - id: group
+        type: u4
+      - id: path_pointer
+        type: u4
+      - id: path_size
+        type: u4
+      - id: tag_id
+        type: u4
+  tag_block:
+    doc: A block; its elements follow the parent's elements
+    seq:
+      - id: count
+        type: u4
+      - id: pointer
+        type: u4
+      - id: definition
+        type: u4
+  tag_data:
+    doc: Tag data; the data follows the parent's elements
+    seq:
+      - id: size
+        type: u4
+      - id: flags
+        type: u4
+      - id: file_offset
+        type: u4
+      - id: pointer
+        type: u4
+      - id: definition
+        type: u4
+";
+
+/// Components of each primitive with more than one, and the Kaitai type of each component
+const COMPOSITES: &[(&str, &[&str], &str)] = &[
+    ("point2d_int", &["x", "y"], "s2"),
+    ("rectangle", &["top", "left", "bottom", "right"], "s2"),
+    ("point2d", &["x", "y"], "f4"),
+    ("point3d", &["x", "y", "z"], "f4"),
+    ("vector2d", &["i", "j"], "f4"),
+    ("vector3d", &["i", "j", "k"], "f4"),
+    ("quaternion", &["i", "j", "k", "w"], "f4"),
+    ("euler2d", &["yaw", "pitch"], "f4"),
+    ("euler3d", &["yaw", "pitch", "roll"], "f4"),
+    ("plane2d", &["i", "j", "d"], "f4"),
+    ("plane3d", &["i", "j", "k", "d"], "f4"),
+    ("color_rgb", &["red", "green", "blue"], "f4"),
+    ("color_argb", &["alpha", "red", "green", "blue"], "f4"),
+    ("color_hsv", &["hue", "saturation", "value"], "f4"),
+    ("color_ahsv", &["alpha", "hue", "saturation", "value"], "f4")
+];
+
+/// Make a name usable as a Kaitai Struct identifier, which has to start with a letter
+fn id(name: &str) -> String {
+    let name = escape_keyword(name.to_owned(), KEYWORDS);
+    if name.starts_with(|c: char| c.is_ascii_lowercase()) { name } else { format!("f{}", name) }
+}
+
+/// Quote text for YAML
+fn quote(text: &str) -> String {
+    serde_json::Value::from(one_line(text)).to_string()
+}
+
+/// Get the Kaitai type of a primitive
+fn primitive_type(type_name: &str) -> &'static str {
+    match type_name {
+        "int8" => "s1",
+        "int16" => "s2",
+        "int32" => "s4",
+        "fourcc" | "color_rgb_int" | "color_argb_int" => "u4",
+        _ => COMPOSITES.iter().find(|(n, _, _)| *n == type_name).map(|(n, _, _)| *n).unwrap_or("f4")
+    }
+}
+
+/// Describe a member for its `doc` key
+fn doc(member: &Member) -> Option<String> {
+    let name = member.field.and_then(|f| f.name.as_ref())?;
+    let mut doc = name.name.clone();
+    if let Some(unit) = &name.unit {
+        doc += &format!(" ({})", unit);
+    }
+    if let Some(description) = &name.description {
+        doc += &format!(" - {}", description);
+    }
+    Some(quote(&doc))
+}
+
+fn write_enum(output: &mut String, e: &Enum) {
+    *output += &format!("  {}:\n", id(&e.name));
+    for o in &e.options {
+        *output += &format!("    {}:\n      id: {}\n", o.value, id(&o.name));
+        if !o.option.name.is_empty() {
+            *output += &format!("      doc: {}\n", quote(&o.option.name));
+        }
+    }
+}
+
+fn write_flags(output: &mut String, e: &Enum) {
+    *output += &format!("  {}:\n", id(&e.name));
+    if let Some(name) = &e.field.name {
+        *output += &format!("    doc: {}\n", quote(&name.name));
+    }
+    let raw_type = match e.size {
+        "int8" => "u1",
+        "int16" => "u2",
+        _ => "u4"
+    };
+    *output += &format!("    seq:\n      - id: raw\n        type: {}\n", raw_type);
+    if !e.options.is_empty() {
+        *output += "    instances:\n";
+        for o in &e.options {
+            *output += &format!("      {}:\n        value: (raw & 0x{:X}) != 0\n", id(&o.name), o.value);
+            if !o.option.name.is_empty() {
+                *output += &format!("        doc: {}\n", quote(&o.option.name));
+            }
+        }
+    }
+}
+
+fn write_struct(output: &mut String, codegen: &Codegen, s: &Struct) {
+    *output += &format!("  {}:\n", id(&s.name));
+    if let Some(block) = s.block {
+        *output += &format!("    doc: {}\n", quote(&format!("{} (maximum {})", block.name.as_deref().unwrap_or(&s.name), block.maximum)));
+    }
+    if s.members.is_empty() {
+        return
+    }
+
+    *output += "    seq:\n";
+    for m in &s.members {
+        *output += &format!("      - id: {}\n", id(&m.name));
+        match &m.kind {
+            MemberKind::Primitive("string") => *output += "        type: strz\n        size: 32\n        encoding: UTF-8\n",
+            MemberKind::Primitive(type_name) => *output += &format!("        type: {}\n", primitive_type(type_name)),
+            MemberKind::Range(type_name) => *output += &format!("        type: {}\n        repeat: expr\n        repeat-expr: 2\n", primitive_type(type_name)),
+            MemberKind::Padding => *output += &format!("        size: 0x{:X}\n", m.size),
+            MemberKind::Index(size) => *output += &format!("        type: {}\n", primitive_type(size)),
+            // Kaitai enums need at least one option
+            MemberKind::Enum(e) if codegen.find_enum(e).is_some_and(|e| e.options.is_empty()) => *output += "        type: s2\n",
+            MemberKind::Enum(e) => *output += &format!("        type: s2\n        enum: {}\n", id(e)),
+            MemberKind::Flags(e, _) => *output += &format!("        type: {}\n", id(e)),
+            MemberKind::Reference => *output += "        type: tag_reference\n",
+            MemberKind::Block(_) => *output += "        type: tag_block\n",
+            MemberKind::TagData => *output += "        type: tag_data\n",
+            MemberKind::Array(n, count) => *output += &format!("        type: {}\n        repeat: expr\n        repeat-expr: {}\n", id(n), count)
+        }
+        if let Some(doc) = doc(m) {
+            *output += &format!("        doc: {}\n", doc);
+        }
+    }
+}
+
+fn write_children(output: &mut String, codegen: &Codegen, s: &Struct) {
+    *output += &format!("  {}_children:\n", id(&s.name));
+    *output += &format!("    params:\n      - id: fixed\n        type: {}\n    seq:\n", id(&s.name));
+    for m in &s.members {
+        let name = id(&m.name);
+        match &m.kind {
+            MemberKind::Reference => *output += &format!(
+                "      - id: {}\n        type: strz\n        size: fixed.{}.path_size + 1\n        encoding: UTF-8\n        if: fixed.{}.path_size > 0\n",
+                name, name, name
+            ),
+            MemberKind::Block(n) => *output += &format!("      - id: {}\n        type: {}_block(fixed.{}.count)\n", name, id(n), name),
+            MemberKind::TagData => *output += &format!("      - id: {}\n        size: fixed.{}.size\n", name, name),
+            MemberKind::Array(n, count) if codegen.find_struct(n).map(|n| codegen.has_children(n)).unwrap_or(false) => *output += &format!(
+                "      - id: {}\n        type: {}_children(fixed.{}[_index])\n        repeat: expr\n        repeat-expr: {}\n", name, id(n), name, count
+            ),
+            _ => ()
+        }
+    }
+}
+
+fn write_block(output: &mut String, codegen: &Codegen, s: &Struct) {
+    let name = id(&s.name);
+    *output += &format!("  {}_block:\n", name);
+    *output += "    params:\n      - id: count\n        type: u4\n    seq:\n";
+    *output += &format!("      - id: elements\n        type: {}\n        repeat: expr\n        repeat-expr: count\n", name);
+    if codegen.has_children(s) {
+        *output += &format!("      - id: children\n        type: {}_children(elements[_index])\n        repeat: expr\n        repeat-expr: count\n", name);
+    }
+}
+
+/// Generate a Kaitai Struct specification for a group's tag files
+pub fn spec(codegen: &Codegen, group: &GroupStruct) -> String {
+    let structs = codegen.structs_used_by(&group.struct_name);
+    let group_id = id(&super::sanitize(group.name));
+
+    let mut output = format!("# Generated by gorilla from the definitions of guerilla {}\n", build_name(codegen.definitions));
+    output += &format!("meta:\n  id: {}\n  title: {}\n  file-extension: {}\n  endian: be\n", group_id, quote(&format!("{} tag", group.name)), group_id);
+    output += &format!(
+        "seq:\n  - id: header\n    type: tag_header(0x{:08X})\n  - id: tag\n    type: {}_block(1)\n",
+        group.group.fourcc, id(&group.struct_name)
+    );
+
+    output += "types:\n";
+    output += PRELUDE;
+    for (name, components, component_type) in COMPOSITES {
+        if structs.iter().any(|s| s.members.iter().any(|m| matches!(m.kind, MemberKind::Primitive(t) | MemberKind::Range(t) if t == *name))) {
+            output += &format!("  {}:\n    seq:\n", name);
+            for c in components.iter() {
+                output += &format!("      - id: {}\n        type: {}\n", c, component_type);
+            }
+        }
+    }
+
+    let used = |e: &Enum| structs.iter().any(|s| s.members.iter().any(|m| matches!(&m.kind, MemberKind::Enum(n) | MemberKind::Flags(n, _) if *n == e.name)));
+    for e in codegen.enums.iter().filter(|e| e.flags && used(e)) {
+        write_flags(&mut output, e);
+    }
+    for s in &structs {
+        write_struct(&mut output, codegen, s);
+        if codegen.has_children(s) {
+            write_children(&mut output, codegen, s);
+        }
+        if s.block.is_some() {
+            write_block(&mut output, codegen, s);
+        }
+    }
+
+    let enums: Vec<&Enum> = codegen.enums.iter().filter(|e| !e.flags && !e.options.is_empty() && used(e)).collect();
+    if !enums.is_empty() {
+        output += "enums:\n";
+        for e in enums {
+            write_enum(&mut output, e);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use tag_file::tests::{definitions, tag_with, temporary_directory};
+
+    /// Reads a tag with a spec, supporting only what the generated specs use
+    const READER: &str = r#"
+import struct, sys, yaml
+
+spec = yaml.safe_load(open(sys.argv[1]))
+spec['types']['root'] = {'seq': spec['seq']}
+data = open(sys.argv[2], 'rb').read()
+PRIMITIVES = {'u1': 'B', 'u2': 'H', 'u4': 'I', 's1': 'b', 's2': 'h', 's4': 'i', 'f4': 'f'}
+
+class Struct:
+    pass
+
+def read(type_name, args, offset):
+    t = spec['types'][type_name]
+    s = Struct()
+    for param, arg in zip(t.get('params', []), args):
+        setattr(s, param['id'], arg)
+    for f in t.get('seq', []):
+        if 'if' in f and not eval(f['if'], {}, vars(s)):
+            continue
+        values = []
+        for i in range(eval(str(f['repeat-expr']), {}, vars(s)) if 'repeat' in f else 1):
+            scope = dict(vars(s), _index=i)
+            field_type = f.get('type')
+            if 'contents' in f:
+                contents = bytes(f['contents']) if isinstance(f['contents'], list) else f['contents'].encode()
+                assert data[offset:offset + len(contents)] == contents, f['id']
+                value, offset = contents, offset + len(contents)
+            elif field_type is None or field_type == 'strz':
+                size = eval(str(f['size']), {}, scope)
+                value, offset = data[offset:offset + size], offset + size
+                if field_type == 'strz':
+                    value = value.split(b'\0')[0].decode()
+            elif field_type in PRIMITIVES:
+                value = struct.unpack_from('>' + PRIMITIVES[field_type], data, offset)[0]
+                offset += struct.calcsize(PRIMITIVES[field_type])
+                if 'enum' in f:
+                    value = spec['enums'][f['enum']][value]['id']
+            else:
+                name, _, args = field_type.rstrip(')').partition('(')
+                value, offset = read(name, [eval(a, {}, scope) for a in args.split(',') if a], offset)
+            values.append(value)
+        value = values if 'repeat' in f else values[0]
+        if 'valid' in f:
+            assert value == eval(str(f['valid']), {}, vars(s)), f['id']
+        setattr(s, f['id'], value)
+    for name, instance in t.get('instances', {}).items():
+        setattr(s, name, eval(instance['value'], {}, vars(s)))
+    return s, offset
+
+root, end = read('root', [], 0)
+assert end == len(data)
+assert root.header.version == 2
+weapon, children = root.tag.elements[0], root.tag.children[0]
+assert weapon.name == 'gun'
+assert weapon.flags.b_c and not weapon.flags.a
+assert weapon.type == 'pistol'
+assert [v.y for v in weapon.vals] == [2, 4]
+assert weapon.trigger_index == 1
+assert children.model == 'weapons\\gun\\gun'
+assert children.data == bytes.fromhex('DEADBEEF')
+triggers = children.triggers
+assert [t.rounds_per_second for t in triggers.elements] == [8, 1]
+assert triggers.children[0].projectile == 'weapons\\gun\\bullet'
+assert not hasattr(triggers.children[1], 'projectile')
+"#;
+
+    #[test]
+    fn read_tag_with_spec() {
+        let definitions = definitions();
+        let codegen = Codegen::new(&definitions);
+        let group = codegen.groups.iter().find(|g| g.name == "weapon").unwrap();
+        let tag = tag_with(&definitions, "weapon", serde_json::json!({
+            "name": "gun",
+            "flags": {"b_c": true},
+            "type": "pistol",
+            "model": {"group": "model", "path": "weapons\\gun\\gun"},
+            "triggers": [
+                {"rounds_per_second": 8, "projectile": {"group": "weapon", "path": "weapons\\gun\\bullet"}},
+                {"rounds_per_second": 1}
+            ],
+            "data": "DEADBEEF",
+            "vals": [{"y": 2}, {"y": 4}],
+            "trigger_index": 1
+        }));
+
+        let directory = temporary_directory("kaitai_read_tag_with_spec");
+        std::fs::write(directory.join("weapon.ksy"), spec(&codegen, group)).unwrap();
+        std::fs::write(directory.join("gun.weapon"), tag.to_bytes(&definitions).unwrap()).unwrap();
+        std::fs::write(directory.join("read.py"), READER).unwrap();
+
+        let run = Command::new("python3").args(["read.py", "weapon.ksy", "gun.weapon"]).current_dir(&directory).output().unwrap();
+        assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    }
+}
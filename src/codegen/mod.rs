@@ -0,0 +1,317 @@
+//! Generating code for reading and writing tags from dumped definitions
+//!
+//! The definitions are first turned into a list of structs and enums with unique snake_case names, which each language
+//! then writes out in its own way. Every block gets a struct for its elements, and so does every array. Blocks with the
+//! same name and fingerprint (see [`fingerprint`](crate::def_dumper::fingerprint)) share a struct.
+
+use def_dumper::{Block, BlockFieldType, Definitions, Field, FieldName, Group, option_identifiers, primitive_size, to_identifier};
+use def_dumper::block_names::synthesize_name;
+use tag_file::StructLayout;
+
+pub mod c;
+pub mod editor010;
+pub mod imhex;
+pub mod json_schema;
+pub mod kaitai;
+pub mod python;
+pub mod reclaimer;
+pub mod rust;
+pub mod typescript;
+
+/// What a struct member is
+pub enum MemberKind {
+    Primitive(&'static str),
+
+    /// Two of a primitive (from and to)
+    Range(&'static str),
+
+    /// Bytes that aren't used
+    Padding,
+
+    /// Index of an element in another block, stored as the given primitive
+    Index(&'static str),
+
+    /// Name of the enum
+    Enum(String),
+
+    /// Name of the bitfield and the primitive it's stored as
+    Flags(String, &'static str),
+
+    Reference,
+
+    /// Name of the struct for each element of the block
+    Block(String),
+
+    TagData,
+
+    /// Name of the struct for each element of the array, and how many elements there are
+    Array(String, usize)
+}
+
+/// A member of a struct
+pub struct Member<'a> {
+    pub name: String,
+
+    /// The key of the member in tag JSON (see [`json`](crate::tag_file::json))
+    pub identifier: String,
+
+    /// The field this is for, or none if this is padding at the end of a block that no field accounts for
+    pub field: Option<&'a Field>,
+
+    pub offset: usize,
+    pub size: usize,
+    pub kind: MemberKind
+}
+
+/// A struct for the elements of a block or array
+pub struct Struct<'a> {
+    pub name: String,
+
+    /// The block this is for, or none if this is an array
+    pub block: Option<&'a Block>,
+
+    pub size: usize,
+    pub members: Vec<Member<'a>>
+}
+
+/// An option of an enum, or a bit of a bitfield
+pub struct EnumOption<'a> {
+    pub name: String,
+
+    /// The name of the option in tag JSON
+    pub identifier: String,
+
+    /// The index of the option, or the mask of the bit
+    pub value: u32,
+
+    pub option: &'a FieldName
+}
+
+/// An enum or bitfield
+pub struct Enum<'a> {
+    pub name: String,
+
+    /// The field this is for
+    pub field: &'a Field,
+
+    pub flags: bool,
+
+    /// The primitive it's stored as
+    pub size: &'static str,
+
+    pub options: Vec<EnumOption<'a>>
+}
+
+/// A group and the struct of its block
+pub struct GroupStruct<'a> {
+    pub name: &'a str,
+    pub group: &'a Group,
+    pub struct_name: String
+}
+
+/// Everything to write code for, in an order where nothing is used before it's written
+pub struct Codegen<'a> {
+    pub definitions: &'a Definitions,
+    pub structs: Vec<Struct<'a>>,
+    pub enums: Vec<Enum<'a>>,
+    pub groups: Vec<GroupStruct<'a>>
+}
+
+/// Turn a guerilla name into a snake_case identifier that doesn't start with a digit
+pub fn sanitize(name: &str) -> String {
+    let identifier = to_identifier(name);
+    match identifier.chars().next() {
+        Some(c) if c.is_ascii_digit() => format!("_{}", identifier),
+        Some(_) => identifier,
+        None => "unnamed".to_owned()
+    }
+}
+
+/// Convert a snake_case identifier to PascalCase
+pub fn pascal_case(identifier: &str) -> String {
+    let mut result = String::with_capacity(identifier.len());
+    let mut upper = true;
+    for c in identifier.chars() {
+        if c == '_' {
+            upper = true;
+        }
+        else if upper {
+            result.push(c.to_ascii_uppercase());
+            upper = false;
+        }
+        else {
+            result.push(c);
+        }
+    }
+
+    // Keep a leading underscore so it doesn't start with a digit
+    if identifier.starts_with('_') { format!("_{}", result) } else { result }
+}
+
+/// Append an underscore to an identifier if it's a keyword
+pub fn escape_keyword(identifier: String, keywords: &[&str]) -> String {
+    if keywords.contains(&identifier.as_str()) { identifier + "_" } else { identifier }
+}
+
+/// Put a name on one line so it can go in a comment
+pub fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Get a name for the build the definitions were dumped from: its file version or the start of its SHA-256 hash
+pub fn build_name(definitions: &Definitions) -> String {
+    match &definitions.exe_pe_file_version {
+        Some(v) => v.to_owned(),
+        None => definitions.exe_sha256sum.chars().take(12).collect()
+    }
+}
+
+struct Builder<'a> {
+    codegen: Codegen<'a>,
+    used: Vec<String>,
+
+    /// Name, fingerprint and struct name of each block so far
+    blocks: Vec<(String, String, String)>
+}
+
+impl<'a> Builder<'a> {
+    fn unique(&mut self, base: String) -> String {
+        let mut name = base.clone();
+        let mut number = 2;
+        while self.used.contains(&name) {
+            name = format!("{}_{}", base, number);
+            number += 1;
+        }
+        self.used.push(name.clone());
+        name
+    }
+
+    fn add_block(&mut self, block: &'a Block, fallback: String) -> String {
+        let base = sanitize(block.name.as_deref().unwrap_or(&fallback));
+        if let Some((_, _, name)) = self.blocks.iter().find(|(b, f, _)| *b == base && !f.is_empty() && *f == block.fingerprint) {
+            return name.clone()
+        }
+
+        let name = self.unique(base.clone());
+        self.blocks.push((base, block.fingerprint.clone(), name.clone()));
+
+        let layout = StructLayout::of(block);
+        let mut members = self.add_members(&name, &layout);
+        if layout.size < block.length {
+            members.push(Member {
+                name: "trailing_padding".to_owned(),
+                identifier: "trailing_padding".to_owned(),
+                field: None,
+                offset: layout.size,
+                size: block.length - layout.size,
+                kind: MemberKind::Padding
+            });
+        }
+
+        self.codegen.structs.push(Struct { name: name.clone(), block: Some(block), size: block.length, members });
+        name
+    }
+
+    fn add_enum(&mut self, name: String, field: &'a Field, options: &'a [FieldName], flags: Option<&'static str>) -> String {
+        let name = self.unique(name);
+        let bits = flags.map(|s| primitive_size(s) * 8).unwrap_or(0);
+        let options = option_identifiers(options, if flags.is_some() { "bit" } else { "option" }).into_iter()
+            .zip(options.iter())
+            .enumerate()
+            .filter(|(i, _)| flags.is_none() || *i < bits)
+            .map(|(i, (identifier, option))| EnumOption {
+                name: sanitize(&identifier),
+                identifier,
+                value: if flags.is_some() { 1 << i } else { i as u32 },
+                option
+            })
+            .collect();
+        self.codegen.enums.push(Enum { name: name.clone(), field, flags: flags.is_some(), size: flags.unwrap_or("int16"), options });
+        name
+    }
+
+    fn add_members(&mut self, struct_name: &str, layout: &StructLayout<'a>) -> Vec<Member<'a>> {
+        let mut members = Vec::with_capacity(layout.entries.len());
+        for e in &layout.entries {
+            let member_name = sanitize(&e.identifier);
+            let nested_name = format!("{}_{}", struct_name, member_name);
+            let kind = match &e.field.block_type {
+                BlockFieldType::Primitive(type_name) => MemberKind::Primitive(type_name),
+                BlockFieldType::Range(type_name) => MemberKind::Range(type_name),
+                BlockFieldType::Index(_, size) => MemberKind::Index(size),
+                BlockFieldType::Enum(options) => MemberKind::Enum(self.add_enum(nested_name, e.field, options, None)),
+                BlockFieldType::Flags(size, bits) => MemberKind::Flags(self.add_enum(nested_name, e.field, bits, Some(size)), size),
+                BlockFieldType::Reference(_) => MemberKind::Reference,
+                BlockFieldType::TagData(_, _) => MemberKind::TagData,
+                BlockFieldType::Block(block) => {
+                    let fallback = synthesize_name(struct_name, &member_name);
+                    MemberKind::Block(self.add_block(block, fallback))
+                },
+                BlockFieldType::ArrayStart(count) => {
+                    let array = e.array.as_ref().unwrap();
+                    let name = self.unique(nested_name);
+                    let element_members = self.add_members(&name, &array.element);
+                    self.codegen.structs.push(Struct { name: name.clone(), block: None, size: array.element.size, members: element_members });
+                    MemberKind::Array(name, *count)
+                },
+                _ => MemberKind::Padding
+            };
+            members.push(Member { name: member_name, identifier: e.identifier.clone(), field: Some(e.field), offset: e.offset, size: e.size, kind });
+        }
+        members
+    }
+}
+
+impl<'a> Codegen<'a> {
+    /// Work out the structs and enums needed for the definitions
+    pub fn new(definitions: &'a Definitions) -> Codegen<'a> {
+        let mut builder = Builder {
+            codegen: Codegen { definitions, structs: Vec::new(), enums: Vec::new(), groups: Vec::new() },
+            used: Vec::new(),
+            blocks: Vec::new()
+        };
+        for (name, group) in &definitions.groups {
+            let struct_name = builder.add_block(&group.block, synthesize_name(name, ""));
+            builder.codegen.groups.push(GroupStruct { name, group, struct_name });
+        }
+        builder.codegen
+    }
+
+    /// Find a struct by its name
+    pub fn find_struct(&self, name: &str) -> Option<&Struct<'a>> {
+        self.structs.iter().find(|s| s.name == name)
+    }
+
+    /// Get a struct and every struct it uses, in the same order as [`structs`](Codegen::structs)
+    pub fn structs_used_by(&self, name: &str) -> Vec<&Struct<'a>> {
+        let mut used = vec![name.to_owned()];
+        let mut i = 0;
+        while i < used.len() {
+            if let Some(s) = self.find_struct(&used[i]) {
+                for m in &s.members {
+                    if let MemberKind::Block(n) | MemberKind::Array(n, _) = &m.kind {
+                        if !used.contains(n) {
+                            used.push(n.clone());
+                        }
+                    }
+                }
+            }
+            i += 1;
+        }
+        self.structs.iter().filter(|s| used.contains(&s.name)).collect()
+    }
+
+    /// Whether anything follows the elements of a struct in tag files (blocks, tag reference paths or tag data)
+    pub fn has_children(&self, s: &Struct) -> bool {
+        s.members.iter().any(|m| match &m.kind {
+            MemberKind::Reference | MemberKind::Block(_) | MemberKind::TagData => true,
+            MemberKind::Array(n, _) => self.find_struct(n).map(|n| self.has_children(n)).unwrap_or(false),
+            _ => false
+        })
+    }
+
+    /// Find an enum or bitfield by its name
+    pub fn find_enum(&self, name: &str) -> Option<&Enum<'a>> {
+        self.enums.iter().find(|e| e.name == name)
+    }
+}
@@ -0,0 +1,449 @@
+//! Generating a Python module with a dataclass for every block, using `struct` to read and write tag files
+
+use def_dumper::BlockFieldType;
+use super::{Codegen, Enum, Member, MemberKind, Struct, build_name, escape_keyword, one_line, pascal_case};
+
+/// Words that can't be used as field names in Python
+pub const KEYWORDS: &[&str] = &[
+    "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else", "except",
+    "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise",
+    "return", "try", "while", "with", "yield"
+];
+
+/// Everything the generated classes need to read and write tags
+const PRELUDE: &str = r##"from __future__ import annotations
+
+import dataclasses
+import enum
+import struct
+import typing
+
+TAG_HEADER_SIZE = 0x40
+"""Size of the header at the start of every tag file"""
+
+
+class TagReader:
+    """Reads the data of a tag file in order"""
+
+    def __init__(self, data: bytes, offset: int = 0):
+        self.data = data
+        self.offset = offset
+
+    def take(self, size: int) -> bytes:
+        """Take the next `size` bytes"""
+        if self.offset + size > len(self.data):
+            raise ValueError("tag data is truncated or doesn't match the definitions")
+        taken = self.data[self.offset:self.offset + size]
+        self.offset += size
+        return taken
+
+
+def _u32(data: bytes, offset: int) -> int:
+    return struct.unpack_from(">I", data, offset)[0]
+
+
+def _read_value(data: bytes, offset: int, fmt: str, components: int, bounds: bool) -> typing.Any:
+    values = struct.unpack_from(">%d%s" % (components * (2 if bounds else 1), fmt), data, offset)
+    if components > 1:
+        values = tuple(values[i:i + components] for i in range(0, len(values), components))
+    return tuple(values) if bounds else values[0]
+
+
+def _write_value(data: bytearray, offset: int, fmt: str, components: int, bounds: bool, value: typing.Any) -> None:
+    values = list(value) if bounds else [value]
+    if components > 1:
+        values = [c for v in values for c in v]
+    struct.pack_into(">%d%s" % (len(values), fmt), data, offset, *values)
+
+
+def _read_string(data: bytes, offset: int) -> str:
+    return data[offset:offset + 32].split(b"\0", 1)[0].decode("utf-8", "replace")
+
+
+def _write_string(data: bytearray, offset: int, value: str) -> None:
+    encoded = value.encode("utf-8")[:31]
+    data[offset:offset + 32] = encoded + bytes(32 - len(encoded))
+
+
+def _read_enum(cls: typing.Any, data: bytes, offset: int) -> typing.Any:
+    value = struct.unpack_from(">h", data, offset)[0]
+    try:
+        return cls(value)
+    except (ValueError, TypeError):
+        return value
+
+
+@dataclasses.dataclass
+class TagReference:
+    """A reference to another tag"""
+
+    group: int = 0xFFFFFFFF
+    """FourCC of the referenced group (or 0xFFFFFFFF if none)"""
+
+    path: str = ""
+
+    SIZE: typing.ClassVar[int] = 0x10
+
+    @classmethod
+    def read_fixed(cls, data: bytes, offset: int) -> TagReference:
+        return cls(group=_u32(data, offset))
+
+    def read_path(self, reader: TagReader, data: bytes, offset: int) -> None:
+        length = _u32(data, offset + 8)
+        if length > 0:
+            self.path = reader.take(length + 1)[:length].decode("utf-8", "replace")
+
+    def write_fixed(self, data: bytearray, offset: int) -> None:
+        struct.pack_into(">IIII", data, offset, self.group, 0, len(self.path.encode("utf-8")), 0xFFFFFFFF)
+
+    def write_path(self, output: bytearray) -> None:
+        if self.path:
+            output += self.path.encode("utf-8") + b"\0"
+
+
+def read_elements(cls: typing.Any, reader: TagReader, count: int) -> list:
+    """Read `count` elements of a block"""
+    data = reader.take(cls.SIZE * count)
+    elements = [cls.read_fixed(data, i * cls.SIZE) for i in range(count)]
+    for i, element in enumerate(elements):
+        element.read_children(reader, data, i * cls.SIZE)
+    return elements
+
+
+def write_elements(elements: list, output: bytearray) -> None:
+    """Write the elements of a block"""
+    if not elements:
+        return
+    size = type(elements[0]).SIZE
+    start = len(output)
+    output += bytes(size * len(elements))
+    for i, element in enumerate(elements):
+        element.write_fixed(output, start + i * size)
+    for element in elements:
+        element.write_children(output)
+
+
+def tag_crc32(data: bytes) -> int:
+    """Calculate the CRC32 used for tag file checksums (a standard CRC32 without the final XOR)"""
+    crc = 0xFFFFFFFF
+    for b in data:
+        crc ^= b
+        for _ in range(8):
+            crc = (crc >> 1) ^ 0xEDB88320 if crc & 1 else crc >> 1
+    return crc
+
+
+def read_tag(data: bytes) -> typing.Any:
+    """Read a tag file, returning the main struct of its group"""
+    if len(data) < TAG_HEADER_SIZE or _u32(data, 0x3C) != 0x626C616D:
+        raise ValueError("not a tag file")
+    fourcc = _u32(data, 0x24)
+    if fourcc not in GROUPS_BY_FOURCC:
+        raise ValueError("unknown tag group 0x%08X" % fourcc)
+    return read_elements(GROUPS_BY_FOURCC[fourcc], TagReader(data, TAG_HEADER_SIZE), 1)[0]
+
+
+def write_tag(tag: typing.Any, group: typing.Optional[str] = None) -> bytes:
+    """Write a tag file from the main struct of a group, calculating its checksum
+
+    The group only needs to be given if more than one group uses the struct."""
+    if group is None:
+        groups = [name for name, cls in GROUPS.items() if cls is type(tag)]
+        if len(groups) != 1:
+            raise ValueError("%s is the struct of %d groups, so the group must be given" % (type(tag).__name__, len(groups)))
+        group = groups[0]
+    fourcc, version = GROUP_HEADERS[group]
+    output = bytearray(TAG_HEADER_SIZE)
+    write_elements([tag], output)
+    struct.pack_into(">III", output, 0x24, fourcc, tag_crc32(output[TAG_HEADER_SIZE:]), TAG_HEADER_SIZE)
+    struct.pack_into(">HHI", output, 0x38, version, 0x00FF, 0x626C616D)
+    return bytes(output)
+"##;
+
+/// Get the `struct` format and number of components of a primitive
+fn primitive_format(type_name: &str) -> (&'static str, usize) {
+    match type_name {
+        "int8" => ("b", 1),
+        "int16" => ("h", 1),
+        "int32" => ("i", 1),
+        "fourcc" | "color_rgb_int" | "color_argb_int" => ("I", 1),
+        "point2d_int" => ("h", 2),
+        "rectangle" => ("h", 4),
+        "point2d" | "vector2d" | "euler2d" => ("f", 2),
+        "point3d" | "vector3d" | "euler3d" | "plane2d" | "color_rgb" | "color_hsv" => ("f", 3),
+        "quaternion" | "plane3d" | "color_argb" | "color_ahsv" => ("f", 4),
+        _ => ("f", 1)
+    }
+}
+
+/// Get the Python type of a primitive
+fn primitive_type(type_name: &str) -> String {
+    match (type_name, primitive_format(type_name)) {
+        ("string", _) => "str".to_owned(),
+        (_, ("f", 1)) => "float".to_owned(),
+        (_, (_, 1)) => "int".to_owned(),
+        (_, (f, n)) => format!("typing.Tuple[{}]", vec![if f == "f" { "float" } else { "int" }; n].join(", "))
+    }
+}
+
+/// Get the default value of a primitive
+fn primitive_default(type_name: &str) -> String {
+    match (type_name, primitive_format(type_name)) {
+        ("string", _) => "\"\"".to_owned(),
+        (_, ("f", 1)) => "0.0".to_owned(),
+        (_, (_, 1)) => "0".to_owned(),
+        (_, (f, n)) => format!("({})", vec![if f == "f" { "0.0" } else { "0" }; n].join(", "))
+    }
+}
+
+/// Escape text to go in a docstring
+fn docstring(text: &str) -> String {
+    one_line(text).replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn write_enum(output: &mut String, e: &Enum) {
+    let type_name = pascal_case(&e.name);
+    *output += &format!("\nclass {}(enum.{}):\n", type_name, if e.flags { "IntFlag" } else { "IntEnum" });
+    if let Some(name) = &e.field.name {
+        *output += &format!("    \"\"\"{}\"\"\"\n", docstring(&name.name));
+    }
+    for o in &e.options {
+        let value = if e.flags { format!("0x{:X}", o.value) } else { o.value.to_string() };
+        *output += &format!("\n    {} = {}\n", o.name.to_ascii_uppercase(), value);
+        if !o.option.name.is_empty() {
+            *output += &format!("    \"\"\"{}\"\"\"\n", docstring(&o.option.name));
+        }
+    }
+    if e.options.is_empty() && e.field.name.is_none() {
+        *output += "    pass\n";
+    }
+    *output += "\n";
+}
+
+/// Get the Python type of a member
+fn member_type(member: &Member) -> String {
+    match &member.kind {
+        MemberKind::Primitive(type_name) => primitive_type(type_name),
+        MemberKind::Range(type_name) => format!("typing.Tuple[{}, {}]", primitive_type(type_name), primitive_type(type_name)),
+        MemberKind::Index(_) => "int".to_owned(),
+        MemberKind::Enum(e) => format!("typing.Union[{}, int]", pascal_case(e)),
+        MemberKind::Flags(e, _) => pascal_case(e),
+        MemberKind::Reference => "TagReference".to_owned(),
+        MemberKind::Block(s) | MemberKind::Array(s, _) => format!("typing.List[{}]", pascal_case(s)),
+        MemberKind::TagData => "bytes".to_owned(),
+        MemberKind::Padding => String::new()
+    }
+}
+
+/// Get the default value of a member
+fn member_default(codegen: &Codegen, member: &Member) -> String {
+    match &member.kind {
+        MemberKind::Primitive(type_name) => primitive_default(type_name),
+        MemberKind::Range(type_name) => format!("({}, {})", primitive_default(type_name), primitive_default(type_name)),
+        MemberKind::Index(_) => "-1".to_owned(),
+        MemberKind::Enum(e) => match codegen.find_enum(e).and_then(|n| n.options.first()) {
+            Some(o) => format!("{}.{}", pascal_case(e), o.name.to_ascii_uppercase()),
+            None => "0".to_owned()
+        },
+        MemberKind::Flags(e, _) => format!("{}(0)", pascal_case(e)),
+        MemberKind::Reference => match member.field.map(|f| &f.block_type) {
+            Some(BlockFieldType::Reference(groups)) if groups.len() == 1 => match codegen.definitions.groups.get(&groups[0]) {
+                Some(g) => format!("dataclasses.field(default_factory=lambda: TagReference(0x{:08X}))", g.fourcc),
+                None => "dataclasses.field(default_factory=TagReference)".to_owned()
+            },
+            _ => "dataclasses.field(default_factory=TagReference)".to_owned()
+        },
+        MemberKind::Block(_) => "dataclasses.field(default_factory=list)".to_owned(),
+        MemberKind::Array(s, count) => format!("dataclasses.field(default_factory=lambda: [{}() for _ in range({})])", pascal_case(s), count),
+        MemberKind::TagData => "b\"\"".to_owned(),
+        MemberKind::Padding => String::new()
+    }
+}
+
+/// Describe a member in a docstring
+fn doc(member: &Member) -> Option<String> {
+    let name = member.field.and_then(|f| f.name.as_ref())?;
+    let mut doc = docstring(&name.name);
+    if let Some(unit) = &name.unit {
+        doc += &format!(" ({})", docstring(unit));
+    }
+    if let Some(description) = &name.description {
+        doc += &format!(" - {}", docstring(description));
+    }
+    Some(doc)
+}
+
+fn write_struct(output: &mut String, codegen: &Codegen, s: &Struct) {
+    let type_name = pascal_case(&s.name);
+    let members: Vec<(String, &Member)> = s.members.iter()
+        .filter(|m| !matches!(m.kind, MemberKind::Padding))
+        .map(|m| (escape_keyword(m.name.clone(), KEYWORDS), m))
+        .collect();
+
+    *output += &format!("\n@dataclasses.dataclass\nclass {}:\n", type_name);
+    match s.block {
+        Some(block) => *output += &format!("    \"\"\"{} (maximum {})\"\"\"\n", docstring(block.name.as_deref().unwrap_or(&s.name)), block.maximum),
+        None => *output += "    \"\"\"Array element\"\"\"\n"
+    }
+
+    for (name, m) in &members {
+        *output += &format!("\n    {}: {} = {}\n", name, member_type(m), member_default(codegen, m));
+        if let Some(doc) = doc(m) {
+            *output += &format!("    \"\"\"{}\"\"\"\n", doc);
+        }
+    }
+    *output += &format!("\n    SIZE: typing.ClassVar[int] = 0x{:X}\n", s.size);
+
+    // Reading
+    *output += &format!("\n    @classmethod\n    def read_fixed(cls, data: bytes, offset: int) -> {}:\n        return cls(\n", type_name);
+    let reads: Vec<String> = members.iter().map(|(name, m)| {
+        let at = format!("offset + 0x{:X}", m.offset);
+        let value = match &m.kind {
+            MemberKind::Primitive("string") => format!("_read_string(data, {})", at),
+            MemberKind::Primitive(type_name) | MemberKind::Range(type_name) => {
+                let (fmt, components) = primitive_format(type_name);
+                format!("_read_value(data, {}, \"{}\", {}, {})", at, fmt, components, if matches!(m.kind, MemberKind::Range(_)) { "True" } else { "False" })
+            },
+            MemberKind::Index(size) => format!("_read_value(data, {}, \"{}\", 1, False)", at, primitive_format(size).0),
+            MemberKind::Enum(e) => format!("_read_enum({}, data, {})", pascal_case(e), at),
+            MemberKind::Flags(e, size) => format!("{}(_read_value(data, {}, \"{}\", 1, False))", pascal_case(e), at, primitive_format(size).0.to_ascii_uppercase()),
+            MemberKind::Reference => format!("TagReference.read_fixed(data, {})", at),
+            MemberKind::Block(_) => "[]".to_owned(),
+            MemberKind::TagData => "b\"\"".to_owned(),
+            MemberKind::Array(s, count) => format!("[{}.read_fixed(data, {} + i * {}.SIZE) for i in range({})]", pascal_case(s), at, pascal_case(s), count),
+            MemberKind::Padding => String::new()
+        };
+        format!("            {}={},\n", name, value)
+    }).collect();
+    *output += &reads.concat();
+    *output += "        )\n";
+
+    *output += "\n    def read_children(self, reader: TagReader, data: bytes, offset: int) -> None:\n";
+    let mut body = String::new();
+    for (name, m) in &members {
+        let at = format!("offset + 0x{:X}", m.offset);
+        match &m.kind {
+            MemberKind::Reference => body += &format!("        self.{}.read_path(reader, data, {})\n", name, at),
+            MemberKind::Block(s) => body += &format!("        self.{} = read_elements({}, reader, _u32(data, {}))\n", name, pascal_case(s), at),
+            MemberKind::TagData => body += &format!("        self.{} = reader.take(_u32(data, {}))\n", name, at),
+            MemberKind::Array(s, _) => body += &format!(
+                "        for i, element in enumerate(self.{}):\n            element.read_children(reader, data, {} + i * {}.SIZE)\n", name, at, pascal_case(s)
+            ),
+            _ => ()
+        }
+    }
+    *output += if body.is_empty() { "        pass\n" } else { &body };
+
+    // Writing
+    *output += "\n    def write_fixed(self, data: bytearray, offset: int) -> None:\n";
+    let mut body = String::new();
+    for (name, m) in &members {
+        let at = format!("offset + 0x{:X}", m.offset);
+        match &m.kind {
+            MemberKind::Primitive("string") => body += &format!("        _write_string(data, {}, self.{})\n", at, name),
+            MemberKind::Primitive(type_name) | MemberKind::Range(type_name) => {
+                let (fmt, components) = primitive_format(type_name);
+                body += &format!("        _write_value(data, {}, \"{}\", {}, {}, self.{})\n", at, fmt, components, if matches!(m.kind, MemberKind::Range(_)) { "True" } else { "False" }, name);
+            },
+            MemberKind::Index(size) => body += &format!("        _write_value(data, {}, \"{}\", 1, False, self.{})\n", at, primitive_format(size).0, name),
+            MemberKind::Enum(_) => body += &format!("        _write_value(data, {}, \"h\", 1, False, int(self.{}))\n", at, name),
+            MemberKind::Flags(_, size) => body += &format!("        _write_value(data, {}, \"{}\", 1, False, int(self.{}))\n", at, primitive_format(size).0.to_ascii_uppercase(), name),
+            MemberKind::Reference => body += &format!("        self.{}.write_fixed(data, {})\n", name, at),
+            MemberKind::Block(_) | MemberKind::TagData => body += &format!("        struct.pack_into(\">I\", data, {}, len(self.{}))\n", at, name),
+            MemberKind::Array(s, count) => body += &format!(
+                "        for i, element in enumerate(self.{}[:{}]):\n            element.write_fixed(data, {} + i * {}.SIZE)\n", name, count, at, pascal_case(s)
+            ),
+            MemberKind::Padding => ()
+        }
+    }
+    *output += if body.is_empty() { "        pass\n" } else { &body };
+
+    *output += "\n    def write_children(self, output: bytearray) -> None:\n";
+    let mut body = String::new();
+    for (name, m) in &members {
+        match &m.kind {
+            MemberKind::Reference => body += &format!("        self.{}.write_path(output)\n", name),
+            MemberKind::Block(_) => body += &format!("        write_elements(self.{}, output)\n", name),
+            MemberKind::TagData => body += &format!("        output += self.{}\n", name),
+            MemberKind::Array(_, count) => body += &format!("        for element in self.{}[:{}]:\n            element.write_children(output)\n", name, count),
+            _ => ()
+        }
+    }
+    *output += if body.is_empty() { "        pass\n" } else { &body };
+    *output += "\n";
+}
+
+/// Generate a Python module with a dataclass for every block and array, along with enums and flags
+pub fn module(codegen: &Codegen) -> String {
+    let mut output = format!("# Generated by gorilla from the definitions of guerilla {}\n\n", build_name(codegen.definitions));
+    output += &format!("\"\"\"Tag definitions of guerilla {}\n\nEverything is read and written as in tag files, which are big endian.\n\"\"\"\n\n", build_name(codegen.definitions));
+    output += PRELUDE;
+    output += "\n";
+
+    for e in &codegen.enums {
+        write_enum(&mut output, e);
+    }
+    for s in &codegen.structs {
+        write_struct(&mut output, codegen, s);
+    }
+
+    output += "\nGROUPS: typing.Dict[str, typing.Any] = {}\n\"\"\"The main struct of each group by name\"\"\"\n\nGROUPS_BY_FOURCC: typing.Dict[int, typing.Any] = {}\n\n\
+               GROUP_HEADERS: typing.Dict[str, typing.Tuple[int, int]] = {}\n\"\"\"The FourCC and version of each group by name\"\"\"\n\n";
+    for g in &codegen.groups {
+        let type_name = pascal_case(&g.struct_name);
+        let name = docstring(g.name);
+        output += &format!(
+            "GROUPS[\"{}\"] = {}\nGROUPS_BY_FOURCC[0x{:08X}] = {}\nGROUP_HEADERS[\"{}\"] = (0x{:08X}, {})\n",
+            name, type_name, g.group.fourcc, type_name, name, g.group.fourcc, g.group.version
+        );
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use tag_file::TagFile;
+    use tag_file::tests::{definitions, temporary_directory};
+
+    /// Reads tags written by gorilla and writes them back, for groups that share a struct
+    const SCRIPT: &str = r#"
+import sys
+import tags
+
+gun_tag = open(sys.argv[1], "rb").read()
+weapon_tag = open(sys.argv[2], "rb").read()
+model_tag = open(sys.argv[3], "rb").read()
+
+gun = tags.read_tag(gun_tag)
+assert gun == tags.WeaponBlock()
+assert tags.write_tag(gun, "gun") == gun_tag
+assert tags.write_tag(tags.read_tag(weapon_tag), "weapon") == weapon_tag
+assert tags.write_tag(tags.read_tag(model_tag)) == model_tag
+try:
+    tags.write_tag(gun)
+    assert False
+except ValueError:
+    pass
+"#;
+
+    #[test]
+    fn groups_sharing_a_struct() {
+        let mut definitions = definitions();
+        let mut gun = definitions.groups["weapon"].clone();
+        gun.fourcc = 0x67756E21;
+        definitions.groups.insert("gun".to_owned(), gun);
+
+        let directory = temporary_directory("python_groups_sharing_a_struct");
+        for group in ["gun", "weapon", "model"] {
+            std::fs::write(directory.join(group), TagFile::new(&definitions, group).unwrap().to_bytes(&definitions).unwrap()).unwrap();
+        }
+        std::fs::write(directory.join("tags.py"), module(&Codegen::new(&definitions))).unwrap();
+        std::fs::write(directory.join("test.py"), SCRIPT).unwrap();
+
+        let run = Command::new("python3").args(["test.py", "gun", "weapon", "model"]).current_dir(&directory).output().unwrap();
+        assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    }
+}
@@ -0,0 +1,297 @@
+//! Generating Reclaimer definition modules, as used by Mozzarilla
+//!
+//! Each group gets a module named after its FourCC (as in `reclaimer.hek.defs`) with a supyr_struct descriptor for
+//! each struct it uses and a `TagDef` for the group. Guerilla's names go in `GUI_NAME`, descriptions in `TOOLTIP` and
+//! units in `SIDETIP`.
+
+extern crate serde_json;
+
+use def_dumper::BlockFieldType;
+use tag_file::json::fourcc_to_string;
+use super::{Codegen, Enum, GroupStruct, Member, MemberKind, Struct, build_name, escape_keyword, one_line};
+use super::python::KEYWORDS;
+
+/// Components of each primitive with more than one, and the supyr_struct field of each component
+const COMPOSITES: &[(&str, &[&str], &str)] = &[
+    ("point2d_int", &["x", "y"], "SInt16"),
+    ("rectangle", &["t", "l", "b", "r"], "SInt16"),
+    ("color_rgb_int", &["unused", "r", "g", "b"], "UInt8"),
+    ("color_argb_int", &["a", "r", "g", "b"], "UInt8"),
+    ("point2d", &["x", "y"], "Float"),
+    ("point3d", &["x", "y", "z"], "Float"),
+    ("vector2d", &["i", "j"], "Float"),
+    ("vector3d", &["i", "j", "k"], "Float"),
+    ("quaternion", &["i", "j", "k", "w"], "Float"),
+    ("euler2d", &["y", "p"], "Float"),
+    ("euler3d", &["y", "p", "r"], "Float"),
+    ("plane2d", &["i", "j", "d"], "Float"),
+    ("plane3d", &["i", "j", "k", "d"], "Float"),
+    ("color_rgb", &["r", "g", "b"], "Float"),
+    ("color_argb", &["a", "r", "g", "b"], "Float"),
+    ("color_hsv", &["h", "s", "v"], "Float"),
+    ("color_ahsv", &["a", "h", "s", "v"], "Float")
+];
+
+/// Quote text as a Python string
+fn quote(text: &str) -> String {
+    serde_json::Value::from(one_line(text)).to_string()
+}
+
+/// Get the supyr_struct field of a primitive with one component
+fn scalar(type_name: &str) -> &'static str {
+    match type_name {
+        "int8" => "SInt8",
+        "int16" => "SInt16",
+        "int32" => "SInt32",
+        "fourcc" => "UInt32",
+        _ => "Float"
+    }
+}
+
+/// Get the name of a group's module, which is its FourCC with anything that can't be in a module name as underscores
+pub fn module_name(group: &GroupStruct) -> String {
+    fourcc_to_string(group.group.fourcc).trim_end().chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect()
+}
+
+/// Get the keyword arguments for a member's name, description, unit and whether it can be edited
+fn keywords(member: &Member) -> Vec<String> {
+    let name = match member.field.and_then(|f| f.name.as_ref()) {
+        Some(n) => n,
+        None => return Vec::new()
+    };
+    let mut keywords = Vec::new();
+    if one_line(&name.name) != member.name.replace('_', " ") {
+        keywords.push(format!("GUI_NAME={}", quote(&name.name)));
+    }
+    if let Some(description) = &name.description {
+        keywords.push(format!("TOOLTIP={}", quote(description)));
+    }
+    if let Some(unit) = &name.unit {
+        keywords.push(format!("SIDETIP={}", quote(unit)));
+    }
+    if name.read_only {
+        keywords.push("EDITABLE=False".to_owned());
+    }
+    if name.hidden {
+        keywords.push("VISIBLE=False".to_owned());
+    }
+    keywords
+}
+
+fn options(e: Option<&Enum>) -> Vec<String> {
+    e.map(|e| e.options.iter().map(|o| quote(&o.name)).collect()).unwrap_or_default()
+}
+
+/// Get the allowed groups of a tag reference as a FourCC or a tuple of them, or none if any group is allowed
+fn valid_groups(codegen: &Codegen, member: &Member) -> Option<String> {
+    let groups = match member.field.map(|f| &f.block_type) {
+        Some(BlockFieldType::Reference(groups)) => groups,
+        _ => return None
+    };
+    if groups.is_empty() || groups.len() >= codegen.definitions.groups.len() {
+        return None
+    }
+    let fourccs: Vec<String> = groups.iter()
+        .filter_map(|g| codegen.definitions.groups.get(g))
+        .map(|g| quote(&fourcc_to_string(g.fourcc)))
+        .collect();
+    match fourccs.as_slice() {
+        [fourcc] => Some(fourcc.clone()),
+        _ => Some(format!("({})", fourccs.join(", ")))
+    }
+}
+
+/// Get the descriptor of a member
+fn member_descriptor(codegen: &Codegen, member: &Member) -> String {
+    let mut arguments = vec![quote(&escape_keyword(member.name.clone(), KEYWORDS))];
+    let function = match &member.kind {
+        MemberKind::Padding => return format!("Pad({})", member.size),
+        MemberKind::Primitive("string") => "ascii_str32".to_owned(),
+        MemberKind::Primitive(type_name) => match COMPOSITES.iter().find(|(n, _, _)| n == type_name) {
+            Some((_, components, component)) => {
+                arguments.extend(components.iter().map(|c| format!("{}(\"{}\")", component, c)));
+                arguments.push("ORIENT=\"h\"".to_owned());
+                "QStruct".to_owned()
+            },
+            None => scalar(type_name).to_owned()
+        },
+        MemberKind::Range(type_name) => {
+            let component = scalar(type_name);
+            arguments.push(format!("{}(\"from\")", component));
+            arguments.push(format!("{}(\"to\")", component));
+            arguments.push("ORIENT=\"h\"".to_owned());
+            "QStruct".to_owned()
+        },
+        MemberKind::Index(size) => scalar(size).to_owned(),
+        MemberKind::Enum(e) => {
+            arguments.extend(options(codegen.find_enum(e)));
+            "SEnum16".to_owned()
+        },
+        MemberKind::Flags(e, size) => {
+            arguments.extend(options(codegen.find_enum(e)));
+            match *size {
+                "int8" => "Bool8",
+                "int16" => "Bool16",
+                _ => "Bool32"
+            }.to_owned()
+        },
+        MemberKind::Reference => {
+            arguments.extend(valid_groups(codegen, member));
+            "dependency".to_owned()
+        },
+        MemberKind::Block(n) => {
+            let maximum = codegen.find_struct(n).and_then(|s| s.block).map(|b| b.maximum).unwrap_or_default();
+            arguments.push(escape_keyword(n.clone(), KEYWORDS));
+            arguments.push(maximum.to_string());
+            "reflexive".to_owned()
+        },
+        MemberKind::TagData => {
+            if let Some(BlockFieldType::TagData(_, max_length)) = member.field.map(|f| &f.block_type) {
+                arguments.push(format!("max_size={}", max_length));
+            }
+            "rawdata_ref".to_owned()
+        },
+        MemberKind::Array(n, count) => {
+            arguments.push(format!("SUB_STRUCT={}", escape_keyword(n.clone(), KEYWORDS)));
+            arguments.push(format!("SIZE={}", count));
+            "Array".to_owned()
+        }
+    };
+    arguments.extend(keywords(member));
+    format!("{}({})", function, arguments.join(", "))
+}
+
+/// Write a struct's descriptor, assigned to `variable` and named `name`
+fn write_struct(output: &mut String, codegen: &Codegen, s: &Struct, variable: &str, name: &str) {
+    match s.block {
+        Some(block) => *output += &format!("# {} (maximum {})\n", one_line(block.name.as_deref().unwrap_or(&s.name)), block.maximum),
+        None => *output += "# array element\n"
+    }
+    *output += &format!("{} = Struct({},\n", variable, quote(name));
+    for m in &s.members {
+        *output += &format!("    {},\n", member_descriptor(codegen, m));
+    }
+    *output += &format!("    SIZE={}\n    )\n\n", s.size);
+}
+
+/// Generate a Reclaimer definition module for a group
+pub fn module(codegen: &Codegen, group: &GroupStruct) -> String {
+    let module = module_name(group);
+    let mut output = format!("# Generated by gorilla from the definitions of guerilla {}\n", build_name(codegen.definitions));
+    output += &format!("# Definition of {} tags\n\n", group.name);
+    output += "from ...common_descs import *\nfrom .objs.tag import HekTag\nfrom supyr_struct.defs.tag_def import TagDef\n\n";
+
+    let structs = codegen.structs_used_by(&group.struct_name);
+    for s in &structs {
+        // The group's struct is written as the body, unless something else in the group uses it too
+        let used_elsewhere = structs.iter().any(|o| o.members.iter().any(|m| matches!(&m.kind, MemberKind::Block(n) | MemberKind::Array(n, _) if *n == s.name)));
+        if s.name != group.struct_name || used_elsewhere {
+            write_struct(&mut output, codegen, s, &escape_keyword(s.name.clone(), KEYWORDS), &s.name);
+        }
+    }
+    if let Some(s) = structs.iter().find(|s| s.name == group.struct_name) {
+        write_struct(&mut output, codegen, s, &format!("{}_body", module), "tagdata");
+    }
+
+    output += &format!("\ndef get():\n    return {}_def\n\n", module);
+    output += &format!(
+        "{}_def = TagDef({},\n    blam_header({}, {}),\n    {}_body,\n\n    ext={}, endian=\">\", tag_cls=HekTag\n    )\n",
+        module, quote(&fourcc_to_string(group.group.fourcc)), quote(&fourcc_to_string(group.group.fourcc)), group.group.version,
+        module, quote(&format!(".{}", group.name))
+    );
+    output
+}
+
+/// Generate the `__init__.py` of a package of Reclaimer definition modules, listing every module
+pub fn package(codegen: &Codegen) -> String {
+    let modules: Vec<String> = codegen.groups.iter().map(|g| format!("    {},\n", quote(&module_name(g)))).collect();
+    format!("# Generated by gorilla from the definitions of guerilla {}\n\n__all__ = (\n{}    )\n", build_name(codegen.definitions), modules.concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use tag_file::tests::{definitions, temporary_directory};
+
+    /// Stand-ins for the parts of Reclaimer and supyr_struct the modules use, which check the size of each struct
+    const STUBS: &[(&str, &str)] = &[
+        ("reclaimer/__init__.py", ""),
+        ("reclaimer/hek/__init__.py", ""),
+        ("reclaimer/hek/defs/objs/__init__.py", ""),
+        ("reclaimer/hek/defs/objs/tag.py", "class HekTag:\n    pass\n"),
+        ("supyr_struct/__init__.py", ""),
+        ("supyr_struct/defs/__init__.py", ""),
+        ("supyr_struct/defs/tag_def.py", "class TagDef:\n    def __init__(self, def_id, header, body, ext, endian, tag_cls):\n        self.def_id, self.header, self.body, self.ext = def_id, header, body, ext\n"),
+        ("reclaimer/common_descs.py", r#"
+class Descriptor:
+    def __init__(self, size, name, *args, **kwargs):
+        self.size, self.name, self.args, self.kwargs = size, name, args, kwargs
+
+def sized(size):
+    return lambda name, *args, **kwargs: Descriptor(size, name, *args, **kwargs)
+
+SInt8, SInt16, SInt32, UInt8, UInt32, Float = sized(1), sized(2), sized(4), sized(1), sized(4), sized(4)
+Bool8, Bool16, Bool32, SEnum16 = sized(1), sized(2), sized(4), sized(2)
+ascii_str32, dependency, reflexive, rawdata_ref = sized(32), sized(16), sized(12), sized(20)
+
+def Pad(size):
+    return Descriptor(size, None)
+
+def Struct(name, *fields, SIZE):
+    assert sum(f.size for f in fields) == SIZE, name
+    return Descriptor(SIZE, name, *fields)
+
+def QStruct(name, *fields, **kwargs):
+    return Descriptor(sum(f.size for f in fields), name, *fields, **kwargs)
+
+def Array(name, SUB_STRUCT, SIZE, **kwargs):
+    return Descriptor(SUB_STRUCT.size * SIZE, name, SUB_STRUCT, **kwargs)
+
+def blam_header(fourcc, version):
+    return (fourcc, version)
+"#),
+        ("test.py", r#"
+import importlib
+from reclaimer.hek.defs import __all__ as modules
+
+assert modules == ("mode", "weip")
+weapon = importlib.import_module("reclaimer.hek.defs.weip").get()
+assert (weapon.def_id, weapon.header, weapon.ext) == ("weip", ("weip", 2), ".weapon")
+
+body = weapon.body
+assert body.size == 132
+assert [f.name for f in body.args] == ["name", "flags", "type", None, "rate", "model", "triggers", "data", "vals", "bounds", "color", "trigger_index", None]
+assert body.args[2].args == ("rifle", "pistol")
+assert body.args[5].args == ("mode",)
+triggers = body.args[6]
+assert (triggers.args[0].size, triggers.args[1]) == (24, 2)
+assert triggers.args[0].args[1].args == ()  # every group is allowed
+assert body.args[7].kwargs == {"max_size": 1024}
+
+model = importlib.import_module("reclaimer.hek.defs.mode").get()
+assert model.body.size == 4
+"#)
+    ];
+
+    #[test]
+    fn load_modules() {
+        let definitions = definitions();
+        let codegen = Codegen::new(&definitions);
+        let directory = temporary_directory("reclaimer_load_modules");
+        let defs = directory.join("reclaimer").join("hek").join("defs");
+        std::fs::create_dir_all(defs.join("objs")).unwrap();
+        std::fs::create_dir_all(directory.join("supyr_struct").join("defs")).unwrap();
+        for (path, contents) in STUBS {
+            std::fs::write(directory.join(path), contents).unwrap();
+        }
+        std::fs::write(defs.join("__init__.py"), package(&codegen)).unwrap();
+        for group in &codegen.groups {
+            std::fs::write(defs.join(format!("{}.py", module_name(group))), module(&codegen, group)).unwrap();
+        }
+
+        let run = Command::new("python3").arg("test.py").current_dir(&directory).output().unwrap();
+        assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    }
+}
@@ -0,0 +1,665 @@
+//! Generating a Rust module with a struct for every block, along with readers and writers for tag files
+//!
+//! The generated module doesn't depend on anything outside of the standard library. To generate it from a build script:
+//!
+//! ```no_run
+//! # extern crate gorilla;
+//! # use std::path::Path;
+//! let output = Path::new(&std::env::var("OUT_DIR").unwrap()).join("tags.rs");
+//! gorilla::codegen::rust::write_module(Path::new("definitions.json"), &output).unwrap();
+//! println!("cargo:rerun-if-changed=definitions.json");
+//! ```
+//!
+//! And to use it:
+//!
+//! ```ignore
+//! mod tags {
+//!     include!(concat!(env!("OUT_DIR"), "/tags.rs"));
+//! }
+//! use tags::TagGroup;
+//!
+//! let weapon = tags::groups::Weapon::read_tag(&std::fs::read("pistol.weapon")?)?;
+//! std::fs::write("pistol.weapon", tags::groups::Weapon::write_tag(&weapon))?;
+//! ```
+//!
+//! Groups whose blocks are the same share a struct, but each group has its own type in `groups` with its FourCC.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use def_dumper::{BlockFieldType, read_definitions};
+use super::{Codegen, Enum, Member, MemberKind, Struct, build_name, escape_keyword, one_line, pascal_case};
+
+/// Words that can't be used as field names in Rust
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate", "do", "dyn", "else",
+    "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop", "macro", "match", "mod", "move",
+    "mut", "override", "priv", "pub", "ref", "return", "self", "static", "struct", "super", "trait", "true", "try",
+    "type", "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield"
+];
+
+/// Keywords that can't be used as type or enum variant names, which are in PascalCase
+const TYPE_KEYWORDS: &[&str] = &["Self"];
+
+/// Get the name of a type or enum variant
+fn type_identifier(identifier: &str) -> String {
+    escape_keyword(pascal_case(identifier), TYPE_KEYWORDS)
+}
+
+/// Everything the generated structs need to read and write tags
+const PRELUDE: &str = r##"/// Size of the header at the start of every tag file
+pub const TAG_HEADER_SIZE: usize = 0x40;
+
+/// Reads the data of a tag file in order
+pub struct TagReader<'a> {
+    pub data: &'a [u8]
+}
+
+impl<'a> TagReader<'a> {
+    /// Take the next `size` bytes
+    pub fn take(&mut self, size: usize) -> Option<&'a [u8]> {
+        if size > self.data.len() {
+            return None
+        }
+        let (taken, remaining) = self.data.split_at(size);
+        self.data = remaining;
+        Some(taken)
+    }
+}
+
+/// A value stored in a fixed number of bytes
+pub trait TagValue: Sized {
+    const SIZE: usize;
+    fn read(data: &[u8]) -> Self;
+    fn write(&self, data: &mut [u8]);
+}
+
+macro_rules! tag_number {
+    ($($t:ty),+) => {
+        $(impl TagValue for $t {
+            const SIZE: usize = std::mem::size_of::<$t>();
+            fn read(data: &[u8]) -> Self {
+                let mut bytes = [0u8; std::mem::size_of::<$t>()];
+                bytes.copy_from_slice(&data[..Self::SIZE]);
+                <$t>::from_be_bytes(bytes)
+            }
+            fn write(&self, data: &mut [u8]) {
+                data[..Self::SIZE].copy_from_slice(&self.to_be_bytes());
+            }
+        })+
+    }
+}
+tag_number!(i8, i16, i32, u8, u16, u32, f32);
+
+/// Strings are 32 bytes, including the null terminator
+impl TagValue for String {
+    const SIZE: usize = 32;
+    fn read(data: &[u8]) -> Self {
+        let data = &data[..Self::SIZE];
+        let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        String::from_utf8_lossy(&data[..end]).into_owned()
+    }
+    fn write(&self, data: &mut [u8]) {
+        let length = self.len().min(Self::SIZE - 1);
+        data[..length].copy_from_slice(&self.as_bytes()[..length]);
+        data[length..Self::SIZE].fill(0);
+    }
+}
+
+macro_rules! tag_struct {
+    ($name:ident { $($field:ident: $t:ty),+ }) => {
+        #[derive(Clone, Copy, PartialEq, Default, Debug)]
+        pub struct $name {
+            $(pub $field: $t),+
+        }
+        impl TagValue for $name {
+            const SIZE: usize = 0 $(+ <$t as TagValue>::SIZE)+;
+            fn read(data: &[u8]) -> Self {
+                let mut offset = 0;
+                $(let $field = <$t>::read(&data[offset..]); offset += <$t as TagValue>::SIZE;)+
+                let _ = offset;
+                $name { $($field),+ }
+            }
+            fn write(&self, data: &mut [u8]) {
+                let mut offset = 0;
+                $(self.$field.write(&mut data[offset..]); offset += <$t as TagValue>::SIZE;)+
+                let _ = offset;
+            }
+        }
+    }
+}
+tag_struct!(Point2DInt { x: i16, y: i16 });
+tag_struct!(Rectangle2D { top: i16, left: i16, bottom: i16, right: i16 });
+tag_struct!(Point2D { x: f32, y: f32 });
+tag_struct!(Point3D { x: f32, y: f32, z: f32 });
+tag_struct!(Vector2D { i: f32, j: f32 });
+tag_struct!(Vector3D { i: f32, j: f32, k: f32 });
+tag_struct!(Quaternion { i: f32, j: f32, k: f32, w: f32 });
+tag_struct!(Euler2D { yaw: f32, pitch: f32 });
+tag_struct!(Euler3D { yaw: f32, pitch: f32, roll: f32 });
+tag_struct!(Plane2D { normal: Vector2D, d: f32 });
+tag_struct!(Plane3D { normal: Vector3D, d: f32 });
+tag_struct!(ColorRGB { red: f32, green: f32, blue: f32 });
+tag_struct!(ColorARGB { alpha: f32, red: f32, green: f32, blue: f32 });
+tag_struct!(ColorHSV { hue: f32, saturation: f32, value: f32 });
+tag_struct!(ColorAHSV { alpha: f32, hue: f32, saturation: f32, value: f32 });
+
+/// A range of values
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Bounds<T> {
+    pub from: T,
+    pub to: T
+}
+
+impl<T: TagValue> TagValue for Bounds<T> {
+    const SIZE: usize = T::SIZE * 2;
+    fn read(data: &[u8]) -> Self {
+        Bounds { from: T::read(data), to: T::read(&data[T::SIZE..]) }
+    }
+    fn write(&self, data: &mut [u8]) {
+        self.from.write(data);
+        self.to.write(&mut data[T::SIZE..]);
+    }
+}
+
+macro_rules! tag_flags {
+    ($name:ident: $t:ty { $($flag:ident = $value:expr),* }) => {
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+        pub struct $name(pub $t);
+        impl $name {
+            $(pub const $flag: $name = $name($value);)*
+
+            /// Returns true if every bit set in `other` is set
+            pub fn contains(self, other: $name) -> bool {
+                self.0 & other.0 == other.0
+            }
+            pub fn insert(&mut self, other: $name) {
+                self.0 |= other.0;
+            }
+            pub fn remove(&mut self, other: $name) {
+                self.0 &= !other.0;
+            }
+        }
+        impl std::ops::BitOr for $name {
+            type Output = $name;
+            fn bitor(self, other: $name) -> $name {
+                $name(self.0 | other.0)
+            }
+        }
+        impl std::ops::BitAnd for $name {
+            type Output = $name;
+            fn bitand(self, other: $name) -> $name {
+                $name(self.0 & other.0)
+            }
+        }
+        impl TagValue for $name {
+            const SIZE: usize = <$t as TagValue>::SIZE;
+            fn read(data: &[u8]) -> Self {
+                $name(<$t>::read(data))
+            }
+            fn write(&self, data: &mut [u8]) {
+                self.0.write(data);
+            }
+        }
+    }
+}
+
+/// A reference to another tag
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct TagReference {
+    /// FourCC of the referenced group (or 0xFFFFFFFF if none)
+    pub group: u32,
+    pub path: String
+}
+
+impl Default for TagReference {
+    fn default() -> Self {
+        TagReference { group: 0xFFFFFFFF, path: String::new() }
+    }
+}
+
+impl TagReference {
+    pub const SIZE: usize = 0x10;
+    fn read_fixed(data: &[u8]) -> Self {
+        TagReference { group: u32::read(data), path: String::new() }
+    }
+    fn read_path(&mut self, reader: &mut TagReader, data: &[u8]) -> Option<()> {
+        let length = u32::read(&data[8..]) as usize;
+        if length > 0 {
+            self.path = String::from_utf8_lossy(&reader.take(length + 1)?[..length]).into_owned();
+        }
+        Some(())
+    }
+    fn write_fixed(&self, data: &mut [u8]) {
+        self.group.write(data);
+        (self.path.len() as u32).write(&mut data[8..]);
+        0xFFFFFFFFu32.write(&mut data[12..]);
+    }
+    fn write_path(&self, output: &mut Vec<u8>) {
+        if !self.path.is_empty() {
+            output.extend_from_slice(self.path.as_bytes());
+            output.push(0);
+        }
+    }
+}
+
+/// An element of a block or array
+///
+/// Elements are stored in two parts: the fixed-size part, and everything the element's references, blocks and tag data
+/// point to, which comes after the fixed-size part of every element of the block.
+pub trait TagElement: Sized + Default {
+    const SIZE: usize;
+    fn read_fixed(data: &[u8]) -> Self;
+    fn read_children(&mut self, reader: &mut TagReader, data: &[u8]) -> Option<()>;
+    fn write_fixed(&self, data: &mut [u8]);
+    fn write_children(&self, output: &mut Vec<u8>);
+}
+
+/// Read `count` elements of a block
+pub fn read_elements<T: TagElement>(reader: &mut TagReader, count: usize) -> Option<Vec<T>> {
+    let data = reader.take(T::SIZE.checked_mul(count)?)?;
+    let mut elements: Vec<T> = (0..count).map(|i| T::read_fixed(&data[i * T::SIZE..])).collect();
+    for (i, element) in elements.iter_mut().enumerate() {
+        element.read_children(reader, &data[i * T::SIZE..])?;
+    }
+    Some(elements)
+}
+
+/// Write the elements of a block
+pub fn write_elements<T: TagElement>(elements: &[T], output: &mut Vec<u8>) {
+    let start = output.len();
+    output.resize(start + T::SIZE * elements.len(), 0);
+    for (i, element) in elements.iter().enumerate() {
+        element.write_fixed(&mut output[start + i * T::SIZE..]);
+    }
+    for element in elements {
+        element.write_children(output);
+    }
+}
+
+fn read_array<T: TagElement>(data: &[u8], count: usize) -> Vec<T> {
+    (0..count).map(|i| T::read_fixed(&data[i * T::SIZE..])).collect()
+}
+
+fn write_array<T: TagElement>(elements: &[T], data: &mut [u8], count: usize) {
+    for (i, element) in elements.iter().take(count).enumerate() {
+        element.write_fixed(&mut data[i * T::SIZE..]);
+    }
+}
+
+/// Calculate the CRC32 used for tag file checksums (a standard CRC32 without the final XOR)
+pub fn tag_crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// A group, with the struct of its tags
+///
+/// Groups whose blocks are the same share a struct, so each group gets its own type in [`groups`].
+pub trait TagGroup {
+    type Element: TagElement;
+    const FOURCC: u32;
+    const VERSION: u16;
+
+    /// Read a tag file of this group
+    fn read_tag(data: &[u8]) -> Option<Self::Element> {
+        if data.len() < TAG_HEADER_SIZE || u32::read(&data[0x3C..]) != 0x626C616D || u32::read(&data[0x24..]) != Self::FOURCC {
+            return None
+        }
+        let mut reader = TagReader { data: &data[TAG_HEADER_SIZE..] };
+        read_elements::<Self::Element>(&mut reader, 1)?.pop()
+    }
+
+    /// Write a tag file of this group, calculating its checksum
+    fn write_tag(element: &Self::Element) -> Vec<u8> {
+        let mut output = vec![0u8; TAG_HEADER_SIZE];
+        write_elements(std::slice::from_ref(element), &mut output);
+
+        let checksum = tag_crc32(&output[TAG_HEADER_SIZE..]);
+        Self::FOURCC.write(&mut output[0x24..]);
+        checksum.write(&mut output[0x28..]);
+        (TAG_HEADER_SIZE as u32).write(&mut output[0x2C..]);
+        Self::VERSION.write(&mut output[0x38..]);
+        0x00FFu16.write(&mut output[0x3A..]);
+        0x626C616Du32.write(&mut output[0x3C..]);
+        output
+    }
+}
+"##;
+
+/// Get the Rust type of a primitive
+fn primitive_type(type_name: &str) -> &'static str {
+    match type_name {
+        "string" => "String",
+        "int8" => "i8",
+        "int16" => "i16",
+        "int32" => "i32",
+        "fourcc" | "color_rgb_int" | "color_argb_int" => "u32",
+        "point2d_int" => "Point2DInt",
+        "rectangle" => "Rectangle2D",
+        "point2d" => "Point2D",
+        "point3d" => "Point3D",
+        "vector2d" => "Vector2D",
+        "vector3d" => "Vector3D",
+        "quaternion" => "Quaternion",
+        "euler2d" => "Euler2D",
+        "euler3d" => "Euler3D",
+        "plane2d" => "Plane2D",
+        "plane3d" => "Plane3D",
+        "color_rgb" => "ColorRGB",
+        "color_argb" => "ColorARGB",
+        "color_hsv" => "ColorHSV",
+        "color_ahsv" => "ColorAHSV",
+        _ => "f32"
+    }
+}
+
+/// Get the unsigned type a bitfield is stored as
+fn flags_type(size: &str) -> &'static str {
+    match size {
+        "int8" => "u8",
+        "int16" => "u16",
+        _ => "u32"
+    }
+}
+
+/// Make names unique by appending a number to duplicates
+fn unique_names(names: Vec<String>) -> Vec<String> {
+    let mut result = Vec::<String>::with_capacity(names.len());
+    for base in names {
+        let mut name = base.clone();
+        let mut number = 2;
+        while result.contains(&name) {
+            name = format!("{}{}", base, number);
+            number += 1;
+        }
+        result.push(name);
+    }
+    result
+}
+
+fn write_enum(output: &mut String, e: &Enum) {
+    let type_name = type_identifier(&e.name);
+    let field_name = e.field.name.as_ref().map(|n| one_line(&n.name)).unwrap_or_default();
+
+    if e.flags {
+        *output += &format!("tag_flags!({}: {} {{\n", type_name, flags_type(e.size));
+        let flags: Vec<String> = e.options.iter().map(|o| format!("    {} = 0x{:X}", o.name.to_ascii_uppercase(), o.value)).collect();
+        *output += &flags.join(",\n");
+        *output += "\n});\n\n";
+        return
+    }
+
+    let variants = unique_names(e.options.iter().map(|o| type_identifier(&o.name)).collect());
+    let mut fallback = "Unrecognized".to_owned();
+    while variants.contains(&fallback) {
+        fallback += "Value";
+    }
+
+    // The first option is the default, or index 0 if there are no options
+    let derives = if variants.is_empty() { "Clone, Copy, PartialEq, Eq, Hash, Debug" } else { "Clone, Copy, PartialEq, Eq, Hash, Default, Debug" };
+    *output += &format!("/// {}\n#[derive({})]\npub enum {} {{\n", field_name, derives, type_name);
+    for (i, (v, o)) in variants.iter().zip(e.options.iter()).enumerate() {
+        *output += &format!("    /// {}\n{}    {},\n", one_line(&o.option.name), if i == 0 { "    #[default]\n" } else { "" }, v);
+    }
+    *output += &format!("    /// An index that isn't in the definitions\n    {}(i16)\n}}\n\n", fallback);
+    if variants.is_empty() {
+        *output += &format!("impl Default for {} {{\n    fn default() -> Self {{\n        {}::{}(0)\n    }}\n}}\n\n", type_name, type_name, fallback);
+    }
+
+    *output += &format!("impl TagValue for {} {{\n    const SIZE: usize = 2;\n    fn read(data: &[u8]) -> Self {{\n        match i16::read(data) {{\n", type_name);
+    for (v, o) in variants.iter().zip(e.options.iter()) {
+        *output += &format!("            {} => {}::{},\n", o.value, type_name, v);
+    }
+    *output += &format!("            n => {}::{}(n)\n        }}\n    }}\n    fn write(&self, data: &mut [u8]) {{\n        let n: i16 = match self {{\n", type_name, fallback);
+    for (v, o) in variants.iter().zip(e.options.iter()) {
+        *output += &format!("            {}::{} => {},\n", type_name, v, o.value);
+    }
+    *output += &format!("            {}::{}(n) => *n\n        }};\n        n.write(data);\n    }}\n}}\n\n", type_name, fallback);
+}
+
+/// Get the Rust type of a member
+fn member_type(member: &Member) -> String {
+    match &member.kind {
+        MemberKind::Primitive(type_name) => primitive_type(type_name).to_owned(),
+        MemberKind::Range(type_name) => format!("Bounds<{}>", primitive_type(type_name)),
+        MemberKind::Index(size) => primitive_type(size).to_owned(),
+        MemberKind::Enum(e) | MemberKind::Flags(e, _) => type_identifier(e),
+        MemberKind::Reference => "TagReference".to_owned(),
+        MemberKind::Block(s) | MemberKind::Array(s, _) => format!("Vec<{}>", type_identifier(s)),
+        MemberKind::TagData => "Vec<u8>".to_owned(),
+        MemberKind::Padding => String::new()
+    }
+}
+
+/// Get the default value of a member
+fn member_default(codegen: &Codegen, member: &Member) -> String {
+    match &member.kind {
+        MemberKind::Index(_) => "-1".to_owned(),
+        MemberKind::Array(_, count) => format!("(0..{}).map(|_| Default::default()).collect()", count),
+        MemberKind::Reference => match member.field.map(|f| &f.block_type) {
+            Some(BlockFieldType::Reference(groups)) if groups.len() == 1 => match codegen.definitions.groups.get(&groups[0]) {
+                Some(g) => format!("TagReference {{ group: 0x{:08X}, path: String::new() }}", g.fourcc),
+                None => "Default::default()".to_owned()
+            },
+            _ => "Default::default()".to_owned()
+        },
+        _ => "Default::default()".to_owned()
+    }
+}
+
+/// Describe a member in a doc comment
+fn doc(member: &Member) -> String {
+    let mut doc = match member.field.and_then(|f| f.name.as_ref()) {
+        Some(name) => {
+            let mut doc = one_line(&name.name);
+            if let Some(unit) = &name.unit {
+                doc += &format!(" ({})", one_line(unit));
+            }
+            if let Some(description) = &name.description {
+                doc += &format!(" - {}", one_line(description));
+            }
+            doc
+        },
+        None => member.name.clone()
+    };
+    if let MemberKind::Array(_, count) = &member.kind {
+        doc += &format!(" (always {} elements)", count);
+    }
+    doc
+}
+
+fn write_struct(output: &mut String, codegen: &Codegen, s: &Struct) {
+    let type_name = type_identifier(&s.name);
+    let members: Vec<(String, &Member)> = s.members.iter()
+        .filter(|m| !matches!(m.kind, MemberKind::Padding))
+        .map(|m| (escape_keyword(m.name.clone(), KEYWORDS), m))
+        .collect();
+
+    match s.block {
+        Some(block) => *output += &format!("/// {} (maximum {})\n", one_line(block.name.as_deref().unwrap_or(&s.name)), block.maximum),
+        None => *output += "/// Array element\n"
+    }
+
+    // Default can only be derived if nothing needs a different default value
+    let defaults: Vec<(&String, String)> = members.iter().map(|(name, m)| (name, member_default(codegen, m))).collect();
+    let derive_default = defaults.iter().all(|(_, d)| d == "Default::default()");
+
+    *output += &format!("#[derive(Clone, PartialEq, {}Debug)]\npub struct {} {{\n", if derive_default { "Default, " } else { "" }, type_name);
+    let fields: Vec<String> = members.iter().map(|(name, m)| format!("    /// {}\n    pub {}: {}", doc(m), name, member_type(m))).collect();
+    *output += &fields.join(",\n\n");
+    *output += "\n}\n\n";
+
+    if !derive_default {
+        *output += &format!("impl Default for {} {{\n    fn default() -> Self {{\n        {} {{\n", type_name, type_name);
+        let defaults: Vec<String> = defaults.iter().map(|(name, d)| format!("            {}: {}", name, d)).collect();
+        *output += &defaults.join(",\n");
+        *output += "\n        }\n    }\n}\n\n";
+    }
+
+    // Reading
+    let has_children = members.iter().any(|(_, m)| matches!(m.kind, MemberKind::Reference | MemberKind::Block(_) | MemberKind::TagData | MemberKind::Array(_, _)));
+    let data = if members.is_empty() { "_data" } else { "data" };
+    *output += &format!("impl TagElement for {} {{\n    const SIZE: usize = 0x{:X};\n\n", type_name, s.size);
+    *output += &format!("    fn read_fixed({}: &[u8]) -> Self {{\n        {} {{\n", data, type_name);
+    let reads: Vec<String> = members.iter().map(|(name, m)| {
+        let value = match &m.kind {
+            MemberKind::Reference => format!("TagReference::read_fixed(&data[0x{:X}..])", m.offset),
+            MemberKind::Block(_) | MemberKind::TagData => "Vec::new()".to_owned(),
+            MemberKind::Array(_, count) => format!("read_array(&data[0x{:X}..], {})", m.offset, count),
+            _ => format!("TagValue::read(&data[0x{:X}..])", m.offset)
+        };
+        format!("            {}: {}", name, value)
+    }).collect();
+    *output += &reads.join(",\n");
+    *output += "\n        }\n    }\n\n";
+
+    let (reader, data) = if has_children { ("reader", "data") } else { ("_reader", "_data") };
+    *output += &format!("    fn read_children(&mut self, {}: &mut TagReader, {}: &[u8]) -> Option<()> {{\n", reader, data);
+    for (name, m) in &members {
+        match &m.kind {
+            MemberKind::Reference => *output += &format!("        self.{}.read_path(reader, &data[0x{:X}..])?;\n", name, m.offset),
+            MemberKind::Block(_) => *output += &format!("        self.{} = read_elements(reader, u32::read(&data[0x{:X}..]) as usize)?;\n", name, m.offset),
+            MemberKind::TagData => *output += &format!("        self.{} = reader.take(u32::read(&data[0x{:X}..]) as usize)?.to_vec();\n", name, m.offset),
+            MemberKind::Array(s, _) => *output += &format!(
+                "        for (i, element) in self.{}.iter_mut().enumerate() {{\n            element.read_children(reader, &data[0x{:X} + i * {}::SIZE..])?;\n        }}\n",
+                name, m.offset, type_identifier(s)
+            ),
+            _ => ()
+        }
+    }
+    *output += "        Some(())\n    }\n\n";
+
+    // Writing
+    let data = if members.is_empty() { "_data" } else { "data" };
+    *output += &format!("    fn write_fixed(&self, {}: &mut [u8]) {{\n", data);
+    for (name, m) in &members {
+        match &m.kind {
+            MemberKind::Reference => *output += &format!("        self.{}.write_fixed(&mut data[0x{:X}..]);\n", name, m.offset),
+            MemberKind::Block(_) | MemberKind::TagData => *output += &format!("        (self.{}.len() as u32).write(&mut data[0x{:X}..]);\n", name, m.offset),
+            MemberKind::Array(_, count) => *output += &format!("        write_array(&self.{}, &mut data[0x{:X}..], {});\n", name, m.offset, count),
+            _ => *output += &format!("        self.{}.write(&mut data[0x{:X}..]);\n", name, m.offset)
+        }
+    }
+    *output += "    }\n\n";
+
+    let output_name = if has_children { "output" } else { "_output" };
+    *output += &format!("    fn write_children(&self, {}: &mut Vec<u8>) {{\n", output_name);
+    for (name, m) in &members {
+        match &m.kind {
+            MemberKind::Reference => *output += &format!("        self.{}.write_path(output);\n", name),
+            MemberKind::Block(_) => *output += &format!("        write_elements(&self.{}, output);\n", name),
+            MemberKind::TagData => *output += &format!("        output.extend_from_slice(&self.{});\n", name),
+            MemberKind::Array(_, count) => *output += &format!(
+                "        for element in self.{}.iter().take({}) {{\n            element.write_children(output);\n        }}\n", name, count
+            ),
+            _ => ()
+        }
+    }
+    *output += "    }\n}\n\n";
+}
+
+/// Generate a Rust module with a struct for every block and array, along with enums and bitfields
+pub fn module(codegen: &Codegen) -> String {
+    let mut output = format!(
+        "// Generated by gorilla from the definitions of guerilla {}\n//\n// Include this in a module with `include!`.\n\n",
+        build_name(codegen.definitions)
+    );
+    output += PRELUDE;
+    output += "\n";
+
+    for e in &codegen.enums {
+        write_enum(&mut output, e);
+    }
+    for s in &codegen.structs {
+        write_struct(&mut output, codegen, s);
+    }
+
+    output += "/// A type for each group, for reading and writing its tag files\npub mod groups {\n";
+    for (i, g) in codegen.groups.iter().enumerate() {
+        output += &format!(
+            "{}    /// `{}` tags\n    pub struct {};\n    impl super::TagGroup for {} {{\n        type Element = super::{};\n        const FOURCC: u32 = 0x{:08X};\n        const VERSION: u16 = {};\n    }}\n",
+            if i == 0 { "" } else { "\n" }, g.name, type_identifier(g.name), type_identifier(g.name), type_identifier(&g.struct_name), g.group.fourcc, g.group.version
+        );
+    }
+    output += "}\n";
+
+    while output.ends_with("\n\n") {
+        output.pop();
+    }
+    output
+}
+
+/// Generate a Rust module from definitions (guerilla.exe or a JSON dump) and write it, such as from a build script
+pub fn write_module(definitions: &Path, output: &Path) -> Option<()> {
+    let mut file_data = Vec::new();
+    if let Err(e) = File::open(definitions).and_then(|mut f| f.read_to_end(&mut file_data)) {
+        eprintln!("Can't read {}: {}", definitions.display(), e);
+        return None
+    }
+
+    let definitions = read_definitions(&file_data)?;
+    let code = module(&Codegen::new(&definitions));
+    if let Err(e) = File::create(output).and_then(|mut f| f.write_all(code.as_bytes())) {
+        eprintln!("Can't write {}: {}", output.display(), e);
+        return None
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use tag_file::TagFile;
+    use tag_file::tests::{definitions, temporary_directory};
+
+    /// Reads a tag of each group written by gorilla and writes it back, for groups that share a struct
+    const MAIN: &str = r#"
+fn main() {
+    use groups::{Gun, Weapon};
+    let args: Vec<String> = std::env::args().collect();
+    let gun_tag = std::fs::read(&args[1]).unwrap();
+    let weapon_tag = std::fs::read(&args[2]).unwrap();
+
+    let gun = Gun::read_tag(&gun_tag).unwrap();
+    assert_eq!(gun, WeaponBlock::default());
+    assert_eq!(Gun::write_tag(&gun), gun_tag);
+    assert!(Weapon::read_tag(&gun_tag).is_none());
+
+    let weapon = Weapon::read_tag(&weapon_tag).unwrap();
+    assert_eq!(Weapon::write_tag(&weapon), weapon_tag);
+    assert!(Gun::read_tag(&weapon_tag).is_none());
+}
+"#;
+
+    #[test]
+    fn groups_sharing_a_struct() {
+        let mut definitions = definitions();
+        let mut gun = definitions.groups["weapon"].clone();
+        gun.fourcc = 0x67756E21;
+        definitions.groups.insert("gun".to_owned(), gun);
+
+        let codegen = Codegen::new(&definitions);
+        assert_eq!(codegen.groups.iter().filter(|g| g.struct_name == "weapon_block").count(), 2);
+
+        let directory = temporary_directory("rust_groups_sharing_a_struct");
+        for group in ["gun", "weapon"] {
+            std::fs::write(directory.join(group), TagFile::new(&definitions, group).unwrap().to_bytes(&definitions).unwrap()).unwrap();
+        }
+        std::fs::write(directory.join("main.rs"), module(&codegen) + MAIN).unwrap();
+
+        let rustc = Command::new("rustc").arg("--edition=2021").arg("main.rs").current_dir(&directory).output().unwrap();
+        assert!(rustc.status.success(), "{}", String::from_utf8_lossy(&rustc.stderr));
+        let run = Command::new(directory.join("main")).arg(directory.join("gun")).arg(directory.join("weapon")).output().unwrap();
+        assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    }
+}
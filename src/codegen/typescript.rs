@@ -0,0 +1,210 @@
+//! Generating TypeScript declarations for tags converted to JSON
+//!
+//! The types match what [`json`](crate::tag_file::json) produces, with an interface for every block and array.
+
+extern crate serde_json;
+
+use def_dumper::BlockFieldType;
+use super::{Codegen, Enum, Member, MemberKind, Struct, build_name, one_line, pascal_case};
+
+/// Types used by the generated interfaces
+const PRELUDE: &str = "\
+/** A reference to another tag */
+export interface TagReference {
+    /** Name of the referenced group (or its FourCC if it isn't a known group), or null if nothing is referenced */
+    group: string | null;
+
+    /** Path of the referenced tag, without an extension */
+    path: string;
+}
+
+/** Tag data as a hexadecimal string */
+export type TagData = string;
+
+/** A FourCC as a four character string */
+export type TagFourCC = string;
+";
+
+/// Get the TypeScript type of a primitive, or the bounds of a range of a primitive
+fn primitive_type(type_name: &str, bounds: bool) -> String {
+    let components = match type_name {
+        "string" => return "string".to_owned(),
+        "fourcc" => return "TagFourCC".to_owned(),
+        "point2d_int" | "point2d" | "vector2d" | "euler2d" => 2,
+        "point3d" | "vector3d" | "euler3d" | "plane2d" | "color_rgb" | "color_hsv" => 3,
+        "rectangle" | "quaternion" | "plane3d" | "color_argb" | "color_ahsv" => 4,
+        _ => 1
+    };
+    let count = if bounds { components * 2 } else { components };
+    if count == 1 {
+        "number".to_owned()
+    }
+    else {
+        format!("[{}]", vec!["number"; count].join(", "))
+    }
+}
+
+/// Quote a string for TypeScript
+fn quote(text: &str) -> String {
+    serde_json::Value::from(text).to_string()
+}
+
+/// Write a property name, quoting it if it isn't an identifier
+fn property(key: &str) -> String {
+    let identifier = !key.is_empty()
+        && !key.starts_with(|c: char| c.is_ascii_digit())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if identifier { key.to_owned() } else { quote(key) }
+}
+
+/// Put text in a JSDoc comment without ending it early
+fn jsdoc(text: &str) -> String {
+    format!("/** {} */", one_line(text).replace("*/", "*\\/"))
+}
+
+fn write_enum(output: &mut String, e: &Enum) {
+    let type_name = pascal_case(&e.name);
+    if let Some(name) = &e.field.name {
+        *output += &format!("{}\n", jsdoc(&name.name));
+    }
+
+    if e.flags {
+        *output += &format!("export interface {} {{\n", type_name);
+        for o in &e.options {
+            if !o.option.name.is_empty() {
+                *output += &format!("    {}\n", jsdoc(&o.option.name));
+            }
+            *output += &format!("    {}: boolean;\n", property(&o.identifier));
+        }
+        *output += "\n    /** Bits past the defined ones are only present if set, as bit_<n> */\n    [bit: string]: boolean;\n}\n\n";
+    }
+    else {
+        // Values without an option are written as numbers
+        let options: Vec<String> = e.options.iter().map(|o| quote(&o.identifier)).chain(std::iter::once("number".to_owned())).collect();
+        *output += &format!("export type {} = {};\n\n", type_name, options.join(" | "));
+    }
+}
+
+/// Get the TypeScript type of a member
+fn member_type(member: &Member) -> String {
+    match &member.kind {
+        MemberKind::Primitive(type_name) => primitive_type(type_name, false),
+        MemberKind::Range(type_name) => primitive_type(type_name, true),
+        MemberKind::Index(_) => "number".to_owned(),
+        MemberKind::Enum(e) | MemberKind::Flags(e, _) => pascal_case(e),
+        MemberKind::Reference => "TagReference".to_owned(),
+        MemberKind::Block(s) | MemberKind::Array(s, _) => format!("{}[]", pascal_case(s)),
+        MemberKind::TagData => "TagData".to_owned(),
+        MemberKind::Padding => String::new()
+    }
+}
+
+/// Describe a member in a comment
+fn comment(member: &Member) -> Option<String> {
+    let mut comment = Vec::new();
+    if let Some(name) = member.field.and_then(|f| f.name.as_ref()) {
+        let mut text = name.name.clone();
+        if let Some(unit) = &name.unit {
+            text += &format!(" ({})", unit);
+        }
+        if let Some(description) = &name.description {
+            text += &format!(" - {}", description);
+        }
+        comment.push(text);
+    }
+    match (&member.kind, member.field.map(|f| &f.block_type)) {
+        (MemberKind::Array(_, count), _) => comment.push(format!("Always {} elements", count)),
+        (MemberKind::Index(_), Some(BlockFieldType::Index(block, _))) => comment.push(format!("Index of an element of {}, or -1 for none", block)),
+        (MemberKind::Reference, Some(BlockFieldType::Reference(groups))) if !groups.is_empty() => comment.push(format!("Allowed groups: {}", groups.join(", "))),
+        _ => ()
+    }
+    if comment.is_empty() { None } else { Some(jsdoc(&comment.join(". "))) }
+}
+
+fn write_struct(output: &mut String, s: &Struct) {
+    let type_name = pascal_case(&s.name);
+    match s.block {
+        Some(block) => *output += &format!("{}\n", jsdoc(&format!("{} (maximum {})", block.name.as_deref().unwrap_or(&s.name), block.maximum))),
+        None => *output += "/** Array element */\n"
+    }
+
+    *output += &format!("export interface {} {{\n", type_name);
+    let mut first = true;
+    for m in s.members.iter().filter(|m| !matches!(m.kind, MemberKind::Padding)) {
+        let comment = comment(m);
+        if comment.is_some() && !first {
+            *output += "\n";
+        }
+        if let Some(comment) = comment {
+            *output += &format!("    {}\n", comment);
+        }
+        *output += &format!("    {}: {};\n", property(&m.identifier), member_type(m));
+        first = false;
+    }
+    *output += "}\n\n";
+}
+
+/// Generate TypeScript declarations for tags converted to JSON, with an interface for every block and array
+pub fn declarations(codegen: &Codegen) -> String {
+    let mut output = format!(
+        "// Generated by gorilla from the definitions of guerilla {}\n//\n// Types of tags converted to JSON by gorilla. Padding is left out.\n\n",
+        build_name(codegen.definitions)
+    );
+    output += PRELUDE;
+    output += "\n";
+
+    for e in &codegen.enums {
+        write_enum(&mut output, e);
+    }
+    for s in &codegen.structs {
+        write_struct(&mut output, s);
+    }
+
+    output += "/** The main interface of each group */\nexport interface Groups {\n";
+    for g in &codegen.groups {
+        output += &format!("    {}: {};\n", property(g.name), pascal_case(&g.struct_name));
+    }
+    output += "}\n\n/** Name of a group */\nexport type GroupName = keyof Groups;\n";
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use tag_file::{StructLayout, json};
+    use tag_file::tests::{definitions, tag_with};
+
+    /// Get the properties of an interface and their types
+    fn interface<'a>(declarations: &'a str, name: &str) -> BTreeMap<&'a str, &'a str> {
+        let start = declarations.find(&format!("export interface {} {{\n", name)).unwrap();
+        declarations[start..].lines().skip(1)
+            .take_while(|l| *l != "}")
+            .filter_map(|l| l.trim().strip_suffix(';')?.split_once(": "))
+            .collect()
+    }
+
+    #[test]
+    fn declarations_match_tag_json() {
+        let definitions = definitions();
+        let declarations = declarations(&Codegen::new(&definitions));
+
+        // Every key of a weapon tag converted to JSON is declared
+        let tag = tag_with(&definitions, "weapon", serde_json::json!({"triggers": [{}], "flags": {"a": true}}));
+        let value = json::struct_to_json(&definitions, &StructLayout::of(&definitions.groups["weapon"].block), &tag.data);
+        let weapon = interface(&declarations, "WeaponBlock");
+        let keys = |value: &serde_json::Value| value.as_object().unwrap().keys().cloned().collect::<Vec<String>>();
+        assert_eq!(weapon.keys().copied().collect::<Vec<&str>>(), keys(&value));
+        assert_eq!(weapon["model"], "TagReference");
+        assert_eq!(weapon["triggers"], "WeaponTriggerBlock[]");
+        assert_eq!(weapon["bounds"], "[number, number]");
+        assert_eq!(weapon["color"], "[number, number, number]");
+
+        let trigger = interface(&declarations, "WeaponTriggerBlock");
+        assert_eq!(trigger.keys().copied().collect::<Vec<&str>>(), keys(&value["triggers"][0]));
+        assert_eq!(interface(&declarations, "WeaponBlockFlags")["b_c"], "boolean");
+        assert!(declarations.contains("export type WeaponBlockType = \"rifle\" | \"pistol\" | number;\n"));
+        assert!(declarations.contains("export interface Groups {\n    model: ModelBlock;\n    weapon: WeaponBlock;\n}\n"));
+    }
+}
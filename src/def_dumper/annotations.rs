@@ -0,0 +1,180 @@
+//! Overlaying corrections and notes onto dumped definitions
+//!
+//! An annotations file is a JSON object or TOML table of [path IDs](super::path_id) to annotations, such as:
+//!
+//! ```toml
+//! ["weapon.triggers.rounds_per_second"]
+//! description = "how many rounds are fired each second"
+//! unit = "rounds per second"
+//!
+//! ["weapon.triggers.rounds_per_second+padding"]
+//! name = "old rate"
+//! deprecated = true
+//! ```
+//!
+//! Since the path IDs are assigned before annotations are applied, renaming a field doesn't change its ID.
+
+extern crate toml;
+
+use std::collections::BTreeMap;
+
+use super::serde::Deserialize;
+use super::serde_json;
+use super::{Block, BlockFieldType, Definitions, FieldName};
+
+/// Changes to make to a field, enum option or bit
+#[derive(Default, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Annotation {
+    /// Name to use instead of the one in the exe; required for fields that don't have one, like padding
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub unit: Option<String>,
+    pub notes: Option<String>,
+    pub deprecated: bool
+}
+
+/// Annotations by path ID
+pub type Annotations = BTreeMap<String, Annotation>;
+
+/// Read annotations from a JSON or TOML file
+pub fn read_annotations(file_data: &[u8]) -> Option<Annotations> {
+    let text = match std::str::from_utf8(file_data) {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("Can't read annotations: {}", e);
+            return None
+        }
+    };
+
+    // A JSON file is always an object, whereas a TOML file can't start with a brace
+    let result = if text.trim_start().starts_with('{') {
+        serde_json::from_str(text).map_err(|e| e.to_string())
+    }
+    else {
+        toml::from_str(text).map_err(|e| e.to_string())
+    };
+
+    match result {
+        Ok(n) => Some(n),
+        Err(e) => {
+            eprintln!("Can't parse annotations: {}", e);
+            None
+        }
+    }
+}
+
+/// Apply an annotation to a name
+fn annotate(name: &mut FieldName, annotation: &Annotation) {
+    if let Some(n) = &annotation.name {
+        name.name = n.to_owned();
+    }
+    if let Some(n) = &annotation.description {
+        name.description = Some(n.to_owned());
+    }
+    if let Some(n) = &annotation.unit {
+        name.unit = Some(n.to_owned());
+    }
+    if let Some(n) = &annotation.notes {
+        name.notes = Some(n.to_owned());
+    }
+    if annotation.deprecated {
+        name.deprecated = true;
+    }
+}
+
+fn annotate_block(block: &mut Block, annotations: &Annotations, used: &mut Vec<String>, warnings: &mut Vec<String>) {
+    for field in &mut block.fields {
+        match &mut field.block_type {
+            BlockFieldType::Block(b) => annotate_block(b, annotations, used, warnings),
+            BlockFieldType::Enum(options) | BlockFieldType::Flags(_, options) => for o in options {
+                if let Some(annotation) = annotations.get(&o.id) {
+                    annotate(o, annotation);
+                    used.push(o.id.clone());
+                }
+            },
+            _ => ()
+        }
+
+        let annotation = match annotations.get(&field.id) {
+            Some(n) => n,
+            None => continue
+        };
+        used.push(field.id.clone());
+
+        match (&mut field.name, &annotation.name) {
+            (Some(name), _) => annotate(name, annotation),
+            (None, Some(_)) => annotate(field.name.get_or_insert_with(FieldName::default), annotation),
+            (None, None) => warnings.push(format!("Annotation for `{}` needs a name since the field doesn't have one", field.id))
+        }
+    }
+}
+
+/// Apply annotations to definitions, returning warnings for any that couldn't be applied
+///
+/// The definitions need to have path IDs (see [`assign_path_ids`](super::path_id::assign_path_ids)).
+pub fn apply_annotations(definitions: &mut Definitions, annotations: &Annotations) -> Vec<String> {
+    let mut used = Vec::new();
+    let mut warnings = Vec::new();
+    for group in definitions.groups.values_mut() {
+        annotate_block(&mut group.block, annotations, &mut used, &mut warnings);
+    }
+
+    for id in annotations.keys() {
+        if !used.contains(id) {
+            warnings.push(format!("Annotation for `{}` doesn't match anything", id));
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tag_file::tests::definitions;
+
+    const TOML: &str = r#"
+["weapon.rate"]
+name = "fire rate"
+unit = "rounds per second"
+
+["weapon.type+padding"]
+name = "old type"
+deprecated = true
+
+["weapon.type:rifle"]
+description = "the long one"
+
+["weapon.trigger_index+padding"]
+notes = "no name"
+
+["weapon.removed"]
+name = "gone"
+"#;
+
+    #[test]
+    fn apply_toml() {
+        let annotations = read_annotations(TOML.as_bytes()).unwrap();
+        let mut definitions = definitions();
+        let warnings = apply_annotations(&mut definitions, &annotations);
+        assert_eq!(warnings, vec![
+            "Annotation for `weapon.trigger_index+padding` needs a name since the field doesn't have one",
+            "Annotation for `weapon.removed` doesn't match anything"
+        ]);
+
+        let rate = definitions.find_field("weapon.rate").unwrap().name.as_ref().unwrap();
+        assert_eq!((rate.name.as_str(), rate.unit.as_deref()), ("fire rate", Some("rounds per second")));
+        let padding = definitions.find_field("weapon.type+padding").unwrap().name.as_ref().unwrap();
+        assert_eq!(padding.name, "old type");
+        assert!(padding.deprecated);
+        assert_eq!(definitions.find_option("weapon.type:rifle").unwrap().description.as_deref(), Some("the long one"));
+        assert!(definitions.find_field("weapon.trigger_index+padding").unwrap().name.is_none());
+    }
+
+    #[test]
+    fn read_json_and_reject_unknown_keys() {
+        let annotations = read_annotations(br#"{"weapon.rate": {"deprecated": true}}"#).unwrap();
+        assert!(annotations["weapon.rate"].deprecated);
+        assert!(read_annotations(br#"{"weapon.rate": {"colour": "red"}}"#).is_none());
+    }
+}
@@ -1,16 +1,25 @@
 //! Halo-related data and block structs
 
-use super::serde::Serialize;
+use super::serde::{Serialize, Deserialize};
 
-#[derive(Default)]
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
 pub struct FieldName {
+    /// Path ID of the enum option or bit (see [`path_id`](super::path_id)), or empty for field names
+    pub id : String,
     pub name : String,
     pub hidden : bool,
     pub read_only : bool,
     pub main : bool,
     pub description : Option<String>,
     pub unit : Option<String>,
-    pub color : Option<String>
+    pub color : Option<String>,
+
+    /// Notes from an annotations file (see [`annotations`](super::annotations))
+    pub notes : Option<String>,
+
+    /// Marked as deprecated by an annotations file
+    pub deprecated : bool
 }
 impl FieldName {
     pub fn new(name: &str) -> Self {
@@ -70,7 +79,18 @@ impl FieldName {
         f
     }
 
+    /// Get an identifier for the name, lowercase with anything that isn't alphanumeric turned into underscores
+    ///
+    /// For example, `rounds per second` becomes `rounds_per_second`.
+    pub fn identifier(&self) -> String {
+        to_identifier(&self.name)
+    }
+
     fn serialize_inplace<S>(&self, map: &mut S::SerializeMap) -> Result<(), S::Error> where S: Serializer {
+        if !self.id.is_empty() {
+            map.serialize_entry("id", &self.id)?;
+        }
+
         map.serialize_entry("name", &self.name)?;
 
         match self.description {
@@ -100,6 +120,12 @@ impl FieldName {
             None => ()
         }
 
+        if let Some(ref n) = self.notes { map.serialize_entry("notes", n)? }
+
+        if self.deprecated {
+            map.serialize_entry("deprecated", &true)?
+        }
+
         Ok(())
     }
 }
@@ -113,28 +139,94 @@ impl Serialize for FieldName {
     }
 }
 
-#[derive(Default)]
+/// Convert a guerilla name into a lowercase identifier, turning any run of characters that isn't alphanumeric into an underscore
+pub fn to_identifier(name: &str) -> String {
+    let mut identifier = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            identifier.push(c.to_ascii_lowercase());
+        }
+        else if !identifier.is_empty() && !identifier.ends_with('_') {
+            identifier.push('_');
+        }
+    }
+    while identifier.ends_with('_') {
+        identifier.pop();
+    }
+    identifier
+}
+
+/// Get unique identifiers for the options of an enum or the bits of a bitfield
+///
+/// Unnamed options get `fallback` followed by their index, and duplicates get a number appended in order of appearance.
+pub fn option_identifiers(options: &[FieldName], fallback: &str) -> Vec<String> {
+    let mut identifiers = Vec::<String>::with_capacity(options.len());
+    for (i, o) in options.iter().enumerate() {
+        let mut base = o.identifier();
+        if base.is_empty() {
+            base = format!("{}_{}", fallback, i);
+        }
+        let mut identifier = base.clone();
+        let mut number = 2;
+        while identifiers.contains(&identifier) {
+            identifier = format!("{}_{}", base, number);
+            number += 1;
+        }
+        identifiers.push(identifier);
+    }
+    identifiers
+}
+
+#[derive(Clone, Default)]
 pub struct Field {
+    /// Path ID of the field (see [`path_id`](super::path_id))
+    pub id : String,
     pub name : Option<FieldName>,
     pub block_type : BlockFieldType
 }
 
-#[derive(Default, Serialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Block {
+    /// Path ID of the block (see [`path_id`](super::path_id))
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id : String,
+
     pub name : Option<String>,
+
+    /// The name wasn't in the exe and was made up (see [`block_names`](super::block_names))
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub name_synthesized : bool,
+
     pub maximum : usize,
     pub length : usize,
+
+    /// SHA-256 of the block's layout (see [`fingerprint`](super::fingerprint))
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub layout_fingerprint : String,
+
+    /// SHA-256 of the block's layout and names
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub fingerprint : String,
+
     pub fields : Vec<Field>
 }
 
+/// Whether a bool is false, for skipping false values when serializing
+pub fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+#[derive(Clone)]
 pub enum BlockFieldType {
     Unknown(u32, u32),
-    Index(String, String),
+    Index(String, &'static str),
     TagData(String, usize),
     Section(String),
     Reference(Vec<String>),
     Primitive(&'static str),
-    PrimitiveArray(&'static str, usize),
+    ArrayStart(usize),
+    ArrayEnd,
+    Custom,
     Enum(Vec<FieldName>),
     Flags(&'static str, Vec<FieldName>),
     Range(&'static str),
@@ -146,11 +238,13 @@ impl std::fmt::Display for BlockFieldType {
         match self {
             Self::Unknown(val, alt) => write!(f, "Unknown (0x{:02X}; 0x{:08X})", val, alt),
             Self::TagData(name, max_length) => write!(f, "Tag Data ({}; max length: 0x{:08X})", name, max_length),
-            Self::Index(block, block2) => write!(f, "Index ({},{})", block, block2),
+            Self::Index(block, size) => write!(f, "Index ({}; {})", block, size),
             Self::Section(section) => write!(f, "Section ({})", section),
             Self::Reference(types) => write!(f, "Reference ({} type(s))", types.len()),
             Self::Primitive(type_name) => write!(f, "Primitive ({})", type_name),
-            Self::PrimitiveArray(type_name, count) => write!(f, "PrimitiveArray ({}x{})", type_name, count),
+            Self::ArrayStart(count) => write!(f, "Array Start (x{})", count),
+            Self::ArrayEnd => write!(f, "Array End"),
+            Self::Custom => write!(f, "Custom"),
             Self::Enum(values) => write!(f, "Enum ({} values)", values.len()),
             Self::Flags(size, flags) => write!(f, "Flags ({}; {} field(s))", size, flags.len()),
             Self::Range(type_name) => write!(f, "Range ({})", type_name),
@@ -166,6 +260,10 @@ impl Serialize for Field {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer, {
         let mut map = serializer.serialize_map(None)?;
 
+        if !self.id.is_empty() {
+            map.serialize_entry("id", &self.id)?;
+        }
+
         // Serialize the name if present
         match self.name {
             Some(ref n) => {
@@ -181,9 +279,10 @@ impl Serialize for Field {
                 map.serialize_entry("size", size)?;
                 map.serialize_entry("count", count)?;
             },
-            BlockFieldType::Index(reference_a,_) => {
+            BlockFieldType::Index(reference_a, size) => {
                 map.serialize_entry("type", "index")?;
                 map.serialize_entry("reference", reference_a)?;
+                map.serialize_entry("size", size)?;
             },
             BlockFieldType::TagData(data_type, max_length) => {
                 map.serialize_entry("type", "tag_data")?;
@@ -201,10 +300,16 @@ impl Serialize for Field {
             BlockFieldType::Primitive(primitive_type) => {
                 map.serialize_entry("type", primitive_type)?;
             },
-            BlockFieldType::PrimitiveArray(primitive_type, count) => {
-                map.serialize_entry("type", primitive_type)?;
+            BlockFieldType::ArrayStart(count) => {
+                map.serialize_entry("type", "array_start")?;
                 map.serialize_entry("count", count)?;
             },
+            BlockFieldType::ArrayEnd => {
+                map.serialize_entry("type", "array_end")?;
+            },
+            BlockFieldType::Custom => {
+                map.serialize_entry("type", "custom")?;
+            },
             BlockFieldType::Enum(values) => {
                 map.serialize_entry("type", "enum")?;
                 map.serialize_entry("options", values)?;
@@ -237,3 +342,134 @@ impl Default for BlockFieldType {
         Self::Unknown(0xFFFFFFFF, 0xFFFFFFFF)
     }
 }
+
+/// Every primitive type name used by [`BlockFieldType`] along with its size in bytes
+pub const PRIMITIVE_TYPES: &[(&str, usize)] = &[
+    ("string", 32),
+    ("int8", 1),
+    ("int16", 2),
+    ("int32", 4),
+    ("float_angle", 4),
+    ("fourcc", 4),
+    ("point2d_int", 4),
+    ("rectangle", 8),
+    ("color_rgb_int", 4),
+    ("color_argb_int", 4),
+    ("float", 4),
+    ("float_clamped", 4),
+    ("point2d", 8),
+    ("point3d", 12),
+    ("vector2d", 8),
+    ("vector3d", 12),
+    ("quaternion", 16),
+    ("euler2d", 8),
+    ("euler3d", 12),
+    ("plane2d", 12),
+    ("plane3d", 16),
+    ("color_rgb", 12),
+    ("color_argb", 16),
+    ("color_hsv", 12),
+    ("color_ahsv", 16)
+];
+
+/// Get the static name of a primitive type (if it is one)
+pub fn primitive_type(name: &str) -> Option<&'static str> {
+    PRIMITIVE_TYPES.iter().find(|(n,_)| *n == name).map(|(n,_)| *n)
+}
+
+/// Get the size of a primitive type in bytes
+pub fn primitive_size(name: &str) -> usize {
+    PRIMITIVE_TYPES.iter().find(|(n,_)| *n == name).map(|(_,s)| *s).unwrap_or(0)
+}
+
+impl BlockFieldType {
+    /// Get the size this field takes up in a block element in bytes
+    ///
+    /// Arrays are not accounted for here, as the fields between the start and end of an array are repeated.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Primitive(type_name) => primitive_size(type_name),
+            Self::Range(type_name) => primitive_size(type_name) * 2,
+            Self::Padding(type_name, count) => primitive_size(type_name) * count,
+            Self::Flags(size, _) | Self::Index(_, size) => primitive_size(size),
+            Self::Enum(_) => 2,
+            Self::Reference(_) => 16,
+            Self::Block(_) => 12,
+            Self::TagData(_, _) => 20,
+            Self::Section(_) | Self::ArrayStart(_) | Self::ArrayEnd | Self::Custom | Self::Unknown(_, _) => 0
+        }
+    }
+}
+
+use super::serde::de::{Deserializer, Error};
+use super::serde_json::{Map, Value};
+
+impl<'de> Deserialize<'de> for Field {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de>, {
+        let map = Map::<String, Value>::deserialize(deserializer)?;
+
+        fn get<'a, E: Error>(map: &'a Map<String, Value>, key: &str) -> Result<&'a Value, E> {
+            map.get(key).ok_or_else(|| E::custom(format!("missing field `{}`", key)))
+        }
+        fn get_str<'a, E: Error>(map: &'a Map<String, Value>, key: &str) -> Result<&'a str, E> {
+            get(map, key)?.as_str().ok_or_else(|| E::custom(format!("`{}` is not a string", key)))
+        }
+        fn get_usize<E: Error>(map: &Map<String, Value>, key: &str) -> Result<usize, E> {
+            get(map, key)?.as_u64().map(|n| n as usize).ok_or_else(|| E::custom(format!("`{}` is not an unsigned integer", key)))
+        }
+        fn get_primitive<E: Error>(map: &Map<String, Value>, key: &str) -> Result<&'static str, E> {
+            let type_name = get_str(map, key)?;
+            primitive_type(type_name).ok_or_else(|| E::custom(format!("unknown primitive type `{}`", type_name)))
+        }
+        fn get_value<T: super::serde::de::DeserializeOwned, E: Error>(map: &Map<String, Value>, key: &str) -> Result<T, E> {
+            T::deserialize(get(map, key)?.clone()).map_err(E::custom)
+        }
+
+        // The name is flattened into the field, so it's only there if the name key is (and its ID is the field's)
+        let name = if map.contains_key("name") {
+            let mut name_map = map.clone();
+            name_map.remove("id");
+            Some(FieldName::deserialize(Value::Object(name_map)).map_err(D::Error::custom)?)
+        }
+        else {
+            None
+        };
+
+        let block_type = match get_str(&map, "type")? {
+            "padding" => match (get_primitive(&map, "size")?, get_usize(&map, "count")?) {
+                // Older dumps wrote array ends and custom fields as padding
+                ("int32", 1) => BlockFieldType::ArrayEnd,
+                ("int16", 1) => BlockFieldType::Custom,
+                (size, count) => BlockFieldType::Padding(size, count)
+            },
+            "index" => BlockFieldType::Index(get_str(&map, "reference")?.to_owned(), if map.contains_key("size") { get_primitive(&map, "size")? } else { "int16" }),
+            "tag_data" => BlockFieldType::TagData(get_str(&map, "data_type")?.to_owned(), get_usize(&map, "max_length")?),
+            "section" => BlockFieldType::Section(get_str(&map, "text")?.to_owned()),
+            "tag_reference" => BlockFieldType::Reference(get_value(&map, "allowed_groups")?),
+            "array_start" => BlockFieldType::ArrayStart(get_usize(&map, "count")?),
+            "array_end" => BlockFieldType::ArrayEnd,
+            "custom" => BlockFieldType::Custom,
+            "enum" => BlockFieldType::Enum(get_value(&map, "options")?),
+            "bitfield" => BlockFieldType::Flags(get_primitive(&map, "size")?, get_value(&map, "fields")?),
+            "block" => BlockFieldType::Block(get_value(&map, "block")?),
+            "unknown" => BlockFieldType::Unknown(get_usize(&map, "type_number")? as u32, 0xFFFFFFFF),
+            _ => {
+                let type_name = get_primitive(&map, "type")?;
+                if map.contains_key("bounds") {
+                    BlockFieldType::Range(type_name)
+                }
+                // Older dumps wrote array starts as a primitive with a count
+                else if map.contains_key("count") {
+                    BlockFieldType::ArrayStart(get_usize(&map, "count")?)
+                }
+                else {
+                    BlockFieldType::Primitive(type_name)
+                }
+            }
+        };
+
+        let id = map.get("id").and_then(Value::as_str).unwrap_or_default().to_owned();
+
+        Ok(Field { id, name, block_type })
+    }
+}
@@ -0,0 +1,107 @@
+//! Naming blocks in dumps from builds that don't store block names
+//!
+//! Builds that need `use_old_offsets` don't have block names, so they're made up from the block they're in and the name
+//! of the block field (e.g. the `triggers` block in `weapon_block` becomes `weapon_triggers_block`), or the index of the
+//! field among the block fields if it's unnamed (e.g. `weapon_block_field_0_block` for the first), so adding other
+//! fields doesn't rename it. A dump from a newer build can be given to take names from instead, matching blocks by the
+//! path of fields leading to them.
+
+use super::{Block, BlockFieldType, Definitions};
+use super::diff::field_key;
+
+/// Make up a name for a block in a block field
+pub fn synthesize_name(parent: &str, field: &str) -> String {
+    let base = parent.strip_suffix("_block").unwrap_or(parent);
+    if field.is_empty() {
+        format!("{}_block", base)
+    }
+    else {
+        format!("{}_{}_block", base, field)
+    }
+}
+
+/// Find the block field matching `key` in a block from another build
+fn find_block<'a>(reference: Option<&'a Block>, key: &(String, String)) -> Option<&'a Block> {
+    reference?.fields.iter().filter(|f| &field_key(f) == key).find_map(|f| match &f.block_type {
+        BlockFieldType::Block(b) => Some(b),
+        _ => None
+    })
+}
+
+/// Name a block and the blocks in it, returning how many were named
+///
+/// Blocks with a synthesized name are named again, so they can take the name of a matching block in `reference`.
+fn name_recursively(block: &mut Block, name: String, reference: Option<&Block>) -> usize {
+    let mut count = 0;
+    if block.name.is_none() || block.name_synthesized {
+        let name = Some(reference.and_then(|r| r.name.clone()).unwrap_or(name));
+        if block.name != name {
+            block.name = name;
+            count += 1;
+        }
+        block.name_synthesized = true;
+    }
+
+    let parent = block.name.clone().unwrap();
+    let mut block_index = 0;
+    for field in &mut block.fields {
+        let key = field_key(field);
+        if let BlockFieldType::Block(child) = &mut field.block_type {
+            let identifier = match field.name.as_ref().map(|n| n.identifier()) {
+                Some(n) if !n.is_empty() => n,
+                _ => format!("block_field_{}", block_index)
+            };
+            block_index += 1;
+            count += name_recursively(child, synthesize_name(&parent, &identifier), find_block(reference, &key));
+        }
+    }
+    count
+}
+
+/// Give every unnamed block or block with a synthesized name a name, returning how many were named
+///
+/// If `reference` is given, names are taken from matching blocks in it where possible. Either way, the blocks are marked
+/// as having synthesized names.
+pub fn synthesize_block_names(definitions: &mut Definitions, reference: Option<&Definitions>) -> usize {
+    let mut count = 0;
+    for (name, group) in &mut definitions.groups {
+        let reference_block = reference.and_then(|r| r.group_by_fourcc(group.fourcc)).map(|(_, g)| &g.block);
+        count += name_recursively(&mut group.block, synthesize_name(name, ""), reference_block);
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::serde_json::{self, Value, json};
+    use def_dumper::read_definitions;
+    use tag_file::tests::DEFINITIONS;
+
+    /// Name the blocks of the test definitions after unnaming the triggers block and editing the weapon's fields
+    fn trigger_block_name(edit: impl Fn(&mut Vec<Value>)) -> String {
+        let mut json: Value = serde_json::from_str(DEFINITIONS).unwrap();
+        let fields = json["groups"]["weapon"]["block"]["fields"].as_array_mut().unwrap();
+        fields[7].as_object_mut().unwrap().remove("name");
+        fields[7]["block"].as_object_mut().unwrap().remove("name");
+        edit(fields);
+
+        let mut definitions = read_definitions(&serde_json::to_vec(&json).unwrap()).unwrap();
+        synthesize_block_names(&mut definitions, None);
+        let block = definitions.groups["weapon"].block.fields.iter().find_map(|f| match &f.block_type {
+            BlockFieldType::Block(b) => Some(b),
+            _ => None
+        }).unwrap();
+        assert!(block.name_synthesized);
+        block.name.clone().unwrap()
+    }
+
+    #[test]
+    fn unnamed_block_fields() {
+        assert_eq!(trigger_block_name(|_| ()), "weapon_block_field_0_block");
+
+        // Adding fields that aren't blocks doesn't change the name
+        assert_eq!(trigger_block_name(|f| f.insert(0, json!({"name": "charge", "type": "float"}))), "weapon_block_field_0_block");
+        assert_eq!(trigger_block_name(|f| f.insert(0, json!({"type": "section", "text": "new"}))), "weapon_block_field_0_block");
+    }
+}
@@ -0,0 +1,148 @@
+//! Writing Markdown changelogs from a definitions diff
+
+use std::collections::BTreeMap;
+
+use super::serde_json::Value;
+use super::Definitions;
+use super::diff::{DefinitionChange, DefinitionChangeKind};
+
+/// Get names for the builds two sets of definitions were dumped from
+///
+/// This is the file version of each exe, or the start of its SHA-256 hash if they don't both have a different one.
+pub fn build_names(old: &Definitions, new: &Definitions) -> (String, String) {
+    match (&old.exe_pe_file_version, &new.exe_pe_file_version) {
+        (Some(o), Some(n)) if o != n => (o.to_owned(), n.to_owned()),
+        _ => (old.exe_sha256sum.chars().take(12).collect(), new.exe_sha256sum.chars().take(12).collect())
+    }
+}
+
+fn code(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("`{}`", s),
+        Value::Array(a) if a.is_empty() => "nothing".to_owned(),
+        Value::Array(a) => a.iter().map(code).collect::<Vec<String>>().join(", "),
+        Value::Null => "nothing".to_owned(),
+        n => format!("`{}`", n)
+    }
+}
+
+fn plain(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.to_owned(),
+        n => n.to_string()
+    }
+}
+
+/// Describe whether a number went up or down
+fn raised_or_lowered(old: &Value, new: &Value, raised: &'static str, lowered: &'static str) -> &'static str {
+    if new.as_u64() > old.as_u64() { raised } else { lowered }
+}
+
+/// Describe a change as a sentence
+pub fn describe_change(change: &DefinitionChange) -> String {
+    let (old, new) = (&change.old, &change.new);
+    let block = &change.block;
+    let field = change.field.as_deref().unwrap_or("");
+
+    // Option changes have the option identifier appended to the field
+    let (option_field, option) = match field.rfind('.') {
+        Some(dot) => (format!("{}.{}", block, &field[..dot]), &field[dot + 1..]),
+        None => (block.to_owned(), field)
+    };
+
+    match change.kind {
+        DefinitionChangeKind::GroupAdded => format!("New group `{}`", block),
+        DefinitionChangeKind::GroupRemoved => format!("Removed group `{}`", block),
+        DefinitionChangeKind::SupergroupChanged => format!("Supergroup of `{}` changed from {} to {}", block, code(old), code(new)),
+        DefinitionChangeKind::FourccChanged => format!("FourCC of `{}` changed from {} to {}", block, code(old), code(new)),
+        DefinitionChangeKind::VersionChanged => format!("Version of `{}` {} from {} to {}", block, raised_or_lowered(old, new, "raised", "lowered"), plain(old), plain(new)),
+        DefinitionChangeKind::BlockRenamed if old.is_null() => format!("Block `{}` is named {}", block, code(new)),
+        DefinitionChangeKind::BlockRenamed => format!("Block `{}` renamed from {} to {}", block, code(old), code(new)),
+        DefinitionChangeKind::MaximumChanged => format!("Maximum of `{}` {} from {} to {}", block, raised_or_lowered(old, new, "raised", "lowered"), plain(old), plain(new)),
+        DefinitionChangeKind::LengthChanged => format!("Elements of `{}` {} from {} to {} bytes", block, raised_or_lowered(old, new, "grew", "shrank"), plain(old), plain(new)),
+        DefinitionChangeKind::FieldInserted => format!("New field `{}` ({}) in `{}`", field, plain(new), block),
+        DefinitionChangeKind::FieldRemoved => format!("Removed field `{}` ({}) from `{}`", field, plain(old), block),
+        DefinitionChangeKind::FieldRenamed => format!("Field {} in `{}` renamed to `{}`", code(old), block, field),
+        DefinitionChangeKind::FieldRetyped => format!("Field `{}` in `{}` changed from {} to {}", field, block, plain(old), plain(new)),
+        DefinitionChangeKind::OptionAdded => format!("New option `{}` in `{}` (index {})", option, option_field, plain(new)),
+        DefinitionChangeKind::OptionRemoved => format!("Removed option `{}` from `{}` (index {})", option, option_field, plain(old)),
+        DefinitionChangeKind::OptionRenamed => format!("Option {} of `{}` renamed to `{}`", code(old), option_field, option),
+        DefinitionChangeKind::OptionMoved => format!("Option `{}` of `{}` moved from index {} to {}", option, option_field, plain(old), plain(new)),
+        DefinitionChangeKind::ReferenceGroupsChanged => format!("Groups allowed by `{}.{}` changed from {} to {}", block, field, code(old), code(new))
+    }
+}
+
+/// Write a Markdown changelog for one group, with a section for each block that changed
+pub fn group_changelog(old: &Definitions, new: &Definitions, group: &str, changes: &[DefinitionChange]) -> String {
+    let (old_name, new_name) = build_names(old, new);
+    let mut output = format!("# {}\n\nChanges to `{}` from {} to {}.\n", group, group, old_name, new_name);
+
+    // Keep blocks in the order they were first changed
+    let mut blocks = Vec::<(&str, Vec<&DefinitionChange>)>::new();
+    for c in changes {
+        match blocks.iter_mut().find(|(b, _)| *b == c.block) {
+            Some((_, block_changes)) => block_changes.push(c),
+            None => blocks.push((&c.block, vec![c]))
+        }
+    }
+
+    for (block, block_changes) in blocks {
+        output += &format!("\n## `{}`\n\n", block);
+        for c in block_changes {
+            output += &format!("- {}\n", describe_change(c));
+        }
+    }
+
+    output
+}
+
+/// Write a Markdown changelog for every group that changed
+///
+/// Returns a map of file names to their contents: one file for each group and an `index.md` linking to them.
+pub fn changelog(old: &Definitions, new: &Definitions, groups: &BTreeMap<String, Vec<DefinitionChange>>) -> BTreeMap<String, String> {
+    let mut files = BTreeMap::new();
+    let (old_name, new_name) = build_names(old, new);
+    let mut index = format!("# Tag format changes\n\nChanges from {} to {}.\n\n", old_name, new_name);
+
+    if groups.is_empty() {
+        index += "Nothing changed.\n";
+    }
+    for (group, changes) in groups {
+        index += &format!("- [{}]({}.md) ({} change{})\n", group, group, changes.len(), if changes.len() == 1 { "" } else { "s" });
+        files.insert(format!("{}.md", group), group_changelog(old, new, group, changes));
+    }
+
+    files.insert("index.md".to_owned(), index);
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::diff::diff_definitions;
+    use super::super::diff::tests::changed_definitions;
+    use tag_file::tests::definitions;
+
+    #[test]
+    fn changelog_of_fixture_pair() {
+        let (old, new) = (definitions(), changed_definitions());
+        let files = changelog(&old, &new, &diff_definitions(&old, &new));
+        assert_eq!(files.keys().collect::<Vec<_>>(), vec!["index.md", "weapon.md"]);
+        assert!(files["index.md"].contains("- [weapon](weapon.md) (7 changes)\n"));
+
+        let weapon = &files["weapon.md"];
+        assert!(weapon.starts_with("# weapon\n\nChanges to `weapon` from "));
+        assert!(weapon.contains("\n## `weapon`\n\n- FourCC of `weapon` changed from `weip` to `gun!`\n- Version of `weapon` raised from 2 to 3\n"));
+        assert!(weapon.contains("\n## `weapon.triggers`\n\n- Maximum of `weapon.triggers` raised from 2 to 4\n"));
+        assert!(weapon.contains("- New option `shotgun` in `weapon.type` (index 2)\n"));
+        assert!(weapon.contains("- Field `rate` in `weapon` renamed to `fire_rate`\n"));
+    }
+
+    #[test]
+    fn nothing_changed() {
+        let definitions = definitions();
+        let files = changelog(&definitions, &definitions, &diff_definitions(&definitions, &definitions));
+        assert_eq!(files.len(), 1);
+        assert!(files["index.md"].ends_with("Nothing changed.\n"));
+    }
+}
@@ -0,0 +1,435 @@
+//! Comparing two sets of definitions
+
+use std::collections::BTreeMap;
+
+use super::serde_json::Value;
+use super::{Block, BlockFieldType, Definitions, Field, FieldName, option_identifiers};
+use tag_file::json::fourcc_to_string;
+
+/// What changed in the definitions
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DefinitionChangeKind {
+    GroupAdded,
+    GroupRemoved,
+    SupergroupChanged,
+    FourccChanged,
+    VersionChanged,
+    BlockRenamed,
+    MaximumChanged,
+    LengthChanged,
+    FieldInserted,
+    FieldRemoved,
+    FieldRenamed,
+    FieldRetyped,
+    OptionAdded,
+    OptionRemoved,
+    OptionRenamed,
+    OptionMoved,
+    ReferenceGroupsChanged
+}
+
+/// A difference between two sets of definitions
+#[derive(serde::Serialize, Clone, PartialEq, Debug)]
+pub struct DefinitionChange {
+    /// Path of the block, starting with the group name followed by the identifiers of the block fields leading to it
+    /// (e.g. `weapon.triggers`)
+    pub block: String,
+
+    /// Identifier of the field in the block, if the change is to a field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+
+    pub kind: DefinitionChangeKind,
+
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub old: Value,
+
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub new: Value
+}
+
+impl DefinitionChange {
+    /// Get the full path of what changed
+    pub fn path(&self) -> String {
+        match &self.field {
+            Some(field) => format!("{}.{}", self.block, field),
+            None => self.block.clone()
+        }
+    }
+}
+
+/// Show a value without quoting strings
+fn show(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "none".to_owned(),
+        n => n.to_string()
+    }
+}
+
+impl std::fmt::Display for DefinitionChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (old, new) = (show(&self.old), show(&self.new));
+        write!(f, "{}: ", self.path())?;
+        match self.kind {
+            DefinitionChangeKind::GroupAdded => write!(f, "group added"),
+            DefinitionChangeKind::GroupRemoved => write!(f, "group removed"),
+            DefinitionChangeKind::SupergroupChanged => write!(f, "supergroup changed from {} to {}", old, new),
+            DefinitionChangeKind::FourccChanged => write!(f, "FourCC changed from {} to {}", old, new),
+            DefinitionChangeKind::VersionChanged => write!(f, "version changed from {} to {}", old, new),
+            DefinitionChangeKind::BlockRenamed => write!(f, "block renamed from {} to {}", old, new),
+            DefinitionChangeKind::MaximumChanged => write!(f, "maximum changed from {} to {}", old, new),
+            DefinitionChangeKind::LengthChanged => write!(f, "length changed from {} to {}", old, new),
+            DefinitionChangeKind::FieldInserted => write!(f, "inserted ({})", new),
+            DefinitionChangeKind::FieldRemoved => write!(f, "removed ({})", old),
+            DefinitionChangeKind::FieldRenamed => write!(f, "renamed from {}", old),
+            DefinitionChangeKind::FieldRetyped => write!(f, "type changed from {} to {}", old, new),
+            DefinitionChangeKind::OptionAdded => write!(f, "option added at index {}", new),
+            DefinitionChangeKind::OptionRemoved => write!(f, "option removed from index {}", old),
+            DefinitionChangeKind::OptionRenamed => write!(f, "option renamed from {}", old),
+            DefinitionChangeKind::OptionMoved => write!(f, "option moved from index {} to {}", old, new),
+            DefinitionChangeKind::ReferenceGroupsChanged => write!(f, "allowed groups changed from {} to {}", old, new)
+        }
+    }
+}
+
+/// Get a short description of the type of a field, such as `float` or `index (int16)`
+pub fn type_name(block_type: &BlockFieldType) -> String {
+    match block_type {
+        BlockFieldType::Unknown(a, _) => format!("unknown (0x{:02X})", a),
+        BlockFieldType::Index(_, size) => format!("index ({})", size),
+        BlockFieldType::TagData(_, _) => "tag_data".to_owned(),
+        BlockFieldType::Section(_) => "section".to_owned(),
+        BlockFieldType::Reference(_) => "tag_reference".to_owned(),
+        BlockFieldType::Primitive(type_name) => type_name.to_string(),
+        BlockFieldType::ArrayStart(count) => format!("array_start (x{})", count),
+        BlockFieldType::ArrayEnd => "array_end".to_owned(),
+        BlockFieldType::Custom => "custom".to_owned(),
+        BlockFieldType::Enum(_) => "enum".to_owned(),
+        BlockFieldType::Flags(size, _) => format!("bitfield ({})", size),
+        BlockFieldType::Range(type_name) => format!("{} bounds", type_name),
+        BlockFieldType::Padding(size, count) => format!("padding ({} bytes)", super::primitive_size(size) * count),
+        BlockFieldType::Block(_) => "block".to_owned()
+    }
+}
+
+/// Get the identifier of a field, falling back to its type if it's unnamed
+fn field_identifier(field: &Field) -> String {
+    match field.name.as_ref().map(FieldName::identifier) {
+        Some(n) if !n.is_empty() => n,
+        _ => type_name(&field.block_type).split(' ').next().unwrap().to_owned()
+    }
+}
+
+/// Get what a field is matched up with other fields by
+pub fn field_key(field: &Field) -> (String, String) {
+    match field.name.as_ref().map(FieldName::identifier) {
+        Some(n) if !n.is_empty() => (n, String::new()),
+        _ => (String::new(), type_name(&field.block_type))
+    }
+}
+
+/// A step in aligning two lists
+#[derive(Clone, PartialEq, Debug)]
+pub enum Alignment {
+    Matched(usize, usize),
+    Unmatched(Vec<usize>, Vec<usize>)
+}
+
+/// Most entries in the table of common subsequence lengths [`align`] builds before it matches by index instead
+const MAX_ALIGN_CELLS: usize = 1 << 22;
+
+/// Collects an alignment, grouping up what's unmatched between each match
+#[derive(Default)]
+struct AlignmentBuilder {
+    alignment: Vec<Alignment>,
+    unmatched_old: Vec<usize>,
+    unmatched_new: Vec<usize>
+}
+
+impl AlignmentBuilder {
+    fn matched(&mut self, i: usize, j: usize) {
+        self.end_unmatched();
+        self.alignment.push(Alignment::Matched(i, j));
+    }
+
+    fn end_unmatched(&mut self) {
+        if !self.unmatched_old.is_empty() || !self.unmatched_new.is_empty() {
+            self.alignment.push(Alignment::Unmatched(std::mem::take(&mut self.unmatched_old), std::mem::take(&mut self.unmatched_new)));
+        }
+    }
+
+    fn finish(mut self) -> Vec<Alignment> {
+        self.end_unmatched();
+        self.alignment
+    }
+}
+
+/// Align two lists with their longest common subsequence, grouping up what's unmatched between each match
+///
+/// Anything the same at the start and end is matched first. If what's left in between is too long to find the longest
+/// common subsequence of, it's matched by index instead.
+pub fn align<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Alignment> {
+    let prefix = old.iter().zip(new.iter()).take_while(|(o, n)| o == n).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(o, n)| o == n).count();
+    let (old_middle, new_middle) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut builder = AlignmentBuilder::default();
+    for i in 0..prefix {
+        builder.matched(i, i);
+    }
+
+    if (old_middle.len() + 1).saturating_mul(new_middle.len() + 1) > MAX_ALIGN_CELLS {
+        for k in 0..old_middle.len().max(new_middle.len()) {
+            match (old_middle.get(k), new_middle.get(k)) {
+                (Some(o), Some(n)) if o == n => builder.matched(prefix + k, prefix + k),
+                (o, n) => {
+                    if o.is_some() {
+                        builder.unmatched_old.push(prefix + k);
+                    }
+                    if n.is_some() {
+                        builder.unmatched_new.push(prefix + k);
+                    }
+                }
+            }
+        }
+    }
+    else {
+        let (old, new) = (old_middle, new_middle);
+
+        // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+        let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                lcs[i][j] = if old[i] == new[j] { lcs[i+1][j+1] + 1 } else { lcs[i+1][j].max(lcs[i][j+1]) };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < old.len() || j < new.len() {
+            if i < old.len() && j < new.len() && old[i] == new[j] {
+                builder.matched(prefix + i, prefix + j);
+                i += 1;
+                j += 1;
+            }
+            else if j < new.len() && (i == old.len() || lcs[i][j+1] >= lcs[i+1][j]) {
+                builder.unmatched_new.push(prefix + j);
+                j += 1;
+            }
+            else {
+                builder.unmatched_old.push(prefix + i);
+                i += 1;
+            }
+        }
+    }
+
+    for k in 0..suffix {
+        builder.matched(old.len() - suffix + k, new.len() - suffix + k);
+    }
+    builder.finish()
+}
+
+fn change(block: &str, field: Option<&str>, kind: DefinitionChangeKind, old: Value, new: Value) -> DefinitionChange {
+    DefinitionChange { block: block.to_owned(), field: field.map(|f| f.to_owned()), kind, old, new }
+}
+
+fn optional(value: &Option<String>) -> Value {
+    value.clone().map_or(Value::Null, Value::from)
+}
+
+/// Compare the options of an enum or the bits of a bitfield
+///
+/// Options are matched by name, as their index is what's stored in tags. Options that aren't matched by name but take
+/// the place of one another are treated as renamed. Changes are given with the option identifier appended to the field.
+fn diff_options(old: &[FieldName], new: &[FieldName], fallback: &str, block: &str, field: &str, changes: &mut Vec<DefinitionChange>) {
+    let old_identifiers = option_identifiers(old, fallback);
+    let new_identifiers = option_identifiers(new, fallback);
+    let path = |identifier: &str| format!("{}.{}", field, identifier);
+
+    for (n, identifier) in new_identifiers.iter().enumerate() {
+        match old_identifiers.iter().position(|o| o == identifier) {
+            Some(o) if o != n => changes.push(change(block, Some(&path(identifier)), DefinitionChangeKind::OptionMoved, Value::from(o), Value::from(n))),
+            Some(_) => (),
+            None => match old_identifiers.get(n) {
+                Some(o) if !new_identifiers.contains(o) => changes.push(change(block, Some(&path(identifier)), DefinitionChangeKind::OptionRenamed, Value::from(o.as_str()), Value::from(identifier.as_str()))),
+                _ => changes.push(change(block, Some(&path(identifier)), DefinitionChangeKind::OptionAdded, Value::Null, Value::from(n)))
+            }
+        }
+    }
+
+    for (o, identifier) in old_identifiers.iter().enumerate() {
+        let renamed = o < new_identifiers.len() && !old_identifiers.contains(&new_identifiers[o]);
+        if !new_identifiers.contains(identifier) && !renamed {
+            changes.push(change(block, Some(&path(identifier)), DefinitionChangeKind::OptionRemoved, Value::from(o), Value::Null));
+        }
+    }
+}
+
+/// Compare two fields that were matched up with each other
+fn diff_fields(old: &Field, new: &Field, block: &str, changes: &mut Vec<DefinitionChange>) {
+    let identifier = field_identifier(new);
+    let (old_type, new_type) = (type_name(&old.block_type), type_name(&new.block_type));
+    if old_type != new_type {
+        changes.push(change(block, Some(&identifier), DefinitionChangeKind::FieldRetyped, Value::from(old_type), Value::from(new_type)));
+        return
+    }
+
+    match (&old.block_type, &new.block_type) {
+        (BlockFieldType::Enum(old_options), BlockFieldType::Enum(new_options)) => diff_options(old_options, new_options, "option", block, &identifier, changes),
+        (BlockFieldType::Flags(_, old_bits), BlockFieldType::Flags(_, new_bits)) => diff_options(old_bits, new_bits, "bit", block, &identifier, changes),
+        (BlockFieldType::Reference(old_groups), BlockFieldType::Reference(new_groups)) if old_groups != new_groups => {
+            changes.push(change(block, Some(&identifier), DefinitionChangeKind::ReferenceGroupsChanged, Value::from(old_groups.clone()), Value::from(new_groups.clone())));
+        },
+        (BlockFieldType::Block(old_block), BlockFieldType::Block(new_block)) => diff_blocks(old_block, new_block, &format!("{}.{}", block, identifier), changes),
+        _ => ()
+    }
+}
+
+/// Compare two blocks, where `path` is the path of the block
+pub fn diff_blocks(old: &Block, new: &Block, path: &str, changes: &mut Vec<DefinitionChange>) {
+    // Made up names don't count as renames
+    if old.name != new.name && !old.name_synthesized && !new.name_synthesized {
+        changes.push(change(path, None, DefinitionChangeKind::BlockRenamed, optional(&old.name), optional(&new.name)));
+    }
+    if old.maximum != new.maximum {
+        changes.push(change(path, None, DefinitionChangeKind::MaximumChanged, Value::from(old.maximum), Value::from(new.maximum)));
+    }
+    if old.length != new.length {
+        changes.push(change(path, None, DefinitionChangeKind::LengthChanged, Value::from(old.length), Value::from(new.length)));
+    }
+
+    let old_keys: Vec<(String, String)> = old.fields.iter().map(field_key).collect();
+    let new_keys: Vec<(String, String)> = new.fields.iter().map(field_key).collect();
+    for a in align(&old_keys, &new_keys) {
+        match a {
+            Alignment::Matched(o, n) => diff_fields(&old.fields[o], &new.fields[n], path, changes),
+
+            // Fields of the same type taking the place of one another are treated as renamed
+            Alignment::Unmatched(removed, inserted) => for k in 0..removed.len().max(inserted.len()) {
+                let old_field = removed.get(k).map(|&o| &old.fields[o]);
+                let new_field = inserted.get(k).map(|&n| &new.fields[n]);
+                match (old_field, new_field) {
+                    (Some(o), Some(n)) if type_name(&o.block_type) == type_name(&n.block_type) => {
+                        changes.push(change(path, Some(&field_identifier(n)), DefinitionChangeKind::FieldRenamed, Value::from(field_identifier(o)), Value::Null));
+                        diff_fields(o, n, path, changes);
+                    },
+                    _ => {
+                        if let Some(o) = old_field {
+                            changes.push(change(path, Some(&field_identifier(o)), DefinitionChangeKind::FieldRemoved, Value::from(type_name(&o.block_type)), Value::Null));
+                        }
+                        if let Some(n) = new_field {
+                            changes.push(change(path, Some(&field_identifier(n)), DefinitionChangeKind::FieldInserted, Value::Null, Value::from(type_name(&n.block_type))));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Compare two sets of definitions, returning the changes to each group
+///
+/// Groups without any changes are left out.
+pub fn diff_definitions(old: &Definitions, new: &Definitions) -> BTreeMap<String, Vec<DefinitionChange>> {
+    let mut groups = BTreeMap::new();
+
+    for name in old.groups.keys().chain(new.groups.keys().filter(|g| !old.groups.contains_key(*g))) {
+        let mut changes = Vec::new();
+        match (old.groups.get(name), new.groups.get(name)) {
+            (Some(_), None) => changes.push(change(name, None, DefinitionChangeKind::GroupRemoved, Value::Null, Value::Null)),
+            (None, Some(_)) => changes.push(change(name, None, DefinitionChangeKind::GroupAdded, Value::Null, Value::Null)),
+            (Some(old_group), Some(new_group)) => {
+                if old_group.supergroup != new_group.supergroup {
+                    changes.push(change(name, None, DefinitionChangeKind::SupergroupChanged, optional(&old_group.supergroup), optional(&new_group.supergroup)));
+                }
+                if old_group.fourcc != new_group.fourcc {
+                    changes.push(change(name, None, DefinitionChangeKind::FourccChanged, Value::from(fourcc_to_string(old_group.fourcc)), Value::from(fourcc_to_string(new_group.fourcc))));
+                }
+                if old_group.version != new_group.version {
+                    changes.push(change(name, None, DefinitionChangeKind::VersionChanged, Value::from(old_group.version), Value::from(new_group.version)));
+                }
+                diff_blocks(&old_group.block, &new_group.block, name, &mut changes);
+            },
+            (None, None) => unreachable!()
+        }
+
+        if !changes.is_empty() {
+            groups.insert(name.to_owned(), changes);
+        }
+    }
+
+    groups
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use super::super::serde_json::{self, json};
+    use def_dumper::read_definitions;
+    use tag_file::tests::{DEFINITIONS, definitions};
+
+    /// The test definitions with the weapon group changed
+    pub fn changed_definitions() -> Definitions {
+        let mut json: Value = serde_json::from_str(DEFINITIONS).unwrap();
+        let weapon = &mut json["groups"]["weapon"];
+        weapon["fourcc"] = json!(0x67756E21);
+        weapon["version"] = json!(3);
+        let fields = weapon["block"]["fields"].as_array_mut().unwrap();
+        fields[2]["options"].as_array_mut().unwrap().push(json!({"name": "shotgun"}));
+        fields[4]["name"] = json!("fire rate");
+        fields[6]["allowed_groups"] = json!(["model", "weapon"]);
+        fields[7]["block"]["maximum"] = json!(4);
+        fields[11]["type"] = json!("int32");
+        read_definitions(&serde_json::to_vec(&json).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn diff_fixture_pair() {
+        let changes = diff_definitions(&definitions(), &changed_definitions());
+        assert_eq!(changes.keys().collect::<Vec<_>>(), vec!["weapon"]);
+
+        let lines: Vec<String> = changes["weapon"].iter().map(|c| c.to_string()).collect();
+        assert_eq!(lines, vec![
+            "weapon: FourCC changed from weip to gun!",
+            "weapon: version changed from 2 to 3",
+            "weapon.type.shotgun: option added at index 2",
+            "weapon.fire_rate: renamed from rate",
+            "weapon.model: allowed groups changed from [\"model\"] to [\"model\",\"weapon\"]",
+            "weapon.triggers: maximum changed from 2 to 4",
+            "weapon.y: type changed from int16 to int32"
+        ]);
+    }
+
+    #[test]
+    fn align_lists() {
+        use super::Alignment::{Matched, Unmatched};
+
+        assert_eq!(align(&[1, 2, 3], &[1, 2, 3]), vec![Matched(0, 0), Matched(1, 1), Matched(2, 2)]);
+        assert_eq!(align(&[1, 2, 3], &[1, 4, 2, 3]), vec![Matched(0, 0), Unmatched(vec![], vec![1]), Matched(1, 2), Matched(2, 3)]);
+        assert_eq!(align(&[1, 2, 3, 4], &[1, 3, 5]), vec![Matched(0, 0), Unmatched(vec![1], vec![]), Matched(2, 1), Unmatched(vec![3], vec![2])]);
+        assert_eq!(align(&[5, 1, 2], &[1, 2, 6]), vec![Unmatched(vec![0], vec![]), Matched(1, 0), Matched(2, 1), Unmatched(vec![], vec![2])]);
+        assert_eq!(align::<i32>(&[], &[]), vec![]);
+    }
+
+    #[test]
+    fn align_long_lists() {
+        // The same lists but with an element inserted in the middle are still aligned exactly
+        let old: Vec<usize> = (0..100000).collect();
+        let mut new = old.clone();
+        new.insert(50000, 7);
+        let alignment = align(&old, &new);
+        assert_eq!(alignment.len(), 100001);
+        assert_eq!(alignment[50000], Alignment::Unmatched(vec![], vec![50000]));
+        assert_eq!(alignment[50001], Alignment::Matched(50000, 50001));
+
+        // Lists that are too different are matched by index
+        let old: Vec<usize> = (0..5000).collect();
+        let new: Vec<usize> = (0..5000).map(|n| if n % 2 == 0 { n } else { n + 10000 }).chain(std::iter::once(1)).collect();
+        let alignment = align(&old, &new);
+        assert_eq!(alignment.len(), 5000);
+        assert_eq!(alignment[0], Alignment::Matched(0, 0));
+        assert_eq!(alignment[1], Alignment::Unmatched(vec![1], vec![1]));
+        assert_eq!(alignment[4999], Alignment::Unmatched(vec![4999], vec![4999, 5000]));
+    }
+}
@@ -0,0 +1,455 @@
+//! Writing a static HTML site documenting each group
+//!
+//! Every group gets a page with a table of each of its blocks, with fields in the order they're in tag files (arrays
+//! flattened into their fields) and section headers. Anchors are path IDs (see [`path_id`](super::path_id)), so any
+//! field can be linked to as `<group>.html#<path ID>`. `search.js` has an index of every group, block, field and option.
+
+use std::collections::BTreeMap;
+
+use super::serde_json::Value;
+use super::schema::object;
+use super::{Block, BlockFieldType, Definitions, Field, FieldName, option_identifiers};
+use super::diff::type_name;
+use codegen::build_name;
+use tag_file::StructLayout;
+use tag_file::json::fourcc_to_string;
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 0; color: #222; }
+nav { background: #333; color: #eee; padding: 0.5em 1em; position: relative; }
+nav a { color: #fff; margin-right: 1em; }
+#search { width: 24em; }
+#search-results { position: absolute; background: #fff; color: #222; list-style: none; margin: 0; padding: 0; z-index: 1; box-shadow: 0 2px 6px #0006; }
+#search-results li { padding: 0.2em 0.5em; }
+#search-results span { color: #777; font-size: 0.85em; }
+main { padding: 0 1em 1em; }
+footer { color: #777; font-size: 0.85em; padding: 1em; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 2em; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.5em; text-align: left; vertical-align: top; }
+tr.section th { background: #e8eef6; }
+tr.padding td { color: #999; }
+tr.array td:nth-child(2) { font-weight: bold; }
+td.offset { font-family: monospace; white-space: nowrap; }
+.badge { font-size: 0.75em; background: #ddd; border-radius: 3px; padding: 0 0.3em; margin-left: 0.3em; }
+.deprecated { text-decoration: line-through; }
+:target { background: #fff3b0; }
+";
+
+const SEARCH: &str = r#"
+document.addEventListener("DOMContentLoaded", () => {
+    const input = document.getElementById("search");
+    const results = document.getElementById("search-results");
+    input.addEventListener("input", () => {
+        const query = input.value.trim().toLowerCase();
+        results.replaceChildren();
+        if (query.length === 0) {
+            return;
+        }
+        let count = 0;
+        for (const entry of SEARCH_INDEX) {
+            if (!entry.name.toLowerCase().includes(query) && !entry.id.includes(query)) {
+                continue;
+            }
+            const item = document.createElement("li");
+            const link = document.createElement("a");
+            link.href = entry.url;
+            link.textContent = entry.name;
+            const detail = document.createElement("span");
+            detail.textContent = " " + entry.kind + " " + entry.id;
+            item.append(link, detail);
+            results.append(item);
+            if (++count === 50) {
+                break;
+            }
+        }
+    });
+});
+"#;
+
+/// Escape text for HTML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Link to a group's page, if it has one
+fn group_link(definitions: &Definitions, group: &str) -> String {
+    if definitions.groups.contains_key(group) {
+        format!("<a href=\"{}.html\">{}</a>", escape(group), escape(group))
+    }
+    else {
+        escape(group)
+    }
+}
+
+fn page(definitions: &Definitions, title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<link rel=\"stylesheet\" href=\"style.css\">\n\
+         <script src=\"search.js\" defer></script>\n</head>\n<body>\n<nav><a href=\"index.html\">All groups</a>\
+         <input type=\"search\" id=\"search\" placeholder=\"Search groups, blocks and fields\" autocomplete=\"off\">\
+         <ul id=\"search-results\"></ul></nav>\n<main>\n{}</main>\n<footer>Generated by gorilla from the definitions of guerilla {}</footer>\n</body>\n</html>\n",
+        escape(title), body, escape(&build_name(definitions))
+    )
+}
+
+/// Every tag reference in the definitions: the group it's in, its path ID and the groups it allows
+struct Reference<'a> {
+    group: &'a str,
+    id: &'a str,
+    allowed: &'a [String]
+}
+
+fn collect_references<'a>(group: &'a str, block: &'a Block, references: &mut Vec<Reference<'a>>) {
+    for f in &block.fields {
+        match &f.block_type {
+            BlockFieldType::Reference(allowed) => references.push(Reference { group, id: &f.id, allowed }),
+            BlockFieldType::Block(b) => collect_references(group, b, references),
+            _ => ()
+        }
+    }
+}
+
+/// Get a group's supergroups, starting with its own supergroup
+fn supergroups<'a>(definitions: &'a Definitions, group: &'a str) -> Vec<&'a str> {
+    let mut supergroups = Vec::new();
+    let mut current = group;
+    while let Some(s) = definitions.groups.get(current).and_then(|g| g.supergroup.as_deref()) {
+        if supergroups.contains(&s) || s == group {
+            break
+        }
+        supergroups.push(s);
+        current = s;
+    }
+    supergroups
+}
+
+fn subgroups<'a>(definitions: &'a Definitions, group: &str) -> Vec<&'a str> {
+    definitions.groups.iter().filter(|(_, g)| g.supergroup.as_deref() == Some(group)).map(|(n, _)| n.as_str()).collect()
+}
+
+struct Writer<'a> {
+    definitions: &'a Definitions,
+    group: &'a str,
+
+    /// Name and path ID of every block in the group
+    blocks: Vec<(&'a str, &'a str)>,
+
+    search: Vec<Value>
+}
+
+impl<'a> Writer<'a> {
+    fn index(&mut self, kind: &str, name: &str, id: &str) {
+        self.search.push(object(vec![
+            ("name", Value::from(name)),
+            ("id", Value::from(id)),
+            ("kind", Value::from(kind)),
+            ("url", Value::from(format!("{}.html#{}", self.group, id)))
+        ]));
+    }
+
+    fn block_link(&self, id: &str, name: &str) -> String {
+        format!("<a href=\"#{}\">{}</a>", escape(id), escape(name))
+    }
+
+    /// Describe the type of a field
+    fn type_cell(&self, field: &Field) -> String {
+        match &field.block_type {
+            BlockFieldType::Reference(allowed) if allowed.is_empty() => "tag reference (no groups allowed)".to_owned(),
+            BlockFieldType::Reference(allowed) if allowed.len() >= self.definitions.groups.len() => "tag reference (any group)".to_owned(),
+            BlockFieldType::Reference(allowed) => format!("tag reference to {}", allowed.iter().map(|g| group_link(self.definitions, g)).collect::<Vec<String>>().join(", ")),
+            BlockFieldType::Block(b) => format!("block of {} (maximum {})", self.block_link(&field.id, b.name.as_deref().unwrap_or("elements")), b.maximum),
+            BlockFieldType::Index(block, size) => match self.blocks.iter().find(|(n, _)| n == block) {
+                Some((name, id)) => format!("index ({}) of {}", size, self.block_link(id, name)),
+                None => format!("index ({}) of {}", size, escape(block))
+            },
+            BlockFieldType::TagData(_, max_length) => format!("tag data (maximum {} bytes)", max_length),
+            BlockFieldType::ArrayStart(count) => format!("array of {}", count),
+            n => escape(&type_name(n))
+        }
+    }
+
+    /// Describe a field, including its options if it's an enum or bitfield
+    fn description_cell(&mut self, field: &Field) -> String {
+        let mut cell = String::new();
+        if let Some(name) = &field.name {
+            if let Some(d) = &name.description {
+                cell += &escape(d);
+            }
+            if let Some(n) = &name.notes {
+                cell += &format!("<p>{}</p>", escape(n));
+            }
+        }
+        let (options, fallback, bit): (&[FieldName], &str, bool) = match &field.block_type {
+            BlockFieldType::Enum(options) => (options, "option", false),
+            BlockFieldType::Flags(_, bits) => (bits, "bit", true),
+            _ => return cell
+        };
+        cell += "<ul>";
+        for (i, (o, identifier)) in options.iter().zip(option_identifiers(options, fallback)).enumerate() {
+            let id = format!("{}:{}", field.id, identifier);
+            let label = if bit { format!("bit {}", i) } else { i.to_string() };
+            cell += &format!("<li id=\"{}\"><code>{}</code> {}</li>", escape(&id), label, escape(&o.name));
+            self.index(if bit { "bit" } else { "option" }, &o.name, &id);
+        }
+        cell + "</ul>"
+    }
+
+    /// Write table rows for fields up to the end of the array (if in one), starting at `offset`
+    fn write_rows(&mut self, output: &mut String, fields: &mut std::slice::Iter<'a, Field>, mut offset: usize, prefix: &str) -> usize {
+        while let Some(field) = fields.next() {
+            let name = field.name.as_ref();
+            let field_name = name.map(|n| n.name.as_str()).unwrap_or_default();
+            match &field.block_type {
+                BlockFieldType::ArrayEnd => break,
+                BlockFieldType::Section(text) => {
+                    let heading = match name.map(|n| n.name.as_str()).filter(|n| !n.is_empty()) {
+                        Some(n) if !text.is_empty() => format!("{} <small>{}</small>", escape(n), escape(text)),
+                        Some(n) => escape(n),
+                        None => escape(text)
+                    };
+                    *output += &format!("<tr class=\"section\"><th colspan=\"5\" id=\"{}\">{}</th></tr>\n", escape(&field.id), heading);
+                    continue
+                },
+                _ => ()
+            }
+
+            let mut badges = String::new();
+            if let Some(n) = name {
+                if n.read_only {
+                    badges += "<span class=\"badge\">read-only</span>";
+                }
+                if n.hidden {
+                    badges += "<span class=\"badge\">hidden</span>";
+                }
+                if n.deprecated {
+                    badges += "<span class=\"badge\">deprecated</span>";
+                }
+            }
+            let class = match &field.block_type {
+                BlockFieldType::Padding(_, _) => " class=\"padding\"",
+                BlockFieldType::ArrayStart(_) => " class=\"array\"",
+                _ => ""
+            };
+            let name_class = if name.map(|n| n.deprecated).unwrap_or(false) { " class=\"deprecated\"" } else { "" };
+            let label = format!("{}{}", prefix, if field_name.is_empty() { type_name(&field.block_type) } else { field_name.to_owned() });
+            let unit = name.and_then(|n| n.unit.as_deref()).map(escape).unwrap_or_default();
+            let type_cell = self.type_cell(field);
+            let description = self.description_cell(field);
+
+            // Blocks have the same ID as their field, and the ID goes to the block's table
+            let id = match &field.block_type {
+                BlockFieldType::Block(_) => String::new(),
+                _ => format!(" id=\"{}\"", escape(&field.id))
+            };
+            *output += &format!(
+                "<tr{}{}><td class=\"offset\">0x{:X}</td><td><a href=\"#{}\"{}>{}</a>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                class, id, offset, escape(&field.id), name_class, escape(&label), badges, type_cell, unit, description
+            );
+            if !field_name.is_empty() {
+                self.index(&type_name(&field.block_type), &label, &field.id);
+            }
+
+            match &field.block_type {
+                BlockFieldType::ArrayStart(count) => {
+                    // The rows are for the first element; each element after it follows right after
+                    let element_size = StructLayout::of_fields(fields.as_slice()).size;
+                    self.write_rows(output, fields, offset, &format!("{}[].", label));
+                    offset += element_size * count;
+                },
+                n => offset += n.size()
+            }
+        }
+        offset
+    }
+
+    /// Write a block's table, followed by the tables of the blocks in it
+    fn write_block(&mut self, output: &mut String, block: &'a Block, id: &str) {
+        let name = block.name.as_deref().unwrap_or(id);
+        *output += &format!(
+            "<h2 id=\"{}\">{}</h2>\n<p>Path <code>{}</code>; {} bytes per element, up to {} element{}.</p>\n",
+            escape(id), escape(name), escape(id), block.length, block.maximum, if block.maximum == 1 { "" } else { "s" }
+        );
+        self.index("block", name, id);
+        *output += "<table>\n<tr><th>Offset</th><th>Name</th><th>Type</th><th>Unit</th><th>Description</th></tr>\n";
+        self.write_rows(output, &mut block.fields.iter(), 0, "");
+        *output += "</table>\n";
+
+        for f in &block.fields {
+            if let BlockFieldType::Block(b) = &f.block_type {
+                self.write_block(output, b, &f.id);
+            }
+        }
+    }
+}
+
+fn collect_blocks<'a>(block: &'a Block, id: &'a str, blocks: &mut Vec<(&'a str, &'a str)>) {
+    blocks.push((block.name.as_deref().unwrap_or(id), id));
+    for f in &block.fields {
+        if let BlockFieldType::Block(b) = &f.block_type {
+            collect_blocks(b, &f.id, blocks);
+        }
+    }
+}
+
+/// Write a list of groups and the groups under them
+fn write_tree(definitions: &Definitions, output: &mut String, groups: &[&str]) {
+    *output += "<ul>\n";
+    for g in groups {
+        *output += &format!("<li>{} <code>{}</code>", group_link(definitions, g), escape(&fourcc_to_string(definitions.groups[*g].fourcc)));
+        let children = subgroups(definitions, g);
+        if !children.is_empty() {
+            write_tree(definitions, output, &children);
+        }
+        *output += "</li>\n";
+    }
+    *output += "</ul>\n";
+}
+
+/// Write a group's page
+fn group_page(definitions: &Definitions, name: &str, references: &[Reference], search: &mut Vec<Value>) -> String {
+    let group = &definitions.groups[name];
+    let mut writer = Writer { definitions, group: name, blocks: Vec::new(), search: Vec::new() };
+    collect_blocks(&group.block, name, &mut writer.blocks);
+    writer.search.push(object(vec![
+        ("name", Value::from(name)),
+        ("id", Value::from(name)),
+        ("kind", Value::from("group")),
+        ("url", Value::from(format!("{}.html", name)))
+    ]));
+
+    let mut body = format!("<h1>{}</h1>\n<dl>\n", escape(name));
+    body += &format!("<dt>FourCC</dt><dd><code>{}</code> (0x{:08X})</dd>\n", escape(&fourcc_to_string(group.fourcc)), group.fourcc);
+    body += &format!("<dt>Version</dt><dd>{}</dd>\n", group.version);
+    let parents = supergroups(definitions, name);
+    if !parents.is_empty() {
+        body += &format!("<dt>Supergroups</dt><dd>{}</dd>\n", parents.iter().map(|g| group_link(definitions, g)).collect::<Vec<String>>().join(" → "));
+    }
+    let children = subgroups(definitions, name);
+    if !children.is_empty() {
+        body += &format!("<dt>Subgroups</dt><dd>{}</dd>\n", children.iter().map(|g| group_link(definitions, g)).collect::<Vec<String>>().join(", "));
+    }
+    body += "</dl>\n";
+
+    // Groups this group's references allow, along with which references allow them
+    let mut referenced = BTreeMap::<&str, Vec<&str>>::new();
+    for r in references.iter().filter(|r| r.group == name && r.allowed.len() < definitions.groups.len()) {
+        for g in r.allowed {
+            referenced.entry(g.as_str()).or_default().push(r.id);
+        }
+    }
+    body += "<h2>References</h2>\n";
+    if referenced.is_empty() {
+        body += "<p>Nothing in this group references a specific group.</p>\n";
+    }
+    else {
+        body += "<ul>\n";
+        for (g, ids) in referenced {
+            let links: Vec<String> = ids.iter().map(|id| writer.block_link(id, id)).collect();
+            body += &format!("<li>{} from {}</li>\n", group_link(definitions, g), links.join(", "));
+        }
+        body += "</ul>\n";
+    }
+
+    // References that allow this group, directly or by allowing one of its supergroups
+    let mut allowed_as = vec![name];
+    allowed_as.extend(parents.iter());
+    let referenced_by: Vec<&Reference> = references.iter()
+        .filter(|r| r.allowed.len() < definitions.groups.len() && r.allowed.iter().any(|g| allowed_as.contains(&g.as_str())))
+        .collect();
+    body += "<h2>Can be referenced by</h2>\n";
+    if referenced_by.is_empty() {
+        body += "<p>Nothing references this group specifically.</p>\n";
+    }
+    else {
+        body += "<ul>\n";
+        for r in referenced_by {
+            body += &format!("<li><a href=\"{}.html#{}\">{}</a></li>\n", escape(r.group), escape(r.id), escape(r.id));
+        }
+        body += "</ul>\n";
+    }
+
+    writer.write_block(&mut body, &group.block, name);
+    search.append(&mut writer.search);
+    page(definitions, &format!("{} tag group", name), &body)
+}
+
+/// Write an HTML site documenting every group
+///
+/// Returns a map of file names to their contents: a page for each group, an `index.html` listing them by supergroup,
+/// `search.js` and `style.css`.
+pub fn docs(definitions: &Definitions) -> BTreeMap<String, String> {
+    let mut files = BTreeMap::new();
+    let mut references = Vec::new();
+    for (name, group) in &definitions.groups {
+        collect_references(name, &group.block, &mut references);
+    }
+
+    let mut search = Vec::new();
+    for name in definitions.groups.keys() {
+        files.insert(format!("{}.html", name), group_page(definitions, name, &references, &mut search));
+    }
+
+    let roots: Vec<&str> = definitions.groups.iter()
+        .filter(|(_, g)| g.supergroup.as_ref().map(|s| !definitions.groups.contains_key(s)).unwrap_or(true))
+        .map(|(n, _)| n.as_str())
+        .collect();
+    let mut index = format!("<h1>Tag groups</h1>\n<p>{} groups, listed under their supergroups.</p>\n", definitions.groups.len());
+    write_tree(definitions, &mut index, &roots);
+    files.insert("index.html".to_owned(), page(definitions, "Tag groups", &index));
+
+    files.insert("search.js".to_owned(), format!("\"use strict\";\n\nconst SEARCH_INDEX = {};\n{}", Value::from(search), SEARCH));
+    files.insert("style.css".to_owned(), STYLE.to_owned());
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use tag_file::tests::{definitions, temporary_directory};
+
+    /// Get every value of an attribute in a page
+    fn attributes<'a>(html: &'a str, attribute: &str) -> Vec<&'a str> {
+        let prefix = format!("{}=\"", attribute);
+        html.split(prefix.as_str()).skip(1).map(|s| &s[..s.find('"').unwrap()]).collect()
+    }
+
+    /// Check that a link from a page goes to a file that exists and an anchor in it
+    fn check_link(files: &BTreeMap<String, String>, page: &str, link: &str) {
+        let (file, anchor) = link.split_once('#').unwrap_or((link, ""));
+        let file = if file.is_empty() { page } else { file };
+        let target = files.get(file).unwrap_or_else(|| panic!("{} links to {}, which doesn't exist", page, file));
+        assert!(anchor.is_empty() || attributes(target, "id").contains(&anchor), "{} links to {}, which doesn't exist", page, link);
+    }
+
+    #[test]
+    fn links_resolve() {
+        let definitions = definitions();
+        let files = docs(&definitions);
+        assert_eq!(files.keys().collect::<Vec<_>>(), vec!["index.html", "model.html", "search.js", "style.css", "weapon.html"]);
+
+        for (name, html) in files.iter().filter(|(n, _)| n.ends_with(".html")) {
+            for link in attributes(html, "href").into_iter().chain(attributes(html, "src")) {
+                check_link(&files, name, &link.replace("&quot;", "\""));
+            }
+        }
+
+        let search = &files["search.js"];
+        let prefix = "const SEARCH_INDEX = ";
+        let index = &search[search.find(prefix).unwrap() + prefix.len()..];
+        let index: Value = serde_json::Deserializer::from_str(index).into_iter().next().unwrap().unwrap();
+        for entry in index.as_array().unwrap() {
+            check_link(&files, "index.html", entry["url"].as_str().unwrap());
+        }
+        assert!(index.as_array().unwrap().iter().any(|e| e["id"] == "weapon.type:pistol"));
+
+        // The model page links back to the references to it
+        assert!(files["model.html"].contains("<a href=\"weapon.html#weapon.model\">weapon.model</a>"));
+        assert!(files["weapon.html"].contains("<td class=\"offset\">0x2C</td>"));
+
+        let directory = temporary_directory("docs_links_resolve");
+        std::fs::write(directory.join("search.js"), search).unwrap();
+        let node = Command::new("node").args(["--check", "search.js"]).current_dir(&directory).output().unwrap();
+        assert!(node.status.success(), "{}", String::from_utf8_lossy(&node.stderr));
+    }
+}
@@ -0,0 +1,152 @@
+//! Fingerprints for telling whether blocks are laid out the same
+//!
+//! Every block gets two SHA-256 hashes:
+//!
+//! - `layout_fingerprint` covers the block's maximum and length, and the offset, size and type of each field that takes
+//!   up space, along with the layout fingerprints of any blocks in it. Two blocks with the same layout fingerprint read
+//!   and write tag data the same way.
+//! - `fingerprint` also covers every other field and names: field names, enum options and bits, section text, the
+//!   blocks indexes point to, tag data types and the groups tag references allow. Descriptions, units and block names
+//!   are left out.
+//!
+//! A group's fingerprints are the fingerprints of its block.
+
+extern crate sha2;
+use self::sha2::{Sha256, Digest};
+
+use super::{Block, BlockFieldType, Definitions, Field, FieldName};
+use tag_file::StructLayout;
+
+/// Describe a field for hashing, with its name and everything else about it
+fn describe_field(field: &Field) -> String {
+    let name = match &field.name {
+        Some(n) => n.name.as_str(),
+        None => ""
+    };
+    let option_names = |options: &[FieldName]| options.iter().map(|o| o.name.as_str()).collect::<Vec<&str>>().join("\t");
+
+    let description = match &field.block_type {
+        BlockFieldType::Unknown(field_type, _) => format!("unknown {}", field_type),
+        BlockFieldType::Index(block, size) => format!("index {} {}", size, block),
+        BlockFieldType::TagData(data_type, max_length) => format!("tag_data {} {}", max_length, data_type),
+        BlockFieldType::Section(text) => format!("section {}", text),
+        BlockFieldType::Reference(groups) => format!("tag_reference {}", groups.join("\t")),
+        BlockFieldType::Primitive(type_name) => format!("primitive {}", type_name),
+        BlockFieldType::ArrayStart(count) => format!("array_start {}", count),
+        BlockFieldType::ArrayEnd => "array_end".to_owned(),
+        BlockFieldType::Custom => "custom".to_owned(),
+        BlockFieldType::Enum(options) => format!("enum {} {}", options.len(), option_names(options)),
+        BlockFieldType::Flags(size, bits) => format!("bitfield {} {} {}", size, bits.len(), option_names(bits)),
+        BlockFieldType::Range(type_name) => format!("range {}", type_name),
+        BlockFieldType::Padding(type_name, count) => format!("padding {} {}", type_name, count),
+        BlockFieldType::Block(b) => format!("block {}", b.fingerprint)
+    };
+
+    // Names can't have line breaks in them, so this can't be confused with another field
+    format!("{}\n{}\n", name.replace('\n', " "), description.replace('\n', " "))
+}
+
+/// Describe the fields of a layout for hashing, leaving out names
+fn describe_layout(layout: &StructLayout) -> String {
+    let mut description = String::new();
+    for entry in &layout.entries {
+        let kind = match (&entry.field.block_type, &entry.array) {
+            (_, Some(array)) => format!("array {} {{\n{}}}", array.count, describe_layout(&array.element)),
+            (BlockFieldType::Index(_, size), _) => format!("index {}", size),
+            (BlockFieldType::Flags(size, _), _) => format!("bitfield {}", size),
+            (BlockFieldType::Primitive(type_name), _) => format!("primitive {}", type_name),
+            (BlockFieldType::Range(type_name), _) => format!("range {}", type_name),
+            (BlockFieldType::Block(b), _) => format!("block {}", b.layout_fingerprint),
+            (BlockFieldType::Enum(_), _) => "enum".to_owned(),
+            (BlockFieldType::Reference(_), _) => "tag_reference".to_owned(),
+            (BlockFieldType::TagData(_, _), _) => "tag_data".to_owned(),
+            _ => "padding".to_owned()
+        };
+        description += &format!("{} {} {}\n", entry.offset, entry.size, kind);
+    }
+    description
+}
+
+fn to_hex(sha256: Sha256) -> String {
+    let mut result_string = String::new();
+    for i in sha256.finalize() {
+        result_string += &format!("{:02X}", i);
+    }
+    result_string
+}
+
+fn hash_layout(block: &Block) -> String {
+    let layout = StructLayout::of(block);
+    let mut sha256 = Sha256::new();
+    sha256.update(format!("{} {} {}\n", block.maximum, block.length, layout.size));
+    sha256.update(describe_layout(&layout));
+    to_hex(sha256)
+}
+
+fn hash_names(block: &Block) -> String {
+    let mut sha256 = Sha256::new();
+    sha256.update(format!("{}\n{}\n", block.layout_fingerprint, block.fields.len()));
+    for field in &block.fields {
+        sha256.update(describe_field(field));
+    }
+    to_hex(sha256)
+}
+
+/// Compute the fingerprints of a block and every block in it
+pub fn fingerprint_block(block: &mut Block) {
+    for field in &mut block.fields {
+        if let BlockFieldType::Block(b) = &mut field.block_type {
+            fingerprint_block(b);
+        }
+    }
+    block.layout_fingerprint = hash_layout(block);
+    block.fingerprint = hash_names(block);
+}
+
+/// Compute the fingerprints of every block, replacing any that are already there
+pub fn assign_fingerprints(definitions: &mut Definitions) {
+    for group in definitions.groups.values_mut() {
+        fingerprint_block(&mut group.block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::serde_json::{self, Value};
+    use def_dumper::read_definitions;
+    use tag_file::tests::DEFINITIONS;
+
+    /// Fingerprint the weapon group after editing its fields
+    fn weapon_fingerprints(edit: impl Fn(&mut Vec<Value>)) -> (String, String) {
+        let mut json: Value = serde_json::from_str(DEFINITIONS).unwrap();
+        edit(json["groups"]["weapon"]["block"]["fields"].as_array_mut().unwrap());
+        let definitions = read_definitions(&serde_json::to_vec(&json).unwrap()).unwrap();
+        let block = &definitions.groups["weapon"].block;
+        (block.layout_fingerprint.clone(), block.fingerprint.clone())
+    }
+
+    #[test]
+    fn layout_leaves_out_names() {
+        let (layout, fingerprint) = weapon_fingerprints(|_| ());
+
+        // Sections and enum options don't change how tags are stored
+        let edits: Vec<Box<dyn Fn(&mut Vec<Value>)>> = vec![
+            Box::new(|f| f.insert(0, serde_json::json!({"type": "section", "text": "extra"}))),
+            Box::new(|f| f[2]["options"].as_array_mut().unwrap().push(serde_json::json!({"name": "shotgun"}))),
+            Box::new(|f| f[2]["options"][0]["name"] = Value::from("carbine")),
+            Box::new(|f| f[4]["name"] = Value::from("fire rate"))
+        ];
+        for edit in edits {
+            let (edited_layout, edited_fingerprint) = weapon_fingerprints(edit);
+            assert_eq!(edited_layout, layout);
+            assert_ne!(edited_fingerprint, fingerprint);
+        }
+
+        // Changing a size or a block in it does
+        let (edited_layout, _) = weapon_fingerprints(|f| f[11]["type"] = Value::from("int32"));
+        assert_ne!(edited_layout, layout);
+        let (edited_layout, _) = weapon_fingerprints(|f| f[7]["block"]["fields"][2]["type"] = Value::from("int32"));
+        assert_ne!(edited_layout, layout);
+    }
+}
@@ -0,0 +1,526 @@
+//! Converting definitions to and from Invader's definition JSON
+//!
+//! Invader describes each group with a JSON array of structs, enums and bitfields referred to by PascalCase names, plus
+//! a `group` entry naming the group's struct. Structs are named after their block without the `_block` suffix, enums
+//! and bitfields after the struct and field they're in, and arrays become structs used with a `count`. A group whose
+//! block starts with the fields of its supergroup's block `inherits` the supergroup's struct.
+
+use std::collections::BTreeMap;
+
+use super::serde_json::{Map, Value};
+use super::{Block, BlockFieldType, Definitions, Field, FieldName, Group, primitive_size, to_identifier};
+use super::diff::{DefinitionChange, diff_definitions, field_key, type_name};
+use super::schema::object;
+use tag_file::StructLayout;
+
+/// Primitive types and their Invader names
+const TYPES: &[(&str, &str)] = &[
+    ("string", "TagString"),
+    ("int8", "int8"),
+    ("int16", "int16"),
+    ("int32", "int32"),
+    ("float_angle", "Angle"),
+    ("fourcc", "TagFourCC"),
+    ("point2d_int", "Point2DInt"),
+    ("rectangle", "Rectangle2D"),
+    ("color_rgb_int", "ColorRGBInt"),
+    ("color_argb_int", "ColorARGBInt"),
+    ("float", "float"),
+    ("float_clamped", "Fraction"),
+    ("point2d", "Point2D"),
+    ("point3d", "Point3D"),
+    ("vector2d", "Vector2D"),
+    ("vector3d", "Vector3D"),
+    ("quaternion", "Quaternion"),
+    ("euler2d", "Euler2D"),
+    ("euler3d", "Euler3D"),
+    ("plane2d", "Plane2D"),
+    ("plane3d", "Plane3D"),
+    ("color_rgb", "ColorRGB"),
+    ("color_argb", "ColorARGB"),
+    ("color_hsv", "ColorHSV"),
+    ("color_ahsv", "ColorAHSV")
+];
+
+/// Invader types that are stored the same as a primitive but mean something else
+const ALIASES: &[(&str, &str)] = &[("uint8", "int8"), ("uint16", "int16"), ("uint32", "int32"), ("TagID", "int32")];
+
+/// Invader types only used in cache files and their sizes, which are left as padding in tag files
+const CACHE_ONLY_TYPES: &[(&str, usize)] = &[("Pointer", 4), ("ScenarioScriptNodeValue", 4)];
+
+fn invader_type(type_name: &str) -> &'static str {
+    TYPES.iter().find(|(n, _)| *n == type_name).map(|(_, i)| *i).unwrap()
+}
+
+fn primitive_from_invader(type_name: &str) -> Option<&'static str> {
+    TYPES.iter().chain(ALIASES.iter()).find(|(_, i)| *i == type_name).map(|(n, _)| *n)
+}
+
+/// Get a field type for an Invader type that isn't a primitive and isn't defined anywhere
+///
+/// Types only used in cache files become padding. Anything else is reported, and becomes padding of the field's `size`
+/// if it has one or is left unknown otherwise.
+fn undefined_type(struct_name: &str, type_name: &str, field: &Value) -> BlockFieldType {
+    if let Some((_, size)) = CACHE_ONLY_TYPES.iter().find(|(n, _)| *n == type_name) {
+        return BlockFieldType::Padding("int8", *size)
+    }
+
+    eprintln!("Invader definitions: a field of `{}` has the unknown type `{}`", struct_name, type_name);
+    match field.get("size").and_then(Value::as_u64) {
+        Some(size) => BlockFieldType::Padding("int8", size as usize),
+        None => BlockFieldType::default()
+    }
+}
+
+/// Turn a guerilla name into a PascalCase name, such as `weapon trigger` into `WeaponTrigger`
+fn pascal_case(name: &str) -> String {
+    to_identifier(name).split('_').filter(|w| !w.is_empty()).map(|w| {
+        let mut chars = w.chars();
+        chars.next().map(|c| c.to_ascii_uppercase().to_string() + chars.as_str()).unwrap_or_default()
+    }).collect()
+}
+
+fn struct_name_of(block: &Block) -> Option<String> {
+    block.name.as_ref().map(|n| pascal_case(n.strip_suffix("_block").unwrap_or(n))).filter(|n| !n.is_empty())
+}
+
+fn width_of(size: &str) -> usize {
+    primitive_size(size) * 8
+}
+
+fn size_of_width(width: u64) -> Option<&'static str> {
+    match width {
+        8 => Some("int8"),
+        16 => Some("int16"),
+        32 => Some("int32"),
+        _ => None
+    }
+}
+
+struct Exporter {
+    /// Every name given out so far, along with what it was given to (the block fingerprint or the enum's contents), or
+    /// an empty string if it can't be shared
+    names: BTreeMap<String, String>,
+
+    /// Entries of the group being exported
+    entries: Vec<Value>
+}
+
+impl Exporter {
+    /// Give something a name based on `base`, or get the name it already has if something with the same key has one
+    fn reserve(&mut self, base: String, key: String) -> Result<String, String> {
+        let mut name = base.clone();
+        let mut number = 2;
+        loop {
+            match self.names.get(&name) {
+                None => {
+                    self.names.insert(name.clone(), key);
+                    return Ok(name)
+                },
+                Some(k) if !key.is_empty() && *k == key => return Err(name),
+                Some(_) => {
+                    name = format!("{}{}", base, number);
+                    number += 1;
+                }
+            }
+        }
+    }
+
+    /// Write an enum or bitfield, returning its name
+    fn options(&mut self, base: String, entry: Vec<(&str, Value)>) -> String {
+        let key = Value::Array(entry.iter().map(|(_, v)| v.clone()).collect()).to_string();
+        match self.reserve(base, key) {
+            Ok(name) => {
+                let mut entry = entry;
+                entry.insert(0, ("name", Value::from(name.as_str())));
+                self.entries.push(object(entry));
+                name
+            },
+            Err(name) => name
+        }
+    }
+
+    /// Write a block's struct, leaving out the fields of the struct it inherits from (if any), returning the struct's name
+    fn block(&mut self, block: &Block, fallback: &str, inherits: Option<(&str, usize)>) -> String {
+        let base = struct_name_of(block).unwrap_or_else(|| fallback.to_owned());
+        let name = match self.reserve(base, block.fingerprint.clone()) {
+            Ok(n) => n,
+            Err(n) => return n
+        };
+
+        let skip = inherits.map(|(_, n)| n).unwrap_or(0);
+        let fields = self.fields(&name, &mut block.fields[skip..].iter()).0;
+        let mut entry = vec![("name", Value::from(name.as_str())), ("fields", Value::from(fields)), ("type", Value::from("struct"))];
+        if let Some((parent, _)) = inherits {
+            entry.push(("inherits", Value::from(parent)));
+        }
+        entry.push(("size", Value::from(block.length)));
+        self.entries.push(object(entry));
+        name
+    }
+
+    /// Convert fields up to the end of the array (if in one), returning them and the size they take up
+    fn fields(&mut self, struct_name: &str, fields: &mut std::slice::Iter<Field>) -> (Vec<Value>, usize) {
+        let mut output = Vec::new();
+        let mut size = 0;
+        while let Some(field) = fields.next() {
+            let mut map = Map::new();
+            let field_name = field.name.as_ref().map(|n| n.name.as_str()).unwrap_or_default();
+            if let Some(name) = &field.name {
+                map.insert("name".to_owned(), Value::from(name.name.as_str()));
+                if let Some(description) = &name.description {
+                    map.insert("description".to_owned(), Value::from(description.as_str()));
+                }
+                if let Some(unit) = &name.unit {
+                    map.insert("unit".to_owned(), Value::from(unit.as_str()));
+                }
+                if name.read_only {
+                    map.insert("read_only".to_owned(), Value::from(true));
+                }
+            }
+            let base = format!("{}{}", struct_name, pascal_case(field_name));
+
+            let type_name = match &field.block_type {
+                BlockFieldType::ArrayEnd => break,
+                BlockFieldType::Custom | BlockFieldType::Unknown(_, _) => continue,
+                BlockFieldType::Primitive(t) => invader_type(t).to_owned(),
+                BlockFieldType::Range(t) => {
+                    map.insert("bounds".to_owned(), Value::from(true));
+                    invader_type(t).to_owned()
+                },
+                BlockFieldType::Padding(_, _) => {
+                    map.insert("size".to_owned(), Value::from(field.block_type.size()));
+                    "pad".to_owned()
+                },
+                BlockFieldType::Section(text) => {
+                    if !text.is_empty() {
+                        map.insert("description".to_owned(), Value::from(text.as_str()));
+                    }
+                    "editor_section".to_owned()
+                },
+                BlockFieldType::Index(block, index_size) => {
+                    map.insert("reflexive".to_owned(), Value::from(block.as_str()));
+                    if *index_size == "int16" { "Index".to_owned() } else { invader_type(index_size).to_owned() }
+                },
+                BlockFieldType::Enum(options) => {
+                    let options: Vec<&str> = options.iter().map(|o| o.name.as_str()).collect();
+                    self.options(base, vec![("options", Value::from(options)), ("type", Value::from("enum"))])
+                },
+                BlockFieldType::Flags(flags_size, bits) => {
+                    let bits: Vec<&str> = bits.iter().map(|o| o.name.as_str()).collect();
+                    self.options(base, vec![("fields", Value::from(bits)), ("type", Value::from("bitfield")), ("width", Value::from(width_of(flags_size)))])
+                },
+                BlockFieldType::Reference(groups) => {
+                    map.insert("groups".to_owned(), Value::from(groups.clone()));
+                    "TagReference".to_owned()
+                },
+                BlockFieldType::Block(block) => {
+                    map.insert("struct".to_owned(), Value::from(self.block(block, &base, None)));
+                    map.insert("maximum".to_owned(), Value::from(block.maximum));
+                    "TagReflexive".to_owned()
+                },
+                BlockFieldType::TagData(_, _) => "TagDataOffset".to_owned(),
+                BlockFieldType::ArrayStart(count) => {
+                    // Arrays can't be shared since what's in them is named after them
+                    let name = self.reserve(base, String::new()).unwrap();
+                    let (array_fields, array_size) = self.fields(&name, fields);
+                    self.entries.push(object(vec![
+                        ("name", Value::from(name.as_str())),
+                        ("fields", Value::from(array_fields)),
+                        ("type", Value::from("struct")),
+                        ("size", Value::from(array_size))
+                    ]));
+                    map.insert("count".to_owned(), Value::from(*count));
+                    size += array_size * count;
+                    name
+                }
+            };
+            size += field.block_type.size();
+            map.insert("type".to_owned(), Value::from(type_name));
+            output.push(Value::Object(map));
+        }
+        (output, size)
+    }
+}
+
+/// Get how many fields of a group's block are its supergroup's, if the block starts with all of them
+fn inherited_fields(definitions: &Definitions, group: &Group) -> Option<usize> {
+    let supergroup = &definitions.groups.get(group.supergroup.as_ref()?)?.block;
+    let parent = &supergroup.fields;
+    let same = |a: &Field, b: &Field| field_key(a) == field_key(b) && type_name(&a.block_type) == type_name(&b.block_type);
+    if !parent.is_empty() && parent.len() < group.block.fields.len() && parent.iter().zip(group.block.fields.iter()).all(|(a, b)| same(a, b)) {
+        Some(parent.len())
+    }
+    else {
+        None
+    }
+}
+
+/// Get how many supergroups a group has
+fn supergroup_depth<'a>(definitions: &'a Definitions, mut name: &'a str) -> usize {
+    let mut depth = 0;
+    while let Some(s) = definitions.groups.get(name).and_then(|g| g.supergroup.as_ref()) {
+        name = s;
+        depth += 1;
+    }
+    depth
+}
+
+/// Convert definitions into Invader's definition JSON, returning the file name and contents of each group's file
+///
+/// Supergroups are converted before the groups that inherit from them, and anything shared between groups goes in the
+/// file of the first group that uses it.
+pub fn export_definitions(definitions: &Definitions) -> BTreeMap<String, Value> {
+    let mut groups: Vec<(&String, &Group)> = definitions.groups.iter().collect();
+    groups.sort_by_key(|(name, _)| (supergroup_depth(definitions, name), name.to_owned()));
+
+    let mut exporter = Exporter { names: BTreeMap::new(), entries: Vec::new() };
+    let mut struct_names = BTreeMap::new();
+    let mut files = BTreeMap::new();
+    for (name, group) in groups {
+        let inherits = match (inherited_fields(definitions, group), group.supergroup.as_ref().and_then(|s| struct_names.get(s))) {
+            (Some(count), Some(parent)) => Some((parent as &String, count)),
+            _ => None
+        };
+        let struct_name = exporter.block(&group.block, &pascal_case(name), inherits.map(|(p, c)| (p.as_str(), c)));
+
+        let mut entry = vec![
+            ("name", Value::from(name.as_str())),
+            ("struct", Value::from(struct_name.as_str())),
+            ("type", Value::from("group")),
+            ("version", Value::from(group.version)),
+            ("fourcc_binary", Value::from(group.fourcc))
+        ];
+        if let Some(supergroup) = &group.supergroup {
+            entry.push(("supergroup", Value::from(supergroup.as_str())));
+        }
+        exporter.entries.push(object(entry));
+
+        struct_names.insert(name.to_owned(), struct_name);
+        files.insert(format!("{}.json", name), Value::Array(std::mem::take(&mut exporter.entries)));
+    }
+    files
+}
+
+struct Importer<'a> {
+    entries: BTreeMap<&'a str, &'a Map<String, Value>>
+}
+
+impl<'a> Importer<'a> {
+    fn entry(&self, name: &str) -> Option<&'a Map<String, Value>> {
+        let entry = self.entries.get(name).copied();
+        if entry.is_none() {
+            eprintln!("Invader definitions: no struct, enum or bitfield named `{}`", name);
+        }
+        entry
+    }
+
+    fn names(&self, entry: &Map<String, Value>, key: &str) -> Vec<FieldName> {
+        entry.get(key).and_then(Value::as_array).map(|o| o.iter().map(|o| FieldName {
+            name: o.as_str().unwrap_or_default().to_owned(),
+            ..Default::default()
+        }).collect()).unwrap_or_default()
+    }
+
+    fn block(&self, struct_name: &str, maximum: usize) -> Option<Block> {
+        let mut fields = Vec::new();
+        self.fields(struct_name, &mut fields)?;
+        let length = match self.entry(struct_name)?.get("size").and_then(Value::as_u64) {
+            Some(n) => n as usize,
+            None => StructLayout::of_fields(&fields).size
+        };
+
+        // Invader's names aren't guerilla's, so they're treated like made up names when comparing
+        Some(Block { name: Some(struct_name.to_owned()), name_synthesized: true, maximum, length, fields, ..Default::default() })
+    }
+
+    /// Add the fields of a struct, starting with those of the struct it inherits from
+    fn fields(&self, struct_name: &str, output: &mut Vec<Field>) -> Option<()> {
+        let entry = self.entry(struct_name)?;
+        if let Some(parent) = entry.get("inherits").and_then(Value::as_str) {
+            self.fields(parent, output)?;
+        }
+        for f in entry.get("fields").and_then(Value::as_array).map(|f| f.as_slice()).unwrap_or_default() {
+            self.field(struct_name, f, output)?;
+        }
+        Some(())
+    }
+
+    fn field(&self, struct_name: &str, field: &Value, output: &mut Vec<Field>) -> Option<()> {
+        let string = |key: &str| field.get(key).and_then(Value::as_str).map(|s| s.to_owned());
+        let name = string("name").map(|name| FieldName {
+            name,
+            description: string("description"),
+            unit: string("unit"),
+            read_only: field.get("read_only").and_then(Value::as_bool).unwrap_or_default(),
+            ..Default::default()
+        });
+        let type_name = match field.get("type").and_then(Value::as_str) {
+            Some(n) => n,
+            None => {
+                eprintln!("Invader definitions: a field of `{}` has no type", struct_name);
+                return None
+            }
+        };
+        let count = field.get("count").and_then(Value::as_u64).map(|c| c as usize);
+        if let Some(count) = count {
+            output.push(Field { name: name.clone(), block_type: BlockFieldType::ArrayStart(count), ..Default::default() });
+        }
+
+        let block_type = match type_name {
+            "pad" => BlockFieldType::Padding("int8", field.get("size").and_then(Value::as_u64).unwrap_or_default() as usize),
+            "editor_section" => BlockFieldType::Section(string("description").unwrap_or_default()),
+            "TagReference" => BlockFieldType::Reference(
+                field.get("groups").and_then(Value::as_array).map(|g| g.iter().filter_map(|g| g.as_str().map(|g| g.to_owned())).collect()).unwrap_or_default()
+            ),
+            "TagReflexive" => {
+                let maximum = field.get("maximum").and_then(Value::as_u64).unwrap_or(u32::MAX as u64) as usize;
+                BlockFieldType::Block(self.block(&string("struct").unwrap_or_default(), maximum)?)
+            },
+            "TagDataOffset" => BlockFieldType::TagData(String::new(), 0),
+            "Index" => BlockFieldType::Index(string("reflexive").unwrap_or_default(), "int16"),
+            n if primitive_from_invader(n).is_none() && !self.entries.contains_key(n) => undefined_type(struct_name, n, field),
+            n => match primitive_from_invader(n) {
+                Some(p @ ("int16" | "int32")) if field.get("reflexive").is_some() => BlockFieldType::Index(string("reflexive").unwrap_or_default(), p),
+                Some(p) if field.get("bounds").and_then(Value::as_bool).unwrap_or_default() => BlockFieldType::Range(p),
+                Some(p) => BlockFieldType::Primitive(p),
+                None => {
+                    let entry = self.entry(n)?;
+                    match entry.get("type").and_then(Value::as_str) {
+                        Some("enum") => BlockFieldType::Enum(self.names(entry, "options")),
+                        Some("bitfield") => match entry.get("width").and_then(Value::as_u64).and_then(size_of_width) {
+                            Some(size) => BlockFieldType::Flags(size, self.names(entry, "fields")),
+                            None => {
+                                eprintln!("Invader definitions: `{}` has an unsupported width", n);
+                                return None
+                            }
+                        },
+
+                        // Structs used as a field are laid out in place
+                        _ => {
+                            self.fields(n, output)?;
+                            if count.is_some() {
+                                output.push(Field { block_type: BlockFieldType::ArrayEnd, ..Default::default() });
+                            }
+                            return Some(())
+                        }
+                    }
+                }
+            }
+        };
+        output.push(Field { name, block_type, ..Default::default() });
+        if count.is_some() {
+            output.push(Field { block_type: BlockFieldType::ArrayEnd, ..Default::default() });
+        }
+        Some(())
+    }
+}
+
+/// Convert Invader's definition JSON (the contents of every file) into definitions
+///
+/// The FourCC and version of groups without `fourcc_binary` and `version` are taken from `fallback`, if it has the
+/// group.
+pub fn import_definitions(files: &[Value], fallback: Option<&Definitions>) -> Option<Definitions> {
+    let mut importer = Importer { entries: BTreeMap::new() };
+    let mut groups = Vec::new();
+    for entry in files.iter().filter_map(Value::as_array).flatten() {
+        let (name, map) = match (entry.get("name").and_then(Value::as_str), entry.as_object()) {
+            (Some(name), Some(map)) => (name, map),
+            _ => {
+                eprintln!("Invader definitions: found an entry without a name");
+                return None
+            }
+        };
+        if map.get("type").and_then(Value::as_str) == Some("group") {
+            groups.push((name, map));
+        }
+        else {
+            importer.entries.insert(name, map);
+        }
+    }
+
+    let mut definitions = Definitions {
+        dumper_version: String::new(),
+        exe_sha256sum: String::new(),
+        exe_pe_file_version: None,
+        exe_pe_creation_date: String::new(),
+        exe_pe_checksum: 0,
+        groups: BTreeMap::new()
+    };
+    for (name, group) in groups {
+        let exe_group = fallback.and_then(|f| f.groups.get(name));
+        let struct_name = group.get("struct").and_then(Value::as_str).unwrap_or_default();
+        definitions.groups.insert(name.to_owned(), Group {
+            supergroup: group.get("supergroup").and_then(Value::as_str).map(|s| s.to_owned()),
+            fourcc: group.get("fourcc_binary").and_then(Value::as_u64).map(|f| f as u32).or_else(|| exe_group.map(|g| g.fourcc)).unwrap_or_default(),
+            version: group.get("version").and_then(Value::as_u64).map(|v| v as u16).or_else(|| exe_group.map(|g| g.version)).unwrap_or_default(),
+            block: importer.block(struct_name, 1)?
+        });
+    }
+    Some(definitions)
+}
+
+/// Remove fields Invader has no equivalent of
+fn strip_block(block: &mut Block) {
+    block.fields.retain(|f| !matches!(f.block_type, BlockFieldType::Custom | BlockFieldType::Unknown(_, _)));
+    for f in &mut block.fields {
+        if let BlockFieldType::Block(b) = &mut f.block_type {
+            strip_block(b);
+        }
+    }
+}
+
+/// Compare Invader's definition JSON (the contents of every file) with definitions, returning how each group in the
+/// definitions differs from Invader's
+///
+/// Struct names aren't compared, as Invader names structs differently, and neither are fields Invader has no equivalent
+/// of (custom and unknown fields).
+pub fn compare_definitions(definitions: &Definitions, files: &[Value]) -> Option<BTreeMap<String, Vec<DefinitionChange>>> {
+    let invader = import_definitions(files, Some(definitions))?;
+    let mut definitions = definitions.clone();
+    for group in definitions.groups.values_mut() {
+        strip_block(&mut group.block);
+    }
+    Some(diff_definitions(&invader, &definitions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tag_file::tests::definitions;
+
+    #[test]
+    fn export_and_compare() {
+        let definitions = definitions();
+        let files = export_definitions(&definitions);
+        assert_eq!(files.keys().collect::<Vec<_>>(), vec!["model.json", "weapon.json"]);
+
+        let weapon = files["weapon.json"].as_array().unwrap();
+        assert_eq!(weapon.last().unwrap(), &serde_json::json!({"fourcc_binary": 2003134832, "name": "weapon", "struct": "Weapon", "type": "group", "version": 2}));
+        let weapon_struct = weapon.iter().find(|e| e["name"] == "Weapon").unwrap();
+        assert_eq!(weapon_struct["size"], 132);
+        assert_eq!(weapon_struct["fields"][7], serde_json::json!({"maximum": 2, "name": "triggers", "struct": "WeaponTrigger", "type": "TagReflexive"}));
+
+        // Exporting and importing again gives the same definitions
+        let mut files: Vec<Value> = files.into_values().collect();
+        assert_eq!(compare_definitions(&definitions, &files).unwrap(), BTreeMap::new());
+
+        files[1][4]["fields"][4]["name"] = Value::from("fire rate");
+        let changes = compare_definitions(&definitions, &files).unwrap();
+        assert_eq!(changes["weapon"].iter().map(|c| c.to_string()).collect::<Vec<_>>(), vec!["weapon.rate: renamed from fire_rate"]);
+    }
+
+    #[test]
+    fn cache_only_types() {
+        let files = vec![serde_json::json!([
+            {"name": "Model", "type": "struct", "fields": [{"name": "node", "type": "Pointer"}, {"name": "scale", "type": "float"}]},
+            {"name": "model", "struct": "Model", "type": "group"}
+        ])];
+        let fallback = definitions();
+        let imported = import_definitions(&files, Some(&fallback)).unwrap();
+        let model = &imported.groups["model"];
+        assert_eq!((model.fourcc, model.version), (1836016741, 4));
+        assert_eq!(model.block.length, 8);
+        assert!(matches!(model.block.fields[0].block_type, BlockFieldType::Padding("int8", 4)));
+    }
+}
@@ -0,0 +1,151 @@
+//! Finding suspicious structures in definitions
+//!
+//! Most of these are either bugs in how the definitions were read or quirks of guerilla that anything using the
+//! definitions needs to deal with.
+
+use std::collections::BTreeMap;
+
+use super::{Block, BlockFieldType, Definitions, primitive_size};
+use tag_file::StructLayout;
+
+/// What looks wrong
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum LintKind {
+    DuplicateFieldName,
+    DuplicateOptionName,
+    TooManyFlags,
+    SizeMismatch,
+    UnknownField,
+    EmptySection,
+    NoAllowedGroups
+}
+
+/// Something suspicious in the definitions
+#[derive(serde::Serialize, Clone, PartialEq, Debug)]
+pub struct LintIssue {
+    /// Path ID of the block, field or option (see [`path_id`](super::path_id))
+    pub id: String,
+
+    pub kind: LintKind,
+
+    pub message: String
+}
+
+impl std::fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.id, self.message)
+    }
+}
+
+fn issue(issues: &mut Vec<LintIssue>, id: &str, kind: LintKind, message: String) {
+    issues.push(LintIssue { id: id.to_owned(), kind, message });
+}
+
+fn lint_block(block: &Block, issues: &mut Vec<LintIssue>) {
+    let size = StructLayout::of(block).size;
+    if size != block.length {
+        issue(issues, &block.id, LintKind::SizeMismatch, format!("fields add up to {} bytes, but the block is {} bytes", size, block.length));
+    }
+
+    // Names only need to be unique within the block or array they're in
+    let mut names = vec![BTreeMap::<String, &str>::new()];
+    for field in &block.fields {
+        match &field.block_type {
+            BlockFieldType::ArrayEnd if names.len() > 1 => { names.pop(); },
+            _ => ()
+        }
+
+        if let Some(identifier) = field.name.as_ref().map(|n| n.identifier()).filter(|i| !i.is_empty()) {
+            match names.last().unwrap().get(&identifier) {
+                Some(other) => issue(issues, &field.id, LintKind::DuplicateFieldName, format!("`{}` is also the name of `{}`", identifier, other)),
+                None => { names.last_mut().unwrap().insert(identifier, &field.id); }
+            }
+        }
+
+        match &field.block_type {
+            BlockFieldType::ArrayStart(_) => names.push(BTreeMap::new()),
+            BlockFieldType::Block(b) => lint_block(b, issues),
+            BlockFieldType::Unknown(field_type, _) => {
+                issue(issues, &field.id, LintKind::UnknownField, format!("unknown field type 0x{:02X}", field_type));
+            },
+            BlockFieldType::Section(text) if text.trim().is_empty() => {
+                issue(issues, &field.id, LintKind::EmptySection, "section has no text".to_owned());
+            },
+            BlockFieldType::Reference(groups) if groups.is_empty() => {
+                issue(issues, &field.id, LintKind::NoAllowedGroups, "reference doesn't allow any groups".to_owned());
+            },
+            BlockFieldType::Flags(size, bits) if bits.len() > primitive_size(size) * 8 => {
+                issue(issues, &field.id, LintKind::TooManyFlags, format!("{} bits named, but {} only has {}", bits.len(), size, primitive_size(size) * 8));
+            },
+            _ => ()
+        }
+
+        if let BlockFieldType::Enum(options) | BlockFieldType::Flags(_, options) = &field.block_type {
+            let mut seen = BTreeMap::<String, &str>::new();
+            for o in options {
+                let identifier = o.identifier();
+                if identifier.is_empty() {
+                    continue
+                }
+                match seen.get(&identifier) {
+                    Some(other) => issue(issues, &o.id, LintKind::DuplicateOptionName, format!("`{}` is also the name of `{}`", identifier, other)),
+                    None => { seen.insert(identifier, &o.id); }
+                }
+            }
+        }
+    }
+}
+
+/// Find suspicious structures in every group, returning the issues found in each group that has any
+pub fn lint_definitions(definitions: &Definitions) -> BTreeMap<String, Vec<LintIssue>> {
+    let mut groups = BTreeMap::new();
+    for (name, group) in &definitions.groups {
+        let mut issues = Vec::new();
+        lint_block(&group.block, &mut issues);
+        if !issues.is_empty() {
+            groups.insert(name.to_owned(), issues);
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::serde_json::{self, Value, json};
+    use def_dumper::read_definitions;
+    use tag_file::tests::{DEFINITIONS, definitions};
+
+    #[test]
+    fn nothing_wrong() {
+        assert!(lint_definitions(&definitions()).is_empty());
+    }
+
+    #[test]
+    fn suspicious_weapon() {
+        let mut json: Value = serde_json::from_str(DEFINITIONS).unwrap();
+        let fields = json["groups"]["weapon"]["block"]["fields"].as_array_mut().unwrap();
+        fields[1]["size"] = json!("int8");
+        fields[1]["fields"] = json!((0..9).map(|i| json!({"name": format!("bit {}", i)})).collect::<Vec<Value>>());
+        fields[2]["options"] = json!([{"name": "rifle"}, {"name": "Rifle"}]);
+        fields[5]["text"] = json!(" ");
+        fields[6]["allowed_groups"] = json!([]);
+        fields[7]["block"]["fields"][2]["name"] = json!("rounds per second");
+        // Fields in an array can have the same names as fields outside it
+        fields[10]["name"] = json!("rate");
+        let definitions = read_definitions(&serde_json::to_vec(&json).unwrap()).unwrap();
+
+        let issues = lint_definitions(&definitions);
+        assert_eq!(issues.keys().collect::<Vec<_>>(), vec!["weapon"]);
+        let lines: Vec<String> = issues["weapon"].iter().map(|i| i.to_string()).collect();
+        assert_eq!(lines, vec![
+            "weapon: fields add up to 129 bytes, but the block is 132 bytes",
+            "weapon.flags: 9 bits named, but int8 only has 8",
+            "weapon.type:rifle_2: `rifle` is also the name of `weapon.type:rifle`",
+            "weapon.rate+section: section has no text",
+            "weapon.model: reference doesn't allow any groups",
+            "weapon.triggers.rounds_per_second_2: `rounds_per_second` is also the name of `weapon.triggers.rounds_per_second`"
+        ]);
+    }
+}
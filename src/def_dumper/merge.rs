@@ -0,0 +1,383 @@
+//! Merging definitions dumped from several builds into one set
+//!
+//! Builds are merged in the order they're given, so the last build should be the newest. Fields are matched up the same
+//! way as in [`diff`](super::diff), and anything not in every build lists the builds it's in.
+
+use std::collections::BTreeMap;
+
+use super::serde::ser::{Error, Serialize, Serializer, SerializeMap};
+use super::serde_json::{self, Value};
+use super::{Block, BlockFieldType, Definitions, Field, FieldName, option_identifiers};
+use super::diff::{Alignment, align, field_key, type_name};
+
+/// A build that definitions were dumped from
+#[derive(serde::Serialize, Clone, PartialEq, Debug)]
+pub struct Build {
+    /// Name of the build used in the rest of the merged definitions
+    pub id: String,
+    pub exe_sha256sum: String,
+    pub exe_pe_file_version: Option<String>,
+    pub exe_pe_creation_date: String,
+    pub exe_pe_checksum: u32
+}
+
+/// A value for each build something is in
+///
+/// This is serialized as just the value if every build has the same one, or as an object of build IDs to values if not.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PerBuild<T> {
+    pub values: Vec<(String, T)>
+}
+
+impl<T> PerBuild<T> {
+    fn new() -> Self {
+        PerBuild { values: Vec::new() }
+    }
+
+    fn push(&mut self, build: &str, value: T) {
+        self.values.push((build.to_owned(), value));
+    }
+
+    /// Get the builds this is in
+    pub fn builds(&self) -> Vec<&str> {
+        self.values.iter().map(|(b, _)| b.as_str()).collect()
+    }
+
+    /// Get the value from the newest build
+    pub fn latest(&self) -> &T {
+        &self.values.last().unwrap().1
+    }
+}
+
+impl<T: PartialEq> PerBuild<T> {
+    /// Returns true if every build has the same value
+    pub fn is_same(&self) -> bool {
+        self.values.windows(2).all(|w| w[0].1 == w[1].1)
+    }
+}
+
+impl<T: Serialize + PartialEq> Serialize for PerBuild<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        if self.is_same() {
+            return self.latest().serialize(serializer)
+        }
+        let mut map = serializer.serialize_map(Some(self.values.len()))?;
+        for (build, value) in &self.values {
+            map.serialize_entry(build, value)?;
+        }
+        map.end()
+    }
+}
+
+/// An enum option or bit in one or more builds
+pub struct MergedOption<'a> {
+    pub identifier: String,
+
+    /// Index of the option in each build it's in
+    pub index: PerBuild<usize>,
+
+    /// The option in each build it's in
+    pub versions: PerBuild<&'a FieldName>
+}
+
+impl<'a> Serialize for MergedOption<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("builds", &self.versions.builds())?;
+        if let Value::Object(option) = serde_json::to_value(self.versions.latest()).map_err(S::Error::custom)? {
+            for (k, v) in option {
+                map.serialize_entry(&k, &v)?;
+            }
+        }
+        if !self.index.is_same() {
+            map.serialize_entry("index", &self.index)?;
+        }
+        map.end()
+    }
+}
+
+/// A field in one or more builds
+pub struct MergedField<'a> {
+    /// The field in each build it's in
+    pub versions: PerBuild<&'a Field>,
+
+    /// The merged block, if this is a block field
+    pub block: Option<MergedBlock<'a>>,
+
+    /// The merged options or bits, if this is an enum or bitfield
+    pub options: Option<Vec<MergedOption<'a>>>
+}
+
+impl<'a> Serialize for MergedField<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("builds", &self.versions.builds())?;
+
+        // Everything else comes from the newest build the field is in, except for anything that differs between builds
+        let mut versions = Vec::new();
+        for (build, field) in &self.versions.values {
+            versions.push((build, serde_json::to_value(*field).map_err(S::Error::custom)?));
+        }
+        if let Some((_, Value::Object(field))) = versions.last() {
+            for (k, v) in field {
+                let mut values = PerBuild::new();
+                for (build, version) in &versions {
+                    values.push(build, version.get(k).cloned().unwrap_or(Value::Null));
+                }
+                match (k.as_str(), &self.block, &self.options) {
+                    ("block", Some(block), _) => map.serialize_entry(k, block)?,
+                    ("options", _, Some(options)) | ("fields", _, Some(options)) => map.serialize_entry(k, options)?,
+                    ("name", _, _) | ("type", _, _) => map.serialize_entry(k, v)?,
+                    _ => map.serialize_entry(k, &values)?
+                }
+            }
+        }
+
+        let mut names = PerBuild::new();
+        let mut types = PerBuild::new();
+        for (build, field) in &self.versions.values {
+            names.push(build, field.name.as_ref().map(|n| n.name.clone()).unwrap_or_default());
+            types.push(build, type_name(&field.block_type));
+        }
+        if !names.is_same() {
+            map.serialize_entry("names", &names)?;
+        }
+        if !types.is_same() {
+            map.serialize_entry("types", &types)?;
+        }
+
+        map.end()
+    }
+}
+
+/// A block in one or more builds
+#[derive(serde::Serialize)]
+pub struct MergedBlock<'a> {
+    pub name: PerBuild<Option<String>>,
+    pub maximum: PerBuild<usize>,
+    pub length: PerBuild<usize>,
+    pub layout_fingerprint: PerBuild<String>,
+    pub fingerprint: PerBuild<String>,
+    pub fields: Vec<MergedField<'a>>
+}
+
+/// A group in one or more builds
+#[derive(serde::Serialize)]
+pub struct MergedGroup<'a> {
+    pub builds: Vec<String>,
+    pub supergroup: PerBuild<Option<String>>,
+    pub fourcc: PerBuild<u32>,
+    pub version: PerBuild<u16>,
+    pub block: MergedBlock<'a>
+}
+
+/// Definitions from several builds merged together
+#[derive(serde::Serialize)]
+pub struct MergedDefinitions<'a> {
+    pub dumper_version: String,
+    pub builds: Vec<Build>,
+    pub groups: BTreeMap<String, MergedGroup<'a>>
+}
+
+impl<'a> MergedOption<'a> {
+    fn merge(options: &mut Vec<MergedOption<'a>>, build: &str, new: &'a [FieldName], fallback: &str) {
+        for (i, (identifier, option)) in option_identifiers(new, fallback).into_iter().zip(new.iter()).enumerate() {
+            match options.iter_mut().find(|o| o.identifier == identifier) {
+                Some(o) => {
+                    o.index.push(build, i);
+                    o.versions.push(build, option);
+                },
+                None => {
+                    let mut o = MergedOption { identifier, index: PerBuild::new(), versions: PerBuild::new() };
+                    o.index.push(build, i);
+                    o.versions.push(build, option);
+                    options.push(o);
+                }
+            }
+        }
+    }
+}
+
+impl<'a> MergedField<'a> {
+    fn new(build: &str, field: &'a Field) -> Self {
+        let mut merged = MergedField { versions: PerBuild::new(), block: None, options: None };
+        merged.add(build, field);
+        merged
+    }
+
+    fn add(&mut self, build: &str, field: &'a Field) {
+        self.versions.push(build, field);
+        match &field.block_type {
+            BlockFieldType::Block(block) => match &mut self.block {
+                Some(merged) => merged.add(build, block),
+                None => self.block = Some(MergedBlock::new(build, block))
+            },
+            BlockFieldType::Enum(options) => MergedOption::merge(self.options.get_or_insert_with(Vec::new), build, options, "option"),
+            BlockFieldType::Flags(_, bits) => MergedOption::merge(self.options.get_or_insert_with(Vec::new), build, bits, "bit"),
+            _ => ()
+        }
+    }
+
+    fn key(&self) -> (String, String) {
+        field_key(self.versions.latest())
+    }
+}
+
+impl<'a> MergedBlock<'a> {
+    fn empty() -> Self {
+        MergedBlock {
+            name: PerBuild::new(),
+            maximum: PerBuild::new(),
+            length: PerBuild::new(),
+            layout_fingerprint: PerBuild::new(),
+            fingerprint: PerBuild::new(),
+            fields: Vec::new()
+        }
+    }
+
+    fn new(build: &str, block: &'a Block) -> Self {
+        let mut merged = MergedBlock::empty();
+        merged.add(build, block);
+        merged
+    }
+
+    fn add(&mut self, build: &str, block: &'a Block) {
+        self.name.push(build, block.name.clone());
+        self.maximum.push(build, block.maximum);
+        self.length.push(build, block.length);
+        self.layout_fingerprint.push(build, block.layout_fingerprint.clone());
+        self.fingerprint.push(build, block.fingerprint.clone());
+
+        let keys: Vec<(String, String)> = self.fields.iter().map(MergedField::key).collect();
+        let new_keys: Vec<(String, String)> = block.fields.iter().map(field_key).collect();
+        let mut old_fields: Vec<Option<MergedField<'a>>> = std::mem::take(&mut self.fields).into_iter().map(Some).collect();
+
+        for a in align(&keys, &new_keys) {
+            match a {
+                Alignment::Matched(o, n) => {
+                    let mut field = old_fields[o].take().unwrap();
+                    field.add(build, &block.fields[n]);
+                    self.fields.push(field);
+                },
+
+                // Fields of the same type taking the place of one another are treated as renamed
+                Alignment::Unmatched(removed, inserted) => {
+                    let mut inserted = inserted.into_iter().peekable();
+                    for o in removed {
+                        let mut field = old_fields[o].take().unwrap();
+                        if let Some(&n) = inserted.peek() {
+                            if type_name(&field.versions.latest().block_type) == type_name(&block.fields[n].block_type) {
+                                field.add(build, &block.fields[n]);
+                                inserted.next();
+                            }
+                        }
+                        self.fields.push(field);
+                    }
+                    for n in inserted {
+                        self.fields.push(MergedField::new(build, &block.fields[n]));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Get a name for each build, using their file version if it's unique and their SHA-256 hash otherwise
+fn build_ids(definitions: &[Definitions]) -> Vec<String> {
+    definitions.iter().map(|d| {
+        let sha = d.exe_sha256sum.chars().take(12).collect::<String>();
+        match &d.exe_pe_file_version {
+            Some(v) if definitions.iter().filter(|o| o.exe_pe_file_version.as_ref() == Some(v)).count() == 1 => v.to_owned(),
+            Some(v) => format!("{}-{}", v, sha),
+            None => sha
+        }
+    }).collect()
+}
+
+/// Merge definitions from several builds, oldest first
+pub fn merge_definitions(definitions: &[Definitions]) -> Option<MergedDefinitions<'_>> {
+    let ids = build_ids(definitions);
+    for (i, d) in definitions.iter().enumerate() {
+        if definitions[..i].iter().any(|o| o.exe_sha256sum == d.exe_sha256sum) {
+            eprintln!("Definitions for {} were given more than once", ids[i]);
+            return None
+        }
+    }
+
+    let mut groups = BTreeMap::<String, MergedGroup>::new();
+    for (build, d) in ids.iter().zip(definitions.iter()) {
+        for (name, group) in &d.groups {
+            let merged = groups.entry(name.to_owned()).or_insert_with(|| MergedGroup {
+                builds: Vec::new(),
+                supergroup: PerBuild::new(),
+                fourcc: PerBuild::new(),
+                version: PerBuild::new(),
+                block: MergedBlock::empty()
+            });
+            merged.builds.push(build.to_owned());
+            merged.supergroup.push(build, group.supergroup.clone());
+            merged.fourcc.push(build, group.fourcc);
+            merged.version.push(build, group.version);
+            merged.block.add(build, &group.block);
+        }
+    }
+
+    Some(MergedDefinitions {
+        dumper_version: env!("gorilla_version").to_owned(),
+        builds: ids.iter().zip(definitions.iter()).map(|(id, d)| Build {
+            id: id.to_owned(),
+            exe_sha256sum: d.exe_sha256sum.clone(),
+            exe_pe_file_version: d.exe_pe_file_version.clone(),
+            exe_pe_creation_date: d.exe_pe_creation_date.clone(),
+            exe_pe_checksum: d.exe_pe_checksum
+        }).collect(),
+        groups
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::diff::tests::changed_definitions;
+    use tag_file::tests::definitions;
+
+    fn builds() -> Vec<Definitions> {
+        let mut old = definitions();
+        old.exe_sha256sum = "0123456789abcdef".to_owned();
+        old.exe_pe_file_version = Some("1.0".to_owned());
+        let mut new = changed_definitions();
+        new.exe_sha256sum = "fedcba9876543210".to_owned();
+        new.exe_pe_file_version = Some("2.0".to_owned());
+        vec![old, new]
+    }
+
+    #[test]
+    fn merge_builds() {
+        let builds = builds();
+        let merged = serde_json::to_value(merge_definitions(&builds).unwrap()).unwrap();
+        assert_eq!(merged["builds"][1]["id"], "2.0");
+
+        // Values the same in every build are written once
+        let model = &merged["groups"]["model"];
+        assert_eq!(model["version"], 4);
+        assert_eq!(model["block"]["fields"][0]["builds"], serde_json::json!(["1.0", "2.0"]));
+
+        let weapon = &merged["groups"]["weapon"];
+        assert_eq!(weapon["version"], serde_json::json!({"1.0": 2, "2.0": 3}));
+        assert_eq!(weapon["fourcc"], serde_json::json!({"1.0": 2003134832, "2.0": 0x67756E21}));
+
+        let fields = &weapon["block"]["fields"];
+        assert_eq!(fields[2]["options"][2]["name"], "shotgun");
+        assert_eq!(fields[2]["options"][2]["builds"], serde_json::json!(["2.0"]));
+        assert_eq!(fields[4]["name"], "fire rate");
+        assert_eq!(fields[4]["names"], serde_json::json!({"1.0": "rate", "2.0": "fire rate"}));
+        assert_eq!(fields[7]["block"]["maximum"], serde_json::json!({"1.0": 2, "2.0": 4}));
+        assert_eq!(fields[11]["types"], serde_json::json!({"1.0": "int16", "2.0": "int32"}));
+    }
+
+    #[test]
+    fn same_build_twice() {
+        let builds = builds();
+        assert!(merge_definitions(&[builds[0].clone(), builds[0].clone()]).is_none());
+    }
+}
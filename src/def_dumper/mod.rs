@@ -10,7 +10,19 @@ extern crate sha2;
 use self::sha2::{Sha256, Digest};
 
 mod block;
-use self::block::*;
+pub use self::block::*;
+
+pub mod diff;
+pub mod changelog;
+pub mod docs;
+pub mod merge;
+pub mod block_names;
+pub mod path_id;
+pub mod annotations;
+pub mod lint;
+pub mod fingerprint;
+pub mod schema;
+pub mod invader;
 
 #[macro_use]
 mod signature_scan;
@@ -39,15 +51,96 @@ fn read_string(data : &[u8]) -> Option<String> {
     Some(CString::from_vec_with_nul(data[..data.iter().position(|&b| b == 0)?+1].to_vec()).unwrap().to_str().unwrap().to_owned())
 }
 
-#[derive(serde::Serialize)]
-struct Group {
-    supergroup: Option<String>,
-    fourcc: u32,
-    block: Block
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Group {
+    pub supergroup: Option<String>,
+    pub fourcc: u32,
+    #[serde(default)]
+    pub version: u16,
+    pub block: Block
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Definitions {
+    #[serde(default)]
+    pub dumper_version: String,
+    pub exe_sha256sum: String,
+    pub exe_pe_file_version: Option<String>,
+    #[serde(default)]
+    pub exe_pe_creation_date: String,
+    pub exe_pe_checksum: u32,
+    pub groups: BTreeMap<String, Group>
+}
+impl Definitions {
+    /// Find a group by its FourCC
+    pub fn group_by_fourcc(&self, fourcc: u32) -> Option<(&String, &Group)> {
+        self.groups.iter().find(|(_,g)| g.fourcc == fourcc)
+    }
+}
+
+/// Read definitions from either a guerilla.exe or a JSON dump of one
+pub fn read_definitions(file_data: &[u8]) -> Option<Definitions> {
+    if file_data.starts_with(b"MZ") {
+        dump_definitions(file_data, None)
+    }
+    else {
+        match serde_json::from_slice(file_data) {
+            Ok(mut n) => {
+                // Older dumps don't have path IDs, and fingerprints may be out of date if the dump was edited
+                path_id::assign_path_ids(&mut n);
+                fingerprint::assign_fingerprints(&mut n);
+                Some(n)
+            },
+            Err(e) => {
+                eprintln!("Can't parse definitions: {}", e);
+                None
+            }
+        }
+    }
 }
 
 /// Dump all definitions into a JSON
-pub fn dump_definitions_into_json(file_data: &[u8]) -> Option<Vec<u8>> {
+pub fn dump_definitions_into_json(file_data: &[u8], annotations: Option<&annotations::Annotations>) -> Option<Vec<u8>> {
+    serde_json::to_vec_pretty(&dump_definitions(file_data, annotations)?).ok()
+}
+
+/// Top-level keys of a dump that can change without the definitions changing, which can be left out of canonical JSON
+pub const VOLATILE_KEYS: &[&str] = &["dumper_version", "exe_pe_creation_date"];
+
+/// Sort the keys of every object
+fn sort_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<(String, serde_json::Value)> = map.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            serde_json::Value::Object(entries.into_iter().map(|(k, v)| (k, sort_keys(v))).collect())
+        },
+        serde_json::Value::Array(array) => serde_json::Value::Array(array.into_iter().map(sort_keys).collect()),
+        n => n
+    }
+}
+
+/// Convert definitions into canonical JSON
+///
+/// Keys are sorted, indentation is two spaces, and there is a line break at the end, so the same definitions always
+/// give the same bytes. If `omit_volatile` is set, [`VOLATILE_KEYS`] are left out.
+pub fn definitions_into_canonical_json(definitions: &Definitions, omit_volatile: bool) -> Vec<u8> {
+    let mut value = serde_json::to_value(definitions).unwrap();
+    if omit_volatile {
+        if let serde_json::Value::Object(map) = &mut value {
+            for k in VOLATILE_KEYS {
+                map.remove(*k);
+            }
+        }
+    }
+
+    let mut json = serde_json::to_vec_pretty(&sort_keys(value)).unwrap();
+    json.push(b'\n');
+    json
+}
+
+/// Dump all definitions, applying any annotations to them
+pub fn dump_definitions(file_data: &[u8], annotations: Option<&annotations::Annotations>) -> Option<Definitions> {
     let pe_data = get_win32_exe_sections(file_data)?;
 
     let pe_sections = &pe_data.sections;
@@ -225,7 +318,7 @@ pub fn dump_definitions_into_json(file_data: &[u8]) -> Option<Vec<u8>> {
                             0x24 | 0x25 => {
                                 let tag_data_info = &file_data[translate_ptr(pe_sections, alt)?..];
                                 let block = read_string(&file_data[translate_ptr(pe_sections, LittleEndian::read_u32(&tag_data_info))?..])?;
-                                BlockFieldType::Index(block, "no-name".to_owned())
+                                BlockFieldType::Index(block, if n == 0x24 { "int16" } else { "int32" })
                             },
 
                             0x26 => {
@@ -234,18 +327,19 @@ pub fn dump_definitions_into_json(file_data: &[u8]) -> Option<Vec<u8>> {
                                 BlockFieldType::TagData(name, LittleEndian::read_u32(&tag_data_info[8..]) as usize)
                             },
 
-                            0x27 => BlockFieldType::PrimitiveArray("int32", alt as usize),
-                            0x28 => BlockFieldType::Padding("int32", 1),
+                            0x27 => BlockFieldType::ArrayStart(alt as usize),
+                            0x28 => BlockFieldType::ArrayEnd,
                             0x29 => BlockFieldType::Padding("int8", alt as usize),
                             0x2A => BlockFieldType::Padding("int8", alt as usize),
 
                             0x2B => BlockFieldType::Section(read_string(&file_data[translate_ptr(pe_sections, alt)?..])?),
 
-                            0x2C => BlockFieldType::Padding("int16", 1),
+                            0x2C => BlockFieldType::Custom,
 
                             n => BlockFieldType::Unknown(n, alt)
                         };
                         b.fields.push(Field {
+                            id: String::new(),
                             name : match name {
                                 Some(n) => Some(FieldName::new(&n)),
                                 None => None
@@ -272,22 +366,13 @@ pub fn dump_definitions_into_json(file_data: &[u8]) -> Option<Vec<u8>> {
                 n => Some(group_fourccs.get(&n)?.to_owned())
             },
             fourcc: LittleEndian::read_u32(&group_struct[8..]),
+            version: LittleEndian::read_u16(&group_struct[0x10..]),
             block: recursively_parse_block(block_struct, file_data, pe_sections, &group_fourccs, use_old_offsets)?
         });
     }
 
-    #[derive(serde::Serialize)]
-    struct FinalJSONOutput {
-        dumper_version: String,
-        exe_sha256sum: String,
-        exe_pe_file_version: Option<String>,
-        exe_pe_creation_date: String,
-        exe_pe_checksum: u32,
-        groups: BTreeMap<String, Group>
-    }
-
-    // Make the JSON output
-    match serde_json::to_vec_pretty(&FinalJSONOutput {
+    // Done
+    let mut definitions = Definitions {
         dumper_version: env!("gorilla_version").to_owned(),
         exe_sha256sum: {
             let mut sha256 = Sha256::new();
@@ -305,8 +390,20 @@ pub fn dump_definitions_into_json(file_data: &[u8]) -> Option<Vec<u8>> {
         exe_pe_creation_date: DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(pe_data.creation_date as i64, 0), Utc).format("%Y-%m-%dT%T").to_string(),
         exe_pe_checksum: pe_data.checksum,
         groups: group_blocks
-    }) {
-        Ok(n) => Some(n),
-        Err(_) => None
+    };
+
+    // Older builds don't have block names
+    if use_old_offsets {
+        block_names::synthesize_block_names(&mut definitions, None);
     }
+    path_id::assign_path_ids(&mut definitions);
+
+    if let Some(annotations) = annotations {
+        for w in annotations::apply_annotations(&mut definitions, annotations) {
+            eprintln!("Warning: {}", w);
+        }
+    }
+    fingerprint::assign_fingerprints(&mut definitions);
+
+    Some(definitions)
 }
@@ -0,0 +1,267 @@
+//! Path IDs for pointing at anything in the definitions
+//!
+//! Every group, block, field, enum option and bit gets an ID made from the identifiers leading to it:
+//!
+//! - A group's ID is its name, such as `weapon`. This is also the ID of the group's block.
+//! - A field's ID is the ID of its block followed by a period and its identifier, such as `weapon.triggers`. A block in
+//!   a block field has the same ID as the field.
+//! - Fields between an array start and its end are in the array, such as `weapon.vals.x`.
+//! - Unnamed fields (padding, array ends, etc.) are named after the nearest named field before them and their type,
+//!   such as `weapon.type+padding`, or just their type if there isn't one.
+//! - Enum options and bits are the ID of their field followed by a colon and their identifier, such as
+//!   `weapon.type:rifle`. Unnamed options are `option_` or `bit_` followed by their index.
+//!
+//! Fields with the same ID in a block get a number appended in order of appearance (e.g. `weapon.unknown_2`), so an ID
+//! only changes if a field is renamed or moved past a field with the same name. IDs that are already there are kept,
+//! and new IDs get a number appended if they're already taken.
+
+use super::{Block, BlockFieldType, Definitions, Field, FieldName, option_identifiers};
+
+/// Get a name for an unnamed field based on its type
+fn unnamed_kind(block_type: &BlockFieldType) -> &'static str {
+    match block_type {
+        BlockFieldType::Padding(_, _) => "padding",
+        BlockFieldType::Section(_) => "section",
+        BlockFieldType::ArrayStart(_) => "array",
+        BlockFieldType::ArrayEnd => "array_end",
+        BlockFieldType::Custom => "custom",
+        BlockFieldType::Unknown(_, _) => "unknown",
+        _ => "unnamed"
+    }
+}
+
+/// Get the IDs of each field in a block, relative to the block
+pub fn field_ids(fields: &[Field]) -> Vec<String> {
+    let mut ids = Vec::<String>::with_capacity(fields.len());
+
+    // Each array is a container with its own anchors, with the outermost being the block itself
+    let mut containers = vec![(String::new(), None::<String>)];
+    for field in fields {
+        // Array ends belong to the container the array is in
+        if let (BlockFieldType::ArrayEnd, true) = (&field.block_type, containers.len() > 1) {
+            containers.pop();
+        }
+
+        let identifier = field.name.as_ref().map(FieldName::identifier).filter(|i| !i.is_empty());
+        let (prefix, anchor) = containers.last().unwrap().clone();
+        let base = match (&identifier, &anchor) {
+            (Some(i), _) => format!("{}{}", prefix, i),
+            (None, Some(a)) => format!("{}{}+{}", prefix, a, unnamed_kind(&field.block_type)),
+            (None, None) => format!("{}{}", prefix, unnamed_kind(&field.block_type))
+        };
+
+        let mut id = base.clone();
+        let mut number = 2;
+        while ids.contains(&id) {
+            id = format!("{}_{}", base, number);
+            number += 1;
+        }
+
+        match &field.block_type {
+            BlockFieldType::ArrayStart(_) => {
+                containers.last_mut().unwrap().1 = Some(id[prefix.len()..].to_owned());
+                containers.push((format!("{}.", id), None));
+            },
+            _ => if identifier.is_some() {
+                containers.last_mut().unwrap().1 = Some(id[prefix.len()..].to_owned());
+            }
+        }
+
+        ids.push(id);
+    }
+    ids
+}
+
+/// Take the IDs that are already there, clearing any that were taken before them
+fn keep_ids<'a>(ids: impl Iterator<Item = &'a mut String>) -> Vec<String> {
+    let mut used = Vec::<String>::new();
+    for id in ids.filter(|i| !i.is_empty()) {
+        if used.contains(id) {
+            id.clear();
+        }
+        else {
+            used.push(id.clone());
+        }
+    }
+    used
+}
+
+/// Make an ID unique by appending a number if it's taken, and take it
+fn take_id(base: String, used: &mut Vec<String>) -> String {
+    let mut id = base.clone();
+    let mut number = 2;
+    while used.contains(&id) {
+        id = format!("{}_{}", base, number);
+        number += 1;
+    }
+    used.push(id.clone());
+    id
+}
+
+fn assign_options(options: &mut [FieldName], field_id: &str, fallback: &str) {
+    let identifiers = option_identifiers(options, fallback);
+    let mut used = keep_ids(options.iter_mut().map(|o| &mut o.id));
+    for (o, identifier) in options.iter_mut().zip(identifiers) {
+        if o.id.is_empty() {
+            o.id = take_id(format!("{}:{}", field_id, identifier), &mut used);
+        }
+    }
+}
+
+fn assign_block(block: &mut Block, id: &str) {
+    if block.id.is_empty() {
+        block.id = id.to_owned();
+    }
+    let ids = field_ids(&block.fields);
+    let mut used = keep_ids(block.fields.iter_mut().map(|f| &mut f.id));
+    for (field, field_id) in block.fields.iter_mut().zip(ids) {
+        if field.id.is_empty() {
+            field.id = take_id(format!("{}.{}", id, field_id), &mut used);
+        }
+        match &mut field.block_type {
+            BlockFieldType::Block(b) => assign_block(b, &field.id),
+            BlockFieldType::Enum(options) => assign_options(options, &field.id, "option"),
+            BlockFieldType::Flags(_, bits) => assign_options(bits, &field.id, "bit"),
+            _ => ()
+        }
+    }
+}
+
+/// Give everything in the definitions that doesn't have a path ID one
+///
+/// IDs that are already there (such as from a JSON dump) are kept so they keep pointing at the same thing if fields
+/// have since been renamed. New IDs never take the ID of something else in the same block.
+pub fn assign_path_ids(definitions: &mut Definitions) {
+    for (name, group) in &mut definitions.groups {
+        assign_block(&mut group.block, name);
+    }
+}
+
+/// Returns true if `id` is the ID of a block or anything in it
+fn is_in(block_id: &str, id: &str) -> bool {
+    id.strip_prefix(block_id).is_some_and(|rest| rest.is_empty() || rest.starts_with('.') || rest.starts_with(':'))
+}
+
+impl Block {
+    /// Find a block in this block (or this block itself) by its path ID
+    pub fn find_block(&self, id: &str) -> Option<&Block> {
+        if self.id == id {
+            return Some(self)
+        }
+        self.fields.iter().find_map(|f| match &f.block_type {
+            BlockFieldType::Block(b) if is_in(&b.id, id) => b.find_block(id),
+            _ => None
+        })
+    }
+
+    /// Find a field in this block or any block in it by its path ID
+    pub fn find_field(&self, id: &str) -> Option<&Field> {
+        self.fields.iter().find_map(|f| match &f.block_type {
+            _ if f.id == id => Some(f),
+            BlockFieldType::Block(b) if is_in(&b.id, id) => b.find_field(id),
+            _ => None
+        })
+    }
+}
+
+impl Definitions {
+    /// Find a group's block or a block in a block field by its path ID
+    pub fn find_block(&self, id: &str) -> Option<&Block> {
+        let group = id.split('.').next()?;
+        self.groups.get(group)?.block.find_block(id)
+    }
+
+    /// Find a field by its path ID
+    pub fn find_field(&self, id: &str) -> Option<&Field> {
+        let group = id.split('.').next()?;
+        self.groups.get(group)?.block.find_field(id)
+    }
+
+    /// Find an enum option or bit by its path ID
+    pub fn find_option(&self, id: &str) -> Option<&FieldName> {
+        let (field_id, _) = id.split_once(':')?;
+        match &self.find_field(field_id)?.block_type {
+            BlockFieldType::Enum(options) | BlockFieldType::Flags(_, options) => options.iter().find(|o| o.id == id),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::serde_json::{self, Value, json};
+    use def_dumper::{definitions_into_canonical_json, read_definitions};
+    use tag_file::tests::{DEFINITIONS, definitions};
+
+    /// Get the IDs of every field, option and bit in a block and the blocks in it
+    fn all_ids(block: &Block, ids: &mut Vec<String>) {
+        for field in &block.fields {
+            ids.push(field.id.clone());
+            match &field.block_type {
+                BlockFieldType::Block(b) => all_ids(b, ids),
+                BlockFieldType::Enum(options) | BlockFieldType::Flags(_, options) => ids.extend(options.iter().map(|o| o.id.clone())),
+                _ => ()
+            }
+        }
+    }
+
+    fn weapon_ids(definitions: &Definitions) -> Vec<String> {
+        let mut ids = Vec::new();
+        all_ids(&definitions.groups["weapon"].block, &mut ids);
+        ids
+    }
+
+    fn read_json(json: &Value) -> Definitions {
+        read_definitions(&serde_json::to_vec(json).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn stable_across_dumps() {
+        let definitions = definitions();
+        let ids = weapon_ids(&definitions);
+        assert!(ids.contains(&"weapon.vals.x".to_owned()));
+        assert!(ids.contains(&"weapon.type+padding".to_owned()));
+        assert!(ids.contains(&"weapon.type:rifle".to_owned()));
+
+        let redumped = read_definitions(&definitions_into_canonical_json(&definitions, false)).unwrap();
+        assert_eq!(weapon_ids(&redumped), ids);
+    }
+
+    #[test]
+    fn stable_when_fields_are_inserted() {
+        let ids = weapon_ids(&definitions());
+
+        let mut json: Value = serde_json::from_str(DEFINITIONS).unwrap();
+        let fields = json["groups"]["weapon"]["block"]["fields"].as_array_mut().unwrap();
+        fields.insert(0, json!({"name": "charge", "type": "float"}));
+        fields.insert(6, json!({"type": "section", "text": "more stuff"}));
+        fields[9]["block"]["fields"].as_array_mut().unwrap().insert(0, json!({"name": "delay", "type": "float"}));
+        let inserted = weapon_ids(&read_json(&json));
+        for id in &ids {
+            assert!(inserted.contains(id), "{} is missing", id);
+        }
+    }
+
+    #[test]
+    fn kept_ids_are_not_reused() {
+        // A field renamed since the dump keeps its ID, so a new field with its old name gets another one
+        let mut json = serde_json::to_value(definitions()).unwrap();
+        let fields = json["groups"]["weapon"]["block"]["fields"].as_array_mut().unwrap();
+        fields[4]["name"] = json!("fire rate");
+        fields.insert(5, json!({"name": "rate", "type": "float"}));
+        fields[2]["options"].as_array_mut().unwrap().push(json!({"name": "rifle"}));
+
+        let definitions = read_json(&json);
+        let block = &definitions.groups["weapon"].block;
+        assert_eq!(block.fields[4].id, "weapon.rate");
+        assert_eq!(block.fields[5].id, "weapon.rate_2");
+        assert_eq!(definitions.find_option("weapon.type:rifle_2").unwrap().name, "rifle");
+
+        let mut ids = weapon_ids(&definitions);
+        let count = ids.len();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), count);
+    }
+}
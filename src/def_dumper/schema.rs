@@ -0,0 +1,266 @@
+//! JSON Schema for the definitions gorilla writes
+//!
+//! Fields are objects with the keys of their name (if they have one) and a `type` key saying what else is there. Each
+//! value of `type` gets its own `if`/`then` so validators can point at the exact key that's wrong.
+//!
+//! The keys of fields are found by writing examples of them, so they can't get out of step with how fields are written.
+//! Merged definitions (see [`merge`](super::merge)) aren't covered.
+
+use super::serde_json::{self, Map, Value};
+use super::{Block, BlockFieldType, Field, FieldName, PRIMITIVE_TYPES};
+
+/// The draft of JSON Schema used
+pub const SCHEMA_DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Make an object from a list of keys and values
+pub fn object(entries: Vec<(&str, Value)>) -> Value {
+    Value::Object(entries.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+}
+
+fn typed(type_name: &str) -> Value {
+    object(vec![("type", Value::from(type_name))])
+}
+
+fn unsigned(maximum: u64) -> Value {
+    object(vec![("type", Value::from("integer")), ("minimum", Value::from(0)), ("maximum", Value::from(maximum))])
+}
+
+fn nullable(type_name: &str) -> Value {
+    object(vec![("type", Value::from(vec![type_name, "null"]))])
+}
+
+fn reference(definition: &str) -> Value {
+    object(vec![("$ref", Value::from(format!("#/$defs/{}", definition)))])
+}
+
+fn array_of(items: Value) -> Value {
+    object(vec![("type", Value::from("array")), ("items", items)])
+}
+
+fn primitive_names() -> Vec<&'static str> {
+    PRIMITIVE_TYPES.iter().map(|(n, _)| *n).collect()
+}
+
+/// Get the keys and values a field is written with
+fn written_keys(block_type: BlockFieldType, name: Option<FieldName>) -> Map<String, Value> {
+    match serde_json::to_value(Field { id: String::new(), name, block_type }) {
+        Ok(Value::Object(keys)) => keys,
+        _ => unreachable!()
+    }
+}
+
+/// Get the JSON Schema type of a value
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object"
+    }
+}
+
+/// The keys written for a [`FieldName`], found by writing one with everything set
+fn name_properties() -> Map<String, Value> {
+    // Every member is given rather than using Default so a new one can't be left out
+    let name = FieldName {
+        id: "id".to_owned(),
+        name: String::new(),
+        hidden: true,
+        read_only: true,
+        main: true,
+        description: Some(String::new()),
+        unit: Some(String::new()),
+        color: Some(String::new()),
+        notes: Some(String::new()),
+        deprecated: true
+    };
+    written_keys(BlockFieldType::Custom, Some(name)).into_iter()
+        .filter(|(k, _)| k != "type")
+        .map(|(k, v)| (k, typed(json_type(&v))))
+        .collect()
+}
+
+/// Get the schema of a key a field type is written with, given an example of its value
+fn key_schema(key: &str, example: &Value) -> Value {
+    match key {
+        "size" => object(vec![("enum", Value::from(primitive_names()))]),
+        "count" | "max_length" | "type_number" => unsigned(u32::MAX as u64),
+        "allowed_groups" => array_of(typed("string")),
+        "options" | "fields" => array_of(reference("option")),
+        "block" => reference("block"),
+        _ => match example {
+            Value::Bool(b) => object(vec![("const", Value::from(*b))]),
+            _ => typed(json_type(example))
+        }
+    }
+}
+
+/// Get the value of `type` a field type is written with, and the schemas of the other keys it's written with
+fn written_type(block_type: BlockFieldType) -> (String, Vec<(String, Value)>) {
+    let mut keys = written_keys(block_type, None);
+    let type_name = match keys.remove("type") {
+        Some(Value::String(t)) => t,
+        _ => unreachable!()
+    };
+    (type_name, keys.iter().map(|(k, v)| (k.clone(), key_schema(k, v))).collect())
+}
+
+/// Values of a field's `type`, along with the keys they need and the keys they can have besides `type`
+type FieldType = (Vec<String>, Vec<(String, Value)>, Vec<(String, Value)>);
+
+/// The keys each value of a field's `type` needs, found by writing an example of each field type
+fn field_types() -> Vec<FieldType> {
+    let examples = vec![
+        BlockFieldType::Padding("int8", 1),
+        BlockFieldType::Index(String::new(), "int16"),
+        BlockFieldType::TagData(String::new(), 0),
+        BlockFieldType::Section(String::new()),
+        BlockFieldType::Reference(Vec::new()),
+        BlockFieldType::ArrayStart(1),
+        BlockFieldType::ArrayEnd,
+        BlockFieldType::Custom,
+        BlockFieldType::Enum(Vec::new()),
+        BlockFieldType::Flags("int32", Vec::new()),
+        BlockFieldType::Block(Block::default()),
+        BlockFieldType::Unknown(0, 0)
+    ];
+    let mut types: Vec<FieldType> = examples.into_iter().map(|t| {
+        let (type_name, keys) = written_type(t);
+        (vec![type_name], keys, Vec::new())
+    }).collect();
+
+    // Primitives and ranges of them are written with the primitive's name as their type, so they share one
+    let names = primitive_names().into_iter().map(str::to_owned).collect();
+    let (_, keys) = written_type(BlockFieldType::Primitive("float"));
+    let (_, range_keys) = written_type(BlockFieldType::Range("float"));
+    let range_keys = range_keys.into_iter().filter(|(k, _)| !keys.iter().any(|(p, _)| p == k)).collect();
+    types.push((names, keys, range_keys));
+
+    types
+}
+
+fn field_schema() -> Value {
+    let types = field_types();
+    let mut properties = name_properties();
+    let all_types: Vec<&str> = types.iter().flat_map(|(t, _, _)| t.iter().map(String::as_str)).collect();
+    properties.insert("type".to_owned(), object(vec![("enum", Value::from(all_types))]));
+
+    let conditions: Vec<Value> = types.into_iter().map(|(type_names, keys, optional_keys)| {
+        let matches = match type_names.as_slice() {
+            [type_name] => object(vec![("const", Value::from(type_name.as_str()))]),
+            _ => object(vec![("enum", Value::from(type_names.clone()))])
+        };
+        let required: Vec<&str> = keys.iter().map(|(k, _)| k.as_str()).collect();
+        let then_properties: Map<String, Value> = keys.iter().chain(optional_keys.iter()).cloned().collect();
+
+        object(vec![
+            ("if", object(vec![("properties", object(vec![("type", matches)]))])),
+            ("then", object(vec![("properties", Value::Object(then_properties)), ("required", Value::from(required))]))
+        ])
+    }).collect();
+
+    object(vec![
+        ("type", Value::from("object")),
+        ("properties", Value::Object(properties)),
+        ("required", Value::from(vec!["type"])),
+        ("allOf", Value::from(conditions)),
+        ("unevaluatedProperties", Value::from(false))
+    ])
+}
+
+/// Get a JSON Schema for definitions as written by gorilla
+pub fn definitions_schema() -> Value {
+    let block = object(vec![
+        ("type", Value::from("object")),
+        ("properties", object(vec![
+            ("id", typed("string")),
+            ("name", nullable("string")),
+            ("name_synthesized", typed("boolean")),
+            ("maximum", unsigned(u32::MAX as u64)),
+            ("length", unsigned(u32::MAX as u64)),
+            ("layout_fingerprint", typed("string")),
+            ("fingerprint", typed("string")),
+            ("fields", array_of(reference("field")))
+        ])),
+        ("required", Value::from(vec!["maximum", "length", "fields"])),
+        ("additionalProperties", Value::from(false))
+    ]);
+
+    let group = object(vec![
+        ("type", Value::from("object")),
+        ("properties", object(vec![
+            ("supergroup", nullable("string")),
+            ("fourcc", unsigned(u32::MAX as u64)),
+            ("version", unsigned(u16::MAX as u64)),
+            ("block", reference("block"))
+        ])),
+        ("required", Value::from(vec!["fourcc", "block"])),
+        ("additionalProperties", Value::from(false))
+    ]);
+
+    let option = object(vec![
+        ("type", Value::from("object")),
+        ("properties", Value::Object(name_properties())),
+        ("required", Value::from(vec!["name"])),
+        ("additionalProperties", Value::from(false))
+    ]);
+
+    object(vec![
+        ("$schema", Value::from(SCHEMA_DRAFT)),
+        ("title", Value::from("Gorilla definitions")),
+        ("type", Value::from("object")),
+        ("properties", object(vec![
+            ("dumper_version", typed("string")),
+            ("exe_sha256sum", typed("string")),
+            ("exe_pe_file_version", nullable("string")),
+            ("exe_pe_creation_date", typed("string")),
+            ("exe_pe_checksum", unsigned(u32::MAX as u64)),
+            ("groups", object(vec![("type", Value::from("object")), ("additionalProperties", reference("group"))]))
+        ])),
+        ("required", Value::from(vec!["exe_sha256sum", "exe_pe_checksum", "groups"])),
+        ("additionalProperties", Value::from(false)),
+        ("$defs", object(vec![
+            ("group", group),
+            ("block", block),
+            ("field", field_schema()),
+            ("option", option)
+        ]))
+    ])
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use def_dumper::definitions_into_canonical_json;
+    use tag_file::tests::{definitions, temporary_directory};
+
+    /// Returns true if Python's jsonschema package says the value is valid
+    pub fn is_valid(schema: &Value, value: &Value, name: &str) -> bool {
+        let directory = temporary_directory(name);
+        std::fs::write(directory.join("schema.json"), schema.to_string()).unwrap();
+        std::fs::write(directory.join("value.json"), value.to_string()).unwrap();
+        let script = "import json, jsonschema\n\
+                      schema = json.load(open('schema.json'))\n\
+                      jsonschema.Draft202012Validator.check_schema(schema)\n\
+                      jsonschema.validate(json.load(open('value.json')), schema)\n";
+        Command::new("python3").args(["-c", script]).current_dir(&directory).output().unwrap().status.success()
+    }
+
+    #[test]
+    fn validate_dump() {
+        let schema = definitions_schema();
+        let mut dump: Value = serde_json::from_slice(&definitions_into_canonical_json(&definitions(), false)).unwrap();
+        assert!(is_valid(&schema, &dump, "schema_valid_dump"));
+
+        let fields = &mut dump["groups"]["weapon"]["block"]["fields"];
+        fields[3].as_object_mut().unwrap().remove("count");
+        assert!(!is_valid(&schema, &dump, "schema_missing_key"));
+        dump["groups"]["weapon"]["block"]["fields"][3]["count"] = Value::from(2);
+        dump["groups"]["weapon"]["block"]["fields"][7]["block"]["fields"][0]["type"] = Value::from("double");
+        assert!(!is_valid(&schema, &dump, "schema_bad_type"));
+    }
+}
@@ -0,0 +1,5 @@
+//! Gorilla - definition dumper and tag tooling for Halo: Combat Evolved's guerilla.exe
+
+pub mod def_dumper;
+pub mod tag_file;
+pub mod codegen;
@@ -1,50 +1,789 @@
 extern crate clap;
-use clap::Parser;
+use clap::{ArgEnum, Parser, Subcommand};
+
+extern crate serde_json;
 
 use std::fs::File;
 use std::io::{Read, Write};
 
-mod def_dumper;
+extern crate gorilla;
+use gorilla::def_dumper::{self, Definitions};
+use gorilla::tag_file::{self, TagFile, StructLayout};
+use gorilla::codegen::{self, Codegen};
+use gorilla::tag_file::directory::TagDirectory;
+use gorilla::tag_file::dependencies::DependencyScanner;
 
-fn main() {
-    #[derive(Parser)]
-    #[clap(version, about)]
-    struct Args {
-        exe_path: String,
+#[derive(Parser)]
+#[clap(version, about, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Args {
+    /// Path to guerilla.exe
+    #[clap(required = true)]
+    exe_path: Option<String>,
+
+    /// Path to write the definitions to
+    #[clap(required = true)]
+    output_json: Option<String>,
+
+    /// Annotations file (JSON or TOML) to apply to the definitions
+    #[clap(long)]
+    annotations: Option<String>,
+
+    /// Write canonical JSON with sorted keys, so dumps of the same exe are always the same
+    #[clap(long)]
+    canonical: bool,
+
+    /// Leave out fields that can change between dumps of the same exe, such as the dumper version (implies --canonical)
+    #[clap(long)]
+    omit_volatile: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compare two sets of definitions
+    Diff {
+        /// Old definitions (guerilla.exe or a JSON dump)
+        old_definitions: String,
+
+        /// New definitions (guerilla.exe or a JSON dump)
+        new_definitions: String,
+
+        /// Output JSON instead of text
+        #[clap(long)]
+        json: bool
+    },
+
+    /// Write a Markdown changelog for each group that changed between two sets of definitions
+    Changelog {
+        /// Old definitions (guerilla.exe or a JSON dump)
+        old_definitions: String,
+
+        /// New definitions (guerilla.exe or a JSON dump)
+        new_definitions: String,
+
+        /// Directory to write the changelog to
+        output_directory: String
+    },
+
+    /// Write an HTML site documenting every group
+    Docs {
+        /// Definitions to document (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Directory to write the site to
+        output_directory: String
+    },
+
+    /// Merge definitions from several builds into one set, noting which builds everything is in
+    Merge {
+        /// Definitions to merge (guerilla.exe or JSON dumps), oldest first
+        #[clap(required = true, min_values = 2)]
+        definitions: Vec<String>,
+
+        /// Path to write the merged definitions to
+        #[clap(long, short)]
+        output: String
+    },
+
+    /// Name blocks that don't have names, such as in dumps of older builds
+    NameBlocks {
+        /// Definitions to name the blocks of (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Path to write the definitions to
+        output_json: String,
+
+        /// Definitions of a newer build to take names from where blocks match
+        #[clap(long)]
+        reference: Option<String>
+    },
+
+    /// Find suspicious structures in definitions, such as duplicate names and blocks with the wrong size
+    Lint {
+        /// Definitions to check (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Output JSON instead of text
+        #[clap(long)]
+        json: bool
+    },
+
+    /// Write definitions in Invader's definition JSON format, one file per group
+    InvaderExport {
+        /// Definitions to export (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Directory to write the files to
+        output_directory: String
+    },
+
+    /// Compare a directory of Invader definition JSON files with a set of definitions
+    InvaderCompare {
+        /// Definitions to compare with (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Directory of Invader definition JSON files
+        invader_directory: String,
+
+        /// Output JSON instead of text
+        #[clap(long)]
+        json: bool
+    },
+
+    /// Generate code for reading and writing tags
+    Codegen {
+        /// Language to generate
+        #[clap(arg_enum)]
+        language: Language,
+
+        /// Definitions to generate code for (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Path to write the code to
+        output: String
+    },
+
+    /// Write a JSON Schema for definitions as written by gorilla
+    Schema {
+        /// Path to write the schema to
+        output: String
+    },
+
+    /// Write a JSON Schema for each group's tags converted to JSON
+    TagSchema {
+        /// Definitions to write schemas for (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Directory to write the schemas to, as <group>.schema.json
+        output_directory: String
+    },
+
+    /// Write a Kaitai Struct specification for each group's tag files
+    Kaitai {
+        /// Definitions to write specifications for (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Directory to write the specifications to, as <group>.ksy
+        output_directory: String
+    },
+
+    /// Write a Reclaimer definition module for each group, as used by Mozzarilla
+    Reclaimer {
+        /// Definitions to write modules for (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Directory to write the modules to, as <fourcc>.py along with __init__.py
+        output_directory: String
+    },
+
+    /// Rewrite definitions as canonical JSON with sorted keys
+    Canonicalize {
+        /// Definitions to rewrite (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Path to write the definitions to
+        output_json: String,
+
+        /// Leave out fields that can change between dumps of the same exe, such as the dumper version
+        #[clap(long)]
+        omit_volatile: bool
+    },
+
+    /// Apply an annotations file (JSON or TOML) to definitions
+    Annotate {
+        /// Definitions to annotate (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Annotations file
+        annotations: String,
+
+        /// Path to write the definitions to
         output_json: String
+    },
+
+    /// Generate a tag with default values
+    NewTag {
+        /// Definitions to use (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Group of the tag (e.g. weapon)
+        group: String,
+
+        /// Path to write the tag to
+        output_tag: String,
+
+        /// JSON file with values to use instead of the defaults
+        #[clap(long)]
+        overrides: Option<String>
+    },
+
+    /// Compare two tags field by field
+    TagDiff {
+        /// Definitions to use (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Tag to compare from
+        old_tag: String,
+
+        /// Tag to compare to
+        new_tag: String,
+
+        /// Output JSON instead of text
+        #[clap(long)]
+        json: bool
+    },
+
+    /// Find the dependencies of tags, references to missing tags, and tags nothing references
+    TagDeps {
+        /// Definitions to use (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Tags directory
+        tags_directory: String,
+
+        /// Tag to list the dependency tree of, relative to the tags directory (e.g. weapons\pistol\pistol.weapon)
+        root_tag: Option<String>,
+
+        /// List references to tags that don't exist (in the root tag's tree, or in every tag if no root tag is given)
+        #[clap(long)]
+        missing: bool,
+
+        /// List tags that aren't referenced by any other tag
+        #[clap(long, conflicts_with = "missing")]
+        orphans: bool,
+
+        /// Output JSON instead of text
+        #[clap(long)]
+        json: bool
+    },
+
+    /// Rename or move a tag or a folder of tags, rewriting every reference to them
+    TagRename {
+        /// Definitions to use (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Tags directory
+        tags_directory: String,
+
+        /// Tag (with its extension) or folder to move, relative to the tags directory
+        from: String,
+
+        /// Where to move it to, relative to the tags directory
+        to: String,
+
+        /// List what would be changed without changing anything
+        #[clap(long)]
+        dry_run: bool
+    },
+
+    /// Upgrade tags made with one guerilla build to the definitions of another
+    TagMigrate {
+        /// Definitions the tags were made with (guerilla.exe or a JSON dump)
+        old_definitions: String,
+
+        /// Definitions to upgrade the tags to (guerilla.exe or a JSON dump)
+        new_definitions: String,
+
+        /// Tag or tags directory to upgrade
+        input: String,
+
+        /// Path to write the upgraded tag or tags directory to
+        output: String
+    },
+
+    /// Export field values of every tag of a group to CSV
+    CsvExport {
+        /// Definitions to use (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Tags directory
+        tags_directory: String,
+
+        /// Group of the tags (e.g. weapon)
+        group: String,
+
+        /// Paths of the fields to export (e.g. triggers[0].rounds_per_second)
+        #[clap(required = true)]
+        fields: Vec<String>,
+
+        /// Path to write the CSV to (instead of printing it)
+        #[clap(long, short)]
+        output: Option<String>
+    },
+
+    /// Apply field values from a CSV back to tags
+    CsvImport {
+        /// Definitions to use (guerilla.exe or a JSON dump)
+        definitions: String,
+
+        /// Tags directory
+        tags_directory: String,
+
+        /// CSV to import, in the format written by csv-export
+        csv: String,
+
+        /// List what would be changed without changing anything
+        #[clap(long)]
+        dry_run: bool
     }
+}
 
-    let args = Args::parse();
+#[derive(ArgEnum, Clone, Copy)]
+enum Language {
+    /// C/C++ header
+    C,
+
+    /// Rust module
+    Rust,
+
+    /// Python module
+    Python,
+
+    /// TypeScript declarations for tags converted to JSON
+    Typescript,
+
+    /// ImHex pattern for tag files
+    Imhex,
+
+    /// 010 Editor binary template for tag files
+    #[clap(name = "010-editor")]
+    Editor010
+}
+
+/// Read a file, exiting if it can't be read
+fn read_file(path: &str) -> Vec<u8> {
     let mut file_data = Vec::new();
+    if let Err(n) = |path: &str, file_data: &mut Vec<u8>| -> std::io::Result<usize> {
+        File::open(path)?.read_to_end(file_data)
+    }(path, &mut file_data) {
+        eprintln!("Can't read {}: {}", path, n);
+        std::process::exit(1);
+    };
+    file_data
+}
 
-    // Read the file
-    match |exe_path: &str, file_data: &mut Vec<u8>| -> std::io::Result<usize> {
-        File::open(exe_path)?.read_to_end(file_data)
-    }(&args.exe_path, &mut file_data) {
-        Err(n) => {
-            eprintln!("Can't read {}: {}", args.exe_path, n);
+/// Write a file, exiting if it can't be written
+fn write_file(path: &str, data: &[u8]) {
+    if let Err(n) = |path: &str| -> std::io::Result<()> {
+        File::create(path)?.write_all(data)
+    }(path) {
+        eprintln!("Can't write {}: {}", path, n);
+        std::process::exit(1);
+    }
+}
+
+/// Read definitions from an exe or a JSON dump, exiting if they can't be read
+fn load_definitions(path: &str) -> Definitions {
+    match def_dumper::read_definitions(&read_file(path)) {
+        Some(n) => n,
+        None => {
+            eprintln!("Failed to get definitions from {}", path);
             std::process::exit(1);
-        },
-        _ => ()
-    };
+        }
+    }
+}
 
-    // Make the json
-    let json = match def_dumper::dump_definitions_into_json(&file_data) {
+/// Read a JSON file, exiting if it can't be parsed
+fn read_json(path: &str) -> serde_json::Value {
+    match serde_json::from_slice(&read_file(path)) {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("Can't parse {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Read a tag file, exiting if it can't be read
+fn load_tag(definitions: &Definitions, path: &str) -> TagFile {
+    match TagFile::read(definitions, &read_file(path)) {
         Some(n) => n,
         None => {
-            eprintln!("Failed! The exe might not be correct.");
+            eprintln!("Failed to read {}", path);
             std::process::exit(1);
         }
-    };
+    }
+}
 
-    // Write the json
-    match |json_path: &str| -> std::io::Result<()> {
-        File::create(json_path)?.write_all(&json)
-    }(&args.output_json) {
-        Err(n) => {
-            eprintln!("Can't write {}: {}", args.output_json, n);
+/// Exit with a message if something failed
+fn or_exit<T>(what: Option<T>, message: &str) -> T {
+    match what {
+        Some(n) => n,
+        None => {
+            eprintln!("{}", message);
             std::process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    match args.command {
+        Some(Command::Diff { old_definitions, new_definitions, json }) => {
+            let old_definitions = load_definitions(&old_definitions);
+            let new_definitions = load_definitions(&new_definitions);
+            let groups = def_dumper::diff::diff_definitions(&old_definitions, &new_definitions);
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&groups).unwrap());
+            }
+            else {
+                for (group, changes) in groups {
+                    println!("{}", group);
+                    for c in changes {
+                        println!("    {}", c);
+                    }
+                }
+            }
+        },
+
+        Some(Command::Changelog { old_definitions, new_definitions, output_directory }) => {
+            let old_definitions = load_definitions(&old_definitions);
+            let new_definitions = load_definitions(&new_definitions);
+            let groups = def_dumper::diff::diff_definitions(&old_definitions, &new_definitions);
+
+            if let Err(e) = std::fs::create_dir_all(&output_directory) {
+                eprintln!("Can't create {}: {}", output_directory, e);
+                std::process::exit(1);
+            }
+            for (name, contents) in def_dumper::changelog::changelog(&old_definitions, &new_definitions, &groups) {
+                write_file(&std::path::Path::new(&output_directory).join(name).to_string_lossy(), contents.as_bytes());
+            }
+        },
+
+        Some(Command::Docs { definitions, output_directory }) => {
+            let definitions = load_definitions(&definitions);
+
+            if let Err(e) = std::fs::create_dir_all(&output_directory) {
+                eprintln!("Can't create {}: {}", output_directory, e);
+                std::process::exit(1);
+            }
+            for (name, contents) in def_dumper::docs::docs(&definitions) {
+                write_file(&std::path::Path::new(&output_directory).join(name).to_string_lossy(), contents.as_bytes());
+            }
+        },
+
+        Some(Command::Merge { definitions, output }) => {
+            let definitions: Vec<Definitions> = definitions.iter().map(|d| load_definitions(d)).collect();
+            let merged = or_exit(def_dumper::merge::merge_definitions(&definitions), "Failed to merge the definitions.");
+            write_file(&output, serde_json::to_string_pretty(&merged).unwrap().as_bytes());
+        },
+
+        Some(Command::NameBlocks { definitions, output_json, reference }) => {
+            let mut definitions = load_definitions(&definitions);
+            let reference = reference.map(|r| load_definitions(&r));
+            let count = def_dumper::block_names::synthesize_block_names(&mut definitions, reference.as_ref());
+            write_file(&output_json, serde_json::to_string_pretty(&definitions).unwrap().as_bytes());
+            println!("Named {} block(s)", count);
+        },
+
+        Some(Command::Lint { definitions, json }) => {
+            let definitions = load_definitions(&definitions);
+            let groups = def_dumper::lint::lint_definitions(&definitions);
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&groups).unwrap());
+            }
+            else {
+                for (group, issues) in &groups {
+                    println!("{}", group);
+                    for i in issues {
+                        println!("    {}", i);
+                    }
+                }
+                println!("{} issue(s) found", groups.values().map(Vec::len).sum::<usize>());
+            }
+        },
+
+        Some(Command::InvaderExport { definitions, output_directory }) => {
+            let definitions = load_definitions(&definitions);
+
+            if let Err(e) = std::fs::create_dir_all(&output_directory) {
+                eprintln!("Can't create {}: {}", output_directory, e);
+                std::process::exit(1);
+            }
+            for (name, contents) in def_dumper::invader::export_definitions(&definitions) {
+                let path = std::path::Path::new(&output_directory).join(name);
+                write_file(&path.to_string_lossy(), serde_json::to_string_pretty(&contents).unwrap().as_bytes());
+            }
+        },
+
+        Some(Command::InvaderCompare { definitions, invader_directory, json }) => {
+            let definitions = load_definitions(&definitions);
+
+            let mut paths: Vec<std::path::PathBuf> = match std::fs::read_dir(&invader_directory) {
+                Ok(n) => n.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.extension().map(|e| e == "json").unwrap_or(false)).collect(),
+                Err(e) => {
+                    eprintln!("Can't read {}: {}", invader_directory, e);
+                    std::process::exit(1);
+                }
+            };
+            paths.sort();
+            let files: Vec<serde_json::Value> = paths.iter().map(|p| {
+                let path = p.to_string_lossy();
+                match serde_json::from_slice(&read_file(&path)) {
+                    Ok(n) => n,
+                    Err(e) => {
+                        eprintln!("Can't parse {}: {}", path, e);
+                        std::process::exit(1);
+                    }
+                }
+            }).collect();
+
+            let groups = or_exit(def_dumper::invader::compare_definitions(&definitions, &files), "Failed to read the Invader definitions.");
+            if json {
+                println!("{}", serde_json::to_string_pretty(&groups).unwrap());
+            }
+            else {
+                for (group, changes) in groups {
+                    println!("{}", group);
+                    for c in changes {
+                        println!("    {}", c);
+                    }
+                }
+            }
+        },
+
+        Some(Command::Codegen { language, definitions, output }) => {
+            let definitions = load_definitions(&definitions);
+            let codegen = Codegen::new(&definitions);
+            let code = match language {
+                Language::C => codegen::c::header(&codegen),
+                Language::Rust => codegen::rust::module(&codegen),
+                Language::Python => codegen::python::module(&codegen),
+                Language::Typescript => codegen::typescript::declarations(&codegen),
+                Language::Imhex => codegen::imhex::pattern(&codegen),
+                Language::Editor010 => codegen::editor010::template(&codegen)
+            };
+            write_file(&output, code.as_bytes());
+        },
+
+        Some(Command::Schema { output }) => {
+            let schema = def_dumper::schema::definitions_schema();
+            write_file(&output, serde_json::to_string_pretty(&schema).unwrap().as_bytes());
+        },
+
+        Some(Command::TagSchema { definitions, output_directory }) => {
+            let definitions = load_definitions(&definitions);
+            let codegen = Codegen::new(&definitions);
+
+            if let Err(e) = std::fs::create_dir_all(&output_directory) {
+                eprintln!("Can't create {}: {}", output_directory, e);
+                std::process::exit(1);
+            }
+            for group in &codegen.groups {
+                let schema = codegen::json_schema::tag_schema(&codegen, group);
+                let path = std::path::Path::new(&output_directory).join(format!("{}.schema.json", group.name));
+                write_file(&path.to_string_lossy(), serde_json::to_string_pretty(&schema).unwrap().as_bytes());
+            }
+        },
+
+        Some(Command::Kaitai { definitions, output_directory }) => {
+            let definitions = load_definitions(&definitions);
+            let codegen = Codegen::new(&definitions);
+
+            if let Err(e) = std::fs::create_dir_all(&output_directory) {
+                eprintln!("Can't create {}: {}", output_directory, e);
+                std::process::exit(1);
+            }
+            for group in &codegen.groups {
+                let path = std::path::Path::new(&output_directory).join(format!("{}.ksy", group.name));
+                write_file(&path.to_string_lossy(), codegen::kaitai::spec(&codegen, group).as_bytes());
+            }
+        },
+
+        Some(Command::Reclaimer { definitions, output_directory }) => {
+            let definitions = load_definitions(&definitions);
+            let codegen = Codegen::new(&definitions);
+
+            if let Err(e) = std::fs::create_dir_all(&output_directory) {
+                eprintln!("Can't create {}: {}", output_directory, e);
+                std::process::exit(1);
+            }
+            for group in &codegen.groups {
+                let path = std::path::Path::new(&output_directory).join(format!("{}.py", codegen::reclaimer::module_name(group)));
+                write_file(&path.to_string_lossy(), codegen::reclaimer::module(&codegen, group).as_bytes());
+            }
+            let path = std::path::Path::new(&output_directory).join("__init__.py");
+            write_file(&path.to_string_lossy(), codegen::reclaimer::package(&codegen).as_bytes());
+        },
+
+        Some(Command::Canonicalize { definitions, output_json, omit_volatile }) => {
+            let definitions = load_definitions(&definitions);
+            write_file(&output_json, &def_dumper::definitions_into_canonical_json(&definitions, omit_volatile));
+        },
+
+        Some(Command::Annotate { definitions, annotations, output_json }) => {
+            let mut definitions = load_definitions(&definitions);
+            let annotations = or_exit(def_dumper::annotations::read_annotations(&read_file(&annotations)), "Failed to read the annotations.");
+            for w in def_dumper::annotations::apply_annotations(&mut definitions, &annotations) {
+                eprintln!("Warning: {}", w);
+            }
+            def_dumper::fingerprint::assign_fingerprints(&mut definitions);
+            write_file(&output_json, serde_json::to_string_pretty(&definitions).unwrap().as_bytes());
+        },
+
+        Some(Command::NewTag { definitions, group, output_tag, overrides }) => {
+            let definitions = load_definitions(&definitions);
+            let mut tag = or_exit(TagFile::new(&definitions, &group), "Failed to make the tag.");
+
+            if let Some(overrides) = overrides {
+                let overrides = read_json(&overrides);
+                let block = &definitions.groups[&group].block;
+                or_exit(tag_file::json::apply_json(&definitions, &StructLayout::of(block), &mut tag.data, &overrides, ""), "Failed to apply overrides.");
+            }
+
+            write_file(&output_tag, &or_exit(tag.to_bytes(&definitions), "Failed to write the tag."));
+        },
+
+        Some(Command::TagDiff { definitions, old_tag, new_tag, json }) => {
+            let definitions = load_definitions(&definitions);
+            let old_tag = load_tag(&definitions, &old_tag);
+            let new_tag = load_tag(&definitions, &new_tag);
+            let changes = or_exit(tag_file::diff::diff_tags(&definitions, &old_tag, &new_tag), "Failed to compare the tags.");
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&changes).unwrap());
+            }
+            else {
+                for c in changes {
+                    println!("{}", c);
+                }
+            }
         },
-        _ => ()
+
+        Some(Command::TagDeps { definitions, tags_directory, root_tag, missing, orphans, json }) => {
+            let definitions = load_definitions(&definitions);
+            let directory = or_exit(TagDirectory::scan(&definitions, std::path::Path::new(&tags_directory)), "Failed to scan the tags directory.");
+            let mut scanner = DependencyScanner::new(&definitions, &directory);
+
+            if missing {
+                let missing = scanner.missing(root_tag.as_deref());
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&missing).unwrap());
+                }
+                else {
+                    for m in missing {
+                        println!("{} -> {}", m.tag, m.reference);
+                    }
+                }
+            }
+            else if orphans {
+                let orphans = scanner.orphans();
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&orphans).unwrap());
+                }
+                else {
+                    for o in orphans {
+                        println!("{}", o);
+                    }
+                }
+            }
+            else {
+                let tree = scanner.tree(&or_exit(root_tag, "A root tag, --missing, or --orphans is required."));
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&tree).unwrap());
+                }
+                else {
+                    let mut text = String::new();
+                    tree.write_text(&mut text, 0);
+                    print!("{}", text);
+                }
+            }
+        },
+
+        Some(Command::TagRename { definitions, tags_directory, from, to, dry_run }) => {
+            let definitions = load_definitions(&definitions);
+            let directory = or_exit(TagDirectory::scan(&definitions, std::path::Path::new(&tags_directory)), "Failed to scan the tags directory.");
+            let updates = or_exit(tag_file::rename::rename_tags(&definitions, &directory, &from, &to, dry_run), "Failed to rename the tags.");
+
+            let prefix = if dry_run { "Would move" } else { "Moved" };
+            for u in updates.iter().filter(|u| u.moved_to.is_some()) {
+                println!("{} {} -> {}", prefix, u.tag, u.moved_to.as_ref().unwrap());
+            }
+            let prefix = if dry_run { "Would update" } else { "Updated" };
+            for u in updates.iter().filter(|u| u.references > 0) {
+                println!("{} {} reference(s) in {}", prefix, u.references, u.tag);
+            }
+        },
+
+        Some(Command::TagMigrate { old_definitions, new_definitions, input, output }) => {
+            let old_definitions = load_definitions(&old_definitions);
+            let new_definitions = load_definitions(&new_definitions);
+
+            let groups = if std::path::Path::new(&input).is_dir() {
+                let directory = or_exit(TagDirectory::scan(&old_definitions, std::path::Path::new(&input)), "Failed to scan the tags directory.");
+                or_exit(tag_file::migrate::migrate_directory(&old_definitions, &new_definitions, &directory, std::path::Path::new(&output)), "Failed to migrate the tags.")
+            }
+            else {
+                let tag = load_tag(&old_definitions, &input);
+                let migrated = or_exit(tag_file::migrate::migrate_tag(&old_definitions, &new_definitions, &tag), "Failed to migrate the tag.");
+                write_file(&output, &or_exit(migrated.to_bytes(&new_definitions), "Failed to write the tag."));
+                std::iter::once(tag.group).collect()
+            };
+
+            for group in groups {
+                let report = or_exit(tag_file::migrate::migration_report(&old_definitions, &new_definitions, &group), "Failed to map the group.");
+                for f in &report.renamed {
+                    println!("{}: mapped {} by position", group, f);
+                }
+                for f in &report.dropped {
+                    println!("{}: dropped {}", group, f);
+                }
+                for f in &report.defaulted {
+                    println!("{}: defaulted {}", group, f);
+                }
+                for f in &report.unmatched_options {
+                    println!("{}: no match for {}", group, f);
+                }
+            }
+        },
+
+        Some(Command::CsvExport { definitions, tags_directory, group, fields, output }) => {
+            let definitions = load_definitions(&definitions);
+            let directory = or_exit(TagDirectory::scan(&definitions, std::path::Path::new(&tags_directory)), "Failed to scan the tags directory.");
+            let rows = or_exit(tag_file::csv::export_csv(&definitions, &directory, &group, &fields), "Failed to export the tags.");
+            let csv = tag_file::csv::write_csv(&rows);
+
+            match output {
+                Some(output) => write_file(&output, csv.as_bytes()),
+                None => print!("{}", csv)
+            }
+        },
+
+        Some(Command::CsvImport { definitions, tags_directory, csv, dry_run }) => {
+            let definitions = load_definitions(&definitions);
+            let directory = or_exit(TagDirectory::scan(&definitions, std::path::Path::new(&tags_directory)), "Failed to scan the tags directory.");
+            let csv_data = String::from_utf8_lossy(&read_file(&csv)).into_owned();
+            let rows = or_exit(tag_file::csv::read_csv(&csv_data), "Failed to read the CSV.");
+            let changed = or_exit(tag_file::csv::import_csv(&definitions, &directory, &rows, dry_run), "Failed to import the CSV.");
+
+            let prefix = if dry_run { "Would update" } else { "Updated" };
+            for c in changed {
+                println!("{} {}", prefix, c);
+            }
+        },
+
+        None => {
+            let exe_path = args.exe_path.unwrap();
+            let output_json = args.output_json.unwrap();
+
+            let annotations = args.annotations.map(|a| or_exit(def_dumper::annotations::read_annotations(&read_file(&a)), "Failed to read the annotations."));
+
+            // Make the json
+            let json = if args.canonical || args.omit_volatile {
+                let definitions = or_exit(def_dumper::dump_definitions(&read_file(&exe_path), annotations.as_ref()), "Failed! The exe might not be correct.");
+                def_dumper::definitions_into_canonical_json(&definitions, args.omit_volatile)
+            }
+            else {
+                or_exit(def_dumper::dump_definitions_into_json(&read_file(&exe_path), annotations.as_ref()), "Failed! The exe might not be correct.")
+            };
+
+            // Write the json
+            write_file(&output_json, &json);
+        }
     }
 }
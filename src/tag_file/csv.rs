@@ -0,0 +1,335 @@
+//! Exporting field values across many tags to CSV and importing edits back
+//!
+//! The first column is the tag path (relative to the tags directory, with its extension) and each other column is a
+//! field path (see [`field_path`](super::field_path)). Cells are written as follows:
+//!
+//! - enums are the identifier of the option
+//! - bitfields are the identifiers of each set bit, separated by `|`
+//! - anything with more than one component (points, colors, bounds, etc.) is numbers separated by spaces
+//! - tag references are the referenced path with the group as its extension
+//! - tag data is a hexadecimal string
+//!
+//! Cells for fields in block elements a tag doesn't have are left empty.
+
+extern crate serde_json;
+use self::serde_json::{Map, Value};
+
+use def_dumper::{BlockFieldType, Definitions, option_identifiers};
+use super::directory::TagDirectory;
+use super::field_path::{PathSegment, parse_field_path, validate_field_path, with_value_mut};
+use super::{FieldValue, LayoutEntry, StructLayout, TagFile, TagStruct, json};
+
+/// Quote a CSV cell if needed
+fn quote_cell(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    }
+    else {
+        cell.to_owned()
+    }
+}
+
+/// Write rows as CSV
+pub fn write_csv(rows: &[Vec<String>]) -> String {
+    let mut output = String::new();
+    for row in rows {
+        output += &row.iter().map(|c| quote_cell(c)).collect::<Vec<String>>().join(",");
+        output += "\r\n";
+    }
+    output
+}
+
+/// Read rows from CSV
+pub fn read_csv(data: &str) -> Option<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = data.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            },
+            ('"', true) => quoted = false,
+            ('"', false) if cell.is_empty() => quoted = true,
+            (',', false) => row.push(std::mem::take(&mut cell)),
+            ('\r', false) => (),
+            ('\n', false) => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            },
+            (c, _) => cell.push(c)
+        }
+    }
+
+    if quoted {
+        eprintln!("CSV ends in the middle of a quoted cell");
+        return None
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+
+    Some(rows)
+}
+
+/// Convert a value to a cell
+pub fn value_to_cell(definitions: &Definitions, entry: &LayoutEntry, value: &FieldValue) -> String {
+    if let FieldValue::Reference(reference) = value {
+        return reference.file_path(definitions).unwrap_or_default()
+    }
+
+    match json::value_to_json(definitions, entry, value) {
+        Some(Value::String(s)) => s,
+        Some(Value::Array(a)) => a.iter().map(|n| n.to_string()).collect::<Vec<String>>().join(" "),
+        Some(Value::Object(bits)) => bits.iter().filter(|(_, set)| set.as_bool() == Some(true)).map(|(name, _)| name.to_owned()).collect::<Vec<String>>().join("|"),
+        Some(n) => n.to_string(),
+        None => String::new()
+    }
+}
+
+/// Convert a cell to the JSON accepted by [`json::apply_json_value`]
+pub fn cell_to_json(entry: &LayoutEntry, value: &FieldValue, cell: &str) -> Value {
+    fn number(s: &str) -> Value {
+        serde_json::from_str::<Value>(s).ok().filter(|n| n.is_number()).unwrap_or_else(|| Value::from(s))
+    }
+
+    match (value, &entry.field.block_type) {
+        (FieldValue::Integer(_), BlockFieldType::Flags(_, bits)) => {
+            // Clear everything that isn't listed
+            let mut map = Map::new();
+            for name in option_identifiers(bits, "bit") {
+                map.insert(name, Value::from(false));
+            }
+            for name in cell.split('|').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+                map.insert(name.to_owned(), Value::from(true));
+            }
+            Value::Object(map)
+        },
+        (FieldValue::Integer(_), BlockFieldType::Enum(_)) => match cell.parse::<i64>() {
+            Ok(n) => Value::from(n),
+            Err(_) => Value::from(cell)
+        },
+        (FieldValue::Reference(_), _) => {
+            let mut map = Map::new();
+            if !cell.is_empty() {
+                let (path, group) = match cell.rfind('.') {
+                    Some(dot) => (&cell[..dot], Value::from(&cell[dot + 1..])),
+                    None => (cell, Value::Null)
+                };
+                map.insert("group".to_owned(), group);
+                map.insert("path".to_owned(), Value::from(path));
+            }
+            Value::Object(map)
+        },
+        (FieldValue::Integers(_), _) | (FieldValue::Floats(_), _) => Value::Array(cell.split_whitespace().map(number).collect()),
+        (FieldValue::Integer(_), _) | (FieldValue::Float(_), _) => number(cell.trim()),
+        _ => Value::from(cell)
+    }
+}
+
+/// Parse field paths, checking that they exist in the group
+fn parse_paths(layout: &StructLayout, paths: &[String]) -> Option<Vec<Vec<PathSegment>>> {
+    paths.iter().map(|p| {
+        let segments = match parse_field_path(p) {
+            Some(n) => n,
+            None => {
+                eprintln!("Invalid field path {}", p);
+                return None
+            }
+        };
+        if let Err(e) = validate_field_path(layout, &segments) {
+            eprintln!("Invalid field path {}: {}", p, e);
+            return None
+        }
+        Some(segments)
+    }).collect()
+}
+
+/// Export fields of every tag of a group in a tags directory as CSV rows, including a header row
+pub fn export_csv(definitions: &Definitions, directory: &TagDirectory, group: &str, paths: &[String]) -> Option<Vec<Vec<String>>> {
+    let block = match definitions.groups.get(group) {
+        Some(n) => &n.block,
+        None => {
+            eprintln!("No such group {}", group);
+            return None
+        }
+    };
+    let layout = StructLayout::of(block);
+    let segments = parse_paths(&layout, paths)?;
+
+    let mut rows = vec![std::iter::once("tag".to_owned()).chain(paths.iter().cloned()).collect::<Vec<String>>()];
+    let extension = format!(".{}", group);
+    for tag_path in directory.tags.keys().filter(|t| t.ends_with(&extension)) {
+        let mut tag = match directory.read(definitions, tag_path) {
+            Some(n) => n,
+            None => {
+                eprintln!("Warning: skipping {}", tag_path);
+                continue
+            }
+        };
+        let mut row = vec![tag_path.to_owned()];
+        for s in &segments {
+            row.push(with_value_mut(&layout, &mut tag.data, s, &mut |entry, value| value_to_cell(definitions, entry, value)).ok()?.unwrap_or_default());
+        }
+        rows.push(row);
+    }
+
+    Some(rows)
+}
+
+/// Apply one row of cells to a block element, returning `None` if a cell is invalid
+fn apply_row(definitions: &Definitions, layout: &StructLayout, data: &mut TagStruct, segments: &[Vec<PathSegment>], paths: &[String], cells: &[String]) -> Option<()> {
+    for ((s, path), cell) in segments.iter().zip(paths.iter()).zip(cells.iter()) {
+        let result = with_value_mut(layout, data, s, &mut |entry, value| {
+            let json = cell_to_json(entry, value, cell);
+            json::apply_json_value(definitions, entry, value, &json, path)
+        });
+        match result {
+            Ok(Some(Some(()))) => (),
+            Ok(None) if cell.is_empty() => (),
+            Ok(None) => {
+                eprintln!("{} doesn't exist in this tag", path);
+                return None
+            },
+            _ => return None
+        }
+    }
+    Some(())
+}
+
+/// Apply CSV rows (including the header row) to tags in a tags directory, returning the tags that were changed
+///
+/// Every row is applied before anything is written, so nothing is changed if any row is invalid. If `dry_run` is set,
+/// nothing is written.
+pub fn import_csv(definitions: &Definitions, directory: &TagDirectory, rows: &[Vec<String>], dry_run: bool) -> Option<Vec<String>> {
+    let header = rows.first()?;
+    if header.first().map(|h| h.as_str()) != Some("tag") {
+        eprintln!("The first column must be \"tag\"");
+        return None
+    }
+    let paths = &header[1..];
+
+    // Tag path, tag and its data before any rows were applied
+    let mut edited: Vec<(String, TagFile, TagStruct)> = Vec::new();
+    for row in &rows[1..] {
+        if row.len() != header.len() {
+            eprintln!("Row for {} has {} cells, but the header has {}", row.first().map(|r| r.as_str()).unwrap_or(""), row.len(), header.len());
+            return None
+        }
+
+        let tag_path = super::directory::normalize_tag_path(&row[0]);
+        if !directory.tags.contains_key(&tag_path) {
+            eprintln!("No such tag {}", tag_path);
+            return None
+        }
+
+        // A tag with more than one row gets all of them
+        let index = match edited.iter().position(|(p, _, _)| *p == tag_path) {
+            Some(n) => n,
+            None => {
+                let tag = directory.read(definitions, &tag_path)?;
+                let original = tag.data.clone();
+                edited.push((tag_path.clone(), tag, original));
+                edited.len() - 1
+            }
+        };
+        let tag = &mut edited[index].1;
+        let layout = StructLayout::of(tag.block(definitions)?);
+        let segments = parse_paths(&layout, paths)?;
+        if apply_row(definitions, &layout, &mut tag.data, &segments, paths, &row[1..]).is_none() {
+            eprintln!("Failed to apply values to {}", tag_path);
+            return None
+        }
+    }
+
+    let mut changed = Vec::new();
+    let mut files = Vec::new();
+    for (tag_path, tag, original) in edited {
+        if tag.data != original {
+            files.push((&directory.tags[&tag_path], tag.to_bytes(definitions)?));
+            changed.push(tag_path);
+        }
+    }
+
+    if !dry_run {
+        for (file, data) in files {
+            if let Err(e) = std::fs::write(file, data) {
+                eprintln!("Can't write {}: {}", file.display(), e);
+                return None
+            }
+        }
+    }
+
+    Some(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tag_file::TagFile;
+    use tag_file::tests::{definitions, tags_directory};
+
+    #[test]
+    fn quoting() {
+        let rows = vec![
+            vec!["tag".to_owned(), "name".to_owned()],
+            vec!["a.weapon".to_owned(), "comma, \"quote\"\nand newline".to_owned()],
+            vec!["b.weapon".to_owned(), String::new()]
+        ];
+        let csv = write_csv(&rows);
+        assert_eq!(csv, "tag,name\r\na.weapon,\"comma, \"\"quote\"\"\nand newline\"\r\nb.weapon,\r\n");
+        assert_eq!(read_csv(&csv).unwrap(), rows);
+        assert!(read_csv("tag,\"name").is_none());
+    }
+
+    #[test]
+    fn export_and_import() {
+        let definitions = definitions();
+        let mut tag = TagFile::new(&definitions, "weapon").unwrap();
+        let directory = tags_directory(&definitions, "csv_export_and_import", &[("a.weapon", &tag)]);
+
+        let paths = vec!["type".to_owned(), "flags".to_owned(), "triggers[0].rounds_per_second".to_owned()];
+        let mut rows = export_csv(&definitions, &directory, "weapon", &paths).unwrap();
+        assert_eq!(rows[1], vec!["a.weapon", "rifle", "", ""]);
+
+        rows[1][1] = "pistol".to_owned();
+        rows[1][2] = "a|b_c".to_owned();
+        assert_eq!(import_csv(&definitions, &directory, &rows, false).unwrap(), vec!["a.weapon"]);
+        assert_eq!(export_csv(&definitions, &directory, "weapon", &paths).unwrap(), rows);
+
+        // Nothing changes the second time
+        assert!(import_csv(&definitions, &directory, &rows, false).unwrap().is_empty());
+
+        // Fields in elements the tag doesn't have can't be set
+        rows[1][3] = "5".to_owned();
+        assert!(import_csv(&definitions, &directory, &rows, false).is_none());
+
+        tag.data = directory.read(&definitions, "a.weapon").unwrap().data;
+        assert_eq!(TagFile::read(&definitions, &std::fs::read(&directory.tags["a.weapon"]).unwrap()).unwrap(), tag);
+    }
+
+    #[test]
+    fn bad_row_writes_nothing() {
+        let definitions = definitions();
+        let tag = TagFile::new(&definitions, "weapon").unwrap();
+        let directory = tags_directory(&definitions, "csv_bad_row_writes_nothing", &[("a.weapon", &tag), ("b.weapon", &tag)]);
+        let before = std::fs::read(&directory.tags["a.weapon"]).unwrap();
+
+        let rows = read_csv("tag,rate\na.weapon,5\nb.weapon,fast\n").unwrap();
+        assert!(import_csv(&definitions, &directory, &rows, false).is_none());
+        assert_eq!(std::fs::read(&directory.tags["a.weapon"]).unwrap(), before);
+
+        let rows = read_csv("tag,rate\na.weapon,5\nmissing.weapon,1\n").unwrap();
+        assert!(import_csv(&definitions, &directory, &rows, false).is_none());
+        assert_eq!(std::fs::read(&directory.tags["a.weapon"]).unwrap(), before);
+
+        let rows = read_csv("tag,rate\na.weapon,5\nb.weapon\n").unwrap();
+        assert!(import_csv(&definitions, &directory, &rows, false).is_none());
+        assert_eq!(std::fs::read(&directory.tags["a.weapon"]).unwrap(), before);
+    }
+}
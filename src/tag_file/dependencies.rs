@@ -0,0 +1,231 @@
+//! Finding what tags depend on
+
+extern crate serde;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use def_dumper::{Definitions, is_false};
+use super::directory::{TagDirectory, normalize_tag_path};
+
+/// A tag referenced by another tag
+#[derive(Clone, PartialEq, Debug)]
+pub struct Dependency {
+    /// Normalized path of the reference with the referenced group as its extension
+    pub reference: String,
+
+    /// Normalized path of the tag in the tags directory (or `None` if it doesn't exist)
+    pub resolved: Option<String>
+}
+
+/// A tag in a dependency tree
+#[derive(serde::Serialize, Clone, PartialEq, Debug)]
+pub struct DependencyNode {
+    pub tag: String,
+
+    /// The tag does not exist
+    #[serde(skip_serializing_if = "is_false")]
+    pub missing: bool,
+
+    /// The tag could not be read
+    #[serde(skip_serializing_if = "is_false")]
+    pub unreadable: bool,
+
+    /// The tag is already in the tree somewhere else, so its dependencies are not listed again
+    #[serde(skip_serializing_if = "is_false")]
+    pub repeated: bool,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<DependencyNode>
+}
+
+impl DependencyNode {
+    /// Write the tree as indented text
+    pub fn write_text(&self, output: &mut String, depth: usize) {
+        output.push_str(&"    ".repeat(depth));
+        output.push_str(&self.tag);
+        if self.missing {
+            output.push_str(" (missing)");
+        }
+        if self.unreadable {
+            output.push_str(" (unreadable)");
+        }
+        if self.repeated {
+            output.push_str(" (see above)");
+        }
+        output.push('\n');
+        for d in &self.dependencies {
+            d.write_text(output, depth + 1);
+        }
+    }
+}
+
+/// A reference to a tag that does not exist
+#[derive(serde::Serialize, Clone, PartialEq, Debug)]
+pub struct MissingReference {
+    pub tag: String,
+    pub reference: String
+}
+
+/// Reads tags in a tags directory to find their dependencies, caching what it reads
+pub struct DependencyScanner<'a> {
+    definitions: &'a Definitions,
+    directory: &'a TagDirectory,
+    cache: BTreeMap<String, Option<Vec<Dependency>>>
+}
+
+impl<'a> DependencyScanner<'a> {
+    pub fn new(definitions: &'a Definitions, directory: &'a TagDirectory) -> DependencyScanner<'a> {
+        DependencyScanner { definitions, directory, cache: BTreeMap::new() }
+    }
+
+    /// Get the tags directly referenced by a tag (or `None` if it can't be read)
+    pub fn dependencies(&mut self, tag_path: &str) -> Option<Vec<Dependency>> {
+        if let Some(n) = self.cache.get(tag_path) {
+            return n.clone()
+        }
+
+        let dependencies = self.directory.read(self.definitions, tag_path).map(|tag| {
+            let mut dependencies = Vec::<Dependency>::new();
+            for reference in tag.references(self.definitions) {
+                let dependency = match reference.file_path(self.definitions) {
+                    Some(n) => Dependency {
+                        reference: normalize_tag_path(&n),
+                        resolved: self.directory.resolve(self.definitions, reference)
+                    },
+                    None => continue
+                };
+                if !dependencies.contains(&dependency) {
+                    dependencies.push(dependency);
+                }
+            }
+            dependencies
+        });
+
+        self.cache.insert(tag_path.to_owned(), dependencies.clone());
+        dependencies
+    }
+
+    /// Get the full dependency tree of a tag
+    ///
+    /// Each tag's dependencies are only listed the first time it appears in the tree.
+    pub fn tree(&mut self, tag_path: &str) -> DependencyNode {
+        let mut visited = BTreeSet::new();
+        self.tree_recursive(&normalize_tag_path(tag_path), &mut visited)
+    }
+
+    fn tree_recursive(&mut self, tag_path: &str, visited: &mut BTreeSet<String>) -> DependencyNode {
+        let mut node = DependencyNode {
+            tag: tag_path.to_owned(),
+            missing: !self.directory.tags.contains_key(tag_path),
+            unreadable: false,
+            repeated: visited.contains(tag_path),
+            dependencies: Vec::new()
+        };
+        if node.missing || node.repeated {
+            return node
+        }
+        visited.insert(tag_path.to_owned());
+
+        match self.dependencies(tag_path) {
+            Some(dependencies) => for d in dependencies {
+                node.dependencies.push(match d.resolved {
+                    Some(resolved) => self.tree_recursive(&resolved, visited),
+                    None => DependencyNode { tag: d.reference, missing: true, unreadable: false, repeated: false, dependencies: Vec::new() }
+                });
+            },
+            None => node.unreadable = true
+        }
+
+        node
+    }
+
+    /// Find references to tags that don't exist, either in the dependency tree of a tag or in every tag if `root` is `None`
+    pub fn missing(&mut self, root: Option<&str>) -> Vec<MissingReference> {
+        let tags: Vec<String> = match root {
+            Some(root) => {
+                let mut tags = Vec::new();
+                fn collect(node: &DependencyNode, tags: &mut Vec<String>) {
+                    if !node.missing && !node.repeated {
+                        tags.push(node.tag.clone());
+                    }
+                    for d in &node.dependencies {
+                        collect(d, tags);
+                    }
+                }
+                collect(&self.tree(root), &mut tags);
+                tags
+            },
+            None => self.directory.tags.keys().cloned().collect()
+        };
+
+        let mut missing = Vec::new();
+        for tag in tags {
+            for d in self.dependencies(&tag).unwrap_or_default() {
+                if d.resolved.is_none() {
+                    missing.push(MissingReference { tag: tag.clone(), reference: d.reference });
+                }
+            }
+        }
+        missing
+    }
+
+    /// Find tags in the directory that no other tag references
+    pub fn orphans(&mut self) -> Vec<String> {
+        let mut referenced = BTreeSet::new();
+        let tags: Vec<String> = self.directory.tags.keys().cloned().collect();
+        for tag in &tags {
+            for d in self.dependencies(tag).unwrap_or_default() {
+                if let Some(resolved) = d.resolved {
+                    if &resolved != tag {
+                        referenced.insert(resolved);
+                    }
+                }
+            }
+        }
+        tags.into_iter().filter(|t| !referenced.contains(t)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tag_file::tests::{definitions, tag_with, tags_directory};
+
+    #[test]
+    fn dependency_tree() {
+        let definitions = definitions();
+        let gun = tag_with(&definitions, "weapon", serde_json::json!({
+            "model": {"group": "model", "path": "weapons\\gun\\gun"},
+            "triggers": [
+                {"projectile": {"group": "weapon", "path": "Weapons\\Gun\\Bullet"}},
+                {"projectile": {"group": "model", "path": "weapons\\gun\\missing"}}
+            ]
+        }));
+        let bullet = tag_with(&definitions, "weapon", serde_json::json!({
+            "model": {"group": "model", "path": "weapons\\gun\\gun"},
+            "triggers": [{"projectile": {"group": "weapon", "path": "weapons\\gun\\gun"}}]
+        }));
+        let model = tag_with(&definitions, "model", serde_json::json!({}));
+        let directory = tags_directory(&definitions, "dependency_tree", &[
+            ("weapons\\gun\\gun.weapon", &gun),
+            ("weapons\\gun\\bullet.weapon", &bullet),
+            ("weapons\\gun\\gun.model", &model),
+            ("other.model", &model)
+        ]);
+        let mut scanner = DependencyScanner::new(&definitions, &directory);
+
+        let mut text = String::new();
+        scanner.tree("Weapons/Gun/Gun.weapon").write_text(&mut text, 0);
+        assert_eq!(text, "weapons\\gun\\gun.weapon\n\
+                          \x20   weapons\\gun\\gun.model\n\
+                          \x20   weapons\\gun\\bullet.weapon\n\
+                          \x20       weapons\\gun\\gun.model (see above)\n\
+                          \x20       weapons\\gun\\gun.weapon (see above)\n\
+                          \x20   weapons\\gun\\missing.model (missing)\n");
+
+        let missing = MissingReference { tag: "weapons\\gun\\gun.weapon".to_owned(), reference: "weapons\\gun\\missing.model".to_owned() };
+        assert_eq!(scanner.missing(Some("weapons\\gun\\bullet.weapon")), vec![missing.clone()]);
+        assert_eq!(scanner.missing(None), vec![missing]);
+        assert_eq!(scanner.orphans(), vec!["other.model"]);
+    }
+}
@@ -0,0 +1,215 @@
+//! Comparing two tags field by field
+
+extern crate serde;
+extern crate serde_json;
+use self::serde_json::Value;
+
+extern crate sha2;
+use self::sha2::{Sha256, Digest};
+
+use def_dumper::{BlockFieldType, Definitions, option_identifiers};
+use def_dumper::diff::{Alignment, align};
+use super::{FieldValue, StructLayout, TagFile, TagStruct, json};
+
+/// What happened to a value
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Changed,
+    Inserted,
+    Removed
+}
+
+/// A value in a tag diff
+#[derive(serde::Serialize, Clone, PartialEq, Debug)]
+#[serde(untagged)]
+pub enum DiffValue {
+    Value(Value),
+    Data { size: usize, sha256: String }
+}
+/// Write a value as JSON, but with whole floats written without a decimal point (e.g. `8` instead of `8.0`)
+fn show(value: &Value) -> String {
+    match value {
+        Value::Number(n) if n.is_f64() => n.as_f64().unwrap().to_string(),
+        Value::Array(a) => format!("[{}]", a.iter().map(show).collect::<Vec<String>>().join(",")),
+        v => v.to_string()
+    }
+}
+
+impl std::fmt::Display for DiffValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Value(Value::String(s)) => write!(f, "{:?}", s),
+            Self::Value(v) => write!(f, "{}", show(v)),
+            Self::Data { size, sha256 } => write!(f, "{} bytes (SHA-256 {})", size, sha256)
+        }
+    }
+}
+
+/// A difference between two tags
+#[derive(serde::Serialize, Clone, PartialEq, Debug)]
+pub struct TagChange {
+    pub path: String,
+    pub kind: ChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<DiffValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<DiffValue>
+}
+impl std::fmt::Display for TagChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.kind, &self.old, &self.new) {
+            (ChangeKind::Changed, Some(old), Some(new)) => write!(f, "{}: {} -> {}", self.path, old, new),
+            (ChangeKind::Inserted, _, _) => write!(f, "{}: inserted", self.path),
+            (ChangeKind::Removed, _, _) => write!(f, "{}: removed", self.path),
+            _ => write!(f, "{}: changed", self.path)
+        }
+    }
+}
+
+fn data_value(data: &[u8]) -> DiffValue {
+    let mut sha256 = Sha256::new();
+    sha256.update(data);
+    DiffValue::Data { size: data.len(), sha256: json::to_hex(&sha256.finalize()) }
+}
+
+fn join_path(path: &str, identifier: &str) -> String {
+    if path.is_empty() { identifier.to_owned() } else { format!("{}.{}", path, identifier) }
+}
+
+/// Compare two tags of the same group
+pub fn diff_tags(definitions: &Definitions, old: &TagFile, new: &TagFile) -> Option<Vec<TagChange>> {
+    if old.group != new.group {
+        eprintln!("Can't compare a {} tag with a {} tag", old.group, new.group);
+        return None
+    }
+
+    let layout = StructLayout::of(old.block(definitions)?);
+    let mut changes = Vec::new();
+    diff_structs(definitions, &layout, &old.data, &new.data, "", &mut changes);
+    Some(changes)
+}
+
+/// Compare two block elements
+pub fn diff_structs(definitions: &Definitions, layout: &StructLayout, old: &TagStruct, new: &TagStruct, path: &str, changes: &mut Vec<TagChange>) {
+    for (e, (old_value, new_value)) in layout.entries.iter().zip(old.values.iter().zip(new.values.iter())) {
+        if old_value == new_value {
+            continue
+        }
+
+        let field_path = join_path(path, &e.identifier);
+        match (old_value, new_value, &e.field.block_type) {
+            (FieldValue::Block(old_elements), FieldValue::Block(new_elements), BlockFieldType::Block(block)) => {
+                diff_elements(definitions, &StructLayout::of(block), old_elements, new_elements, &field_path, changes);
+            },
+            (FieldValue::Array(old_elements), FieldValue::Array(new_elements), _) => {
+                let array = e.array.as_ref().unwrap();
+                for (i, (o, n)) in old_elements.iter().zip(new_elements.iter()).enumerate() {
+                    diff_structs(definitions, &array.element, o, n, &format!("{}[{}]", field_path, i), changes);
+                }
+            },
+            (FieldValue::Integer(o), FieldValue::Integer(n), BlockFieldType::Flags(_, bits)) => {
+                let identifiers = option_identifiers(bits, "bit");
+                for bit in 0..(e.size * 8) {
+                    let (was_set, is_set) = (o & (1 << bit) != 0, n & (1 << bit) != 0);
+                    if was_set != is_set {
+                        let name = identifiers.get(bit).cloned().unwrap_or_else(|| format!("bit_{}", bit));
+                        changes.push(TagChange {
+                            path: join_path(&field_path, &name),
+                            kind: ChangeKind::Changed,
+                            old: Some(DiffValue::Value(Value::from(was_set))),
+                            new: Some(DiffValue::Value(Value::from(is_set)))
+                        });
+                    }
+                }
+            },
+            (FieldValue::Reference(o), FieldValue::Reference(n), _) => changes.push(TagChange {
+                path: field_path,
+                kind: ChangeKind::Changed,
+                old: Some(DiffValue::Value(o.file_path(definitions).map(Value::from).unwrap_or(Value::Null))),
+                new: Some(DiffValue::Value(n.file_path(definitions).map(Value::from).unwrap_or(Value::Null)))
+            }),
+            (FieldValue::Data(o), FieldValue::Data(n), _) => changes.push(TagChange {
+                path: field_path,
+                kind: ChangeKind::Changed,
+                old: Some(data_value(o)),
+                new: Some(data_value(n))
+            }),
+
+            // Padding isn't interesting
+            (FieldValue::Raw(_), _, _) => (),
+
+            _ => changes.push(TagChange {
+                path: field_path,
+                kind: ChangeKind::Changed,
+                old: json::value_to_json(definitions, e, old_value).map(DiffValue::Value),
+                new: json::value_to_json(definitions, e, new_value).map(DiffValue::Value)
+            })
+        }
+    }
+}
+
+/// Compare the elements of a block
+///
+/// Elements are matched up with the longest common subsequence of identical elements, so inserting or removing an
+/// element doesn't make every element after it show up as changed. Unmatched elements between matches are compared
+/// with each other in order, and any left over are inserted or removed.
+pub fn diff_elements(definitions: &Definitions, layout: &StructLayout, old: &[TagStruct], new: &[TagStruct], path: &str, changes: &mut Vec<TagChange>) {
+    for alignment in align(old, new) {
+        let (unmatched_old, unmatched_new) = match alignment {
+            Alignment::Unmatched(o, n) => (o, n),
+            Alignment::Matched(_, _) => continue
+        };
+        for k in 0..unmatched_old.len().max(unmatched_new.len()) {
+            match (unmatched_old.get(k), unmatched_new.get(k)) {
+                (Some(&o), Some(&n)) => diff_structs(definitions, layout, &old[o], &new[n], &format!("{}[{}]", path, n), changes),
+                (Some(&o), None) => changes.push(TagChange {
+                    path: format!("{}[{}]", path, o),
+                    kind: ChangeKind::Removed,
+                    old: Some(DiffValue::Value(json::struct_to_json(definitions, layout, &old[o]))),
+                    new: None
+                }),
+                (None, Some(&n)) => changes.push(TagChange {
+                    path: format!("{}[{}]", path, n),
+                    kind: ChangeKind::Inserted,
+                    old: None,
+                    new: Some(DiffValue::Value(json::struct_to_json(definitions, layout, &new[n])))
+                }),
+                (None, None) => unreachable!()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tag_file::tests::{definitions, tag_with};
+
+    #[test]
+    fn diff_weapons() {
+        let definitions = definitions();
+        let old = tag_with(&definitions, "weapon", serde_json::json!({
+            "rate": 2.5,
+            "triggers": [{"rounds_per_second": 8}, {"rounds_per_second": 1}],
+            "bounds": [0, 1]
+        }));
+        let new = tag_with(&definitions, "weapon", serde_json::json!({
+            "rate": 2.5,
+            "model": {"group": "model", "path": "weapons\\gun\\gun"},
+            "triggers": [{"rounds_per_second": 10}],
+            "data": "DEADBEEF",
+            "bounds": [0, 1.5]
+        }));
+
+        let changes: Vec<String> = diff_tags(&definitions, &old, &new).unwrap().iter().map(|c| c.to_string()).collect();
+        assert_eq!(changes, vec![
+            r#"model: null -> "weapons\\gun\\gun.model""#.to_owned(),
+            "triggers[0].rounds_per_second: 8 -> 10".to_owned(),
+            "triggers[1]: removed".to_owned(),
+            format!("data: 0 bytes (SHA-256 {}) -> 4 bytes (SHA-256 {})", json::to_hex(&Sha256::digest(b"")), json::to_hex(&Sha256::digest([0xDE, 0xAD, 0xBE, 0xEF]))),
+            "bounds: [0,1] -> [0,1.5]".to_owned()
+        ]);
+        assert!(diff_tags(&definitions, &old, &old).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+//! Finding tags in a tags directory
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use def_dumper::Definitions;
+use super::{TagFile, TagReference};
+
+/// Normalize a tag path so it can be compared with others
+///
+/// Tag paths use backslashes and are case insensitive, so this lowercases everything and turns forward slashes into
+/// backslashes.
+pub fn normalize_tag_path(path: &str) -> String {
+    path.to_lowercase().replace('/', "\\")
+}
+
+/// Every tag in a tags directory
+pub struct TagDirectory {
+    pub root: PathBuf,
+
+    /// Normalized tag paths with their extension (e.g. `weapons\pistol\pistol.weapon`) mapped to file paths
+    pub tags: BTreeMap<String, PathBuf>
+}
+
+impl TagDirectory {
+    /// Find every tag in a directory
+    ///
+    /// Only files with an extension that is a group in the definitions are tags.
+    pub fn scan(definitions: &Definitions, root: &Path) -> Option<TagDirectory> {
+        fn scan_recursively(definitions: &Definitions, root: &Path, directory: &Path, tags: &mut BTreeMap<String, PathBuf>) -> std::io::Result<()> {
+            for entry in std::fs::read_dir(directory)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    scan_recursively(definitions, root, &path, tags)?;
+                }
+                else if let Some(tag_path) = TagDirectory::tag_path_in(definitions, root, &path) {
+                    tags.insert(tag_path, path);
+                }
+            }
+            Ok(())
+        }
+
+        let mut tags = BTreeMap::new();
+        if let Err(e) = scan_recursively(definitions, root, root, &mut tags) {
+            eprintln!("Can't read {}: {}", root.display(), e);
+            return None
+        }
+
+        Some(TagDirectory { root: root.to_owned(), tags })
+    }
+
+    fn tag_path_in(definitions: &Definitions, root: &Path, file: &Path) -> Option<String> {
+        let extension = file.extension()?.to_str()?;
+        if !definitions.groups.contains_key(extension) {
+            return None
+        }
+
+        let relative = file.strip_prefix(root).ok()?;
+        let components = relative.components().map(|c| c.as_os_str().to_str()).collect::<Option<Vec<&str>>>()?;
+        Some(normalize_tag_path(&components.join("\\")))
+    }
+
+    /// Get the normalized tag path of a file in the directory
+    pub fn tag_path(&self, definitions: &Definitions, file: &Path) -> Option<String> {
+        Self::tag_path_in(definitions, &self.root, file)
+    }
+
+    /// Find the tag a tag reference points to, returning its normalized tag path
+    ///
+    /// If the reference is to a group with subgroups (e.g. `object`), a tag of any of those subgroups will do.
+    pub fn resolve(&self, definitions: &Definitions, reference: &TagReference) -> Option<String> {
+        let group = reference.group(definitions)?;
+        let path = normalize_tag_path(&reference.path);
+        if path.is_empty() {
+            return None
+        }
+
+        let mut groups = vec![group];
+        let mut i = 0;
+        while i < groups.len() {
+            let supergroup = groups[i].clone();
+            for (name, g) in &definitions.groups {
+                if g.supergroup.as_ref() == Some(&supergroup) && !groups.contains(name) {
+                    groups.push(name.to_owned());
+                }
+            }
+            i += 1;
+        }
+
+        groups.into_iter().map(|g| format!("{}.{}", path, g)).find(|p| self.tags.contains_key(p))
+    }
+
+    /// Read a tag in the directory by its normalized tag path
+    pub fn read(&self, definitions: &Definitions, tag_path: &str) -> Option<TagFile> {
+        let file = self.tags.get(tag_path)?;
+        let data = match std::fs::read(file) {
+            Ok(n) => n,
+            Err(e) => {
+                eprintln!("Can't read {}: {}", file.display(), e);
+                return None
+            }
+        };
+        let tag = TagFile::read(definitions, &data);
+        if tag.is_none() {
+            eprintln!("Failed to read {}", file.display());
+        }
+        tag
+    }
+}
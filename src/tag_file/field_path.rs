@@ -0,0 +1,83 @@
+//! Paths to values inside of a tag, such as `triggers[0].rounds_per_second`
+//!
+//! A path is a list of field identifiers (see [`StructLayout`]) separated by periods. Blocks and arrays must be given an
+//! element index in brackets before going into their fields.
+
+use def_dumper::BlockFieldType;
+use super::{FieldValue, LayoutEntry, StructLayout, TagStruct};
+
+/// A part of a field path
+#[derive(Clone, PartialEq, Debug)]
+pub struct PathSegment {
+    pub identifier: String,
+    pub index: Option<usize>
+}
+
+/// Parse a field path into its segments
+pub fn parse_field_path(path: &str) -> Option<Vec<PathSegment>> {
+    path.split('.').map(|segment| {
+        match segment.find('[') {
+            Some(bracket) => {
+                let index = segment[bracket + 1..].strip_suffix(']')?.parse().ok()?;
+                Some(PathSegment { identifier: segment[..bracket].to_owned(), index: Some(index) })
+            },
+            None => Some(PathSegment { identifier: segment.to_owned(), index: None })
+        }
+    }).collect::<Option<Vec<_>>>().filter(|s| s.iter().all(|s| !s.identifier.is_empty()))
+}
+
+/// Check that a path exists in the definitions
+pub fn validate_field_path(layout: &StructLayout, path: &[PathSegment]) -> Result<(), String> {
+    let segment = match path.first() {
+        Some(n) => n,
+        None => return Err("empty path".to_owned())
+    };
+    let (_, entry) = match layout.find(&segment.identifier) {
+        Some(n) => n,
+        None => return Err(format!("no such field {}", segment.identifier))
+    };
+    let rest = &path[1..];
+
+    match (&entry.field.block_type, &entry.array, segment.index) {
+        (BlockFieldType::Block(block), _, Some(_)) if !rest.is_empty() => validate_field_path(&StructLayout::of(block), rest),
+        (_, Some(array), Some(i)) if !rest.is_empty() && i < array.count => validate_field_path(&array.element, rest),
+        (_, Some(array), Some(_)) if !rest.is_empty() => Err(format!("{} only has {} elements", segment.identifier, array.count)),
+        (BlockFieldType::Block(_), _, _) | (_, Some(_), _) => Err(format!("{} needs an element index and a field in it", segment.identifier)),
+        (_, None, None) if rest.is_empty() => Ok(()),
+        _ => Err(format!("{} isn't a block or array", segment.identifier))
+    }
+}
+
+/// Find a value in a block element by its path and call `f` with it
+///
+/// Returns `Err` if the path doesn't exist in the definitions, or `Ok(None)` if it goes into a block element that the
+/// tag doesn't have.
+pub fn with_value_mut<R>(layout: &StructLayout, data: &mut TagStruct, path: &[PathSegment], f: &mut dyn FnMut(&LayoutEntry, &mut FieldValue) -> R) -> Result<Option<R>, String> {
+    let segment = match path.first() {
+        Some(n) => n,
+        None => return Err("empty path".to_owned())
+    };
+    let (index, entry) = match layout.find(&segment.identifier) {
+        Some(n) => n,
+        None => return Err(format!("no such field {}", segment.identifier))
+    };
+    let value = &mut data.values[index];
+    let rest = &path[1..];
+
+    match (value, &entry.field.block_type, segment.index) {
+        (FieldValue::Block(elements), BlockFieldType::Block(block), Some(i)) if !rest.is_empty() => match elements.get_mut(i) {
+            Some(element) => with_value_mut(&StructLayout::of(block), element, rest, f),
+            None => validate_field_path(&StructLayout::of(block), rest).map(|_| None)
+        },
+        (FieldValue::Array(elements), _, Some(i)) if !rest.is_empty() => {
+            let array = entry.array.as_ref().unwrap();
+            match elements.get_mut(i) {
+                Some(element) => with_value_mut(&array.element, element, rest, f),
+                None => Err(format!("{} only has {} elements", segment.identifier, array.count))
+            }
+        },
+        (FieldValue::Block(_), _, _) | (FieldValue::Array(_), _, _) => Err(format!("{} needs an element index and a field in it", segment.identifier)),
+        (value, _, None) if rest.is_empty() => Ok(Some(f(entry, value))),
+        _ => Err(format!("{} isn't a block or array", segment.identifier))
+    }
+}
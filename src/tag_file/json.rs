@@ -0,0 +1,317 @@
+//! Converting tags to and from JSON
+//!
+//! Each block element is an object keyed by field identifiers (see [`StructLayout`]). Padding is left out. Values are
+//! written as follows:
+//!
+//! - enums are the identifier of the option (or a number if out of range)
+//! - bitfields are an object of bit identifiers to booleans
+//! - blocks and arrays are arrays of objects
+//! - tag references are an object with `group` and `path`
+//! - tag data is a hexadecimal string
+//! - fourccs are a four character string
+//! - anything with more than one component (points, colors, bounds, etc.) is an array of numbers
+
+extern crate serde_json;
+use self::serde_json::{Map, Number, Value};
+
+use def_dumper::{BlockFieldType, Definitions, option_identifiers};
+use super::{FieldValue, LayoutEntry, StructLayout, TagStruct};
+
+/// Convert a float to a JSON number, keeping the shortest representation of the float
+pub fn float_to_json(f: f32) -> Value {
+    format!("{}", f).parse::<f64>().ok().and_then(Number::from_f64).map(Value::Number).unwrap_or(Value::Null)
+}
+
+/// Convert a FourCC to a string
+pub fn fourcc_to_string(fourcc: u32) -> String {
+    fourcc.to_be_bytes().iter().map(|&c| c as char).collect()
+}
+
+/// Convert a four character string to a FourCC
+pub fn string_to_fourcc(string: &str) -> Option<u32> {
+    let bytes = string.as_bytes();
+    if bytes.len() != 4 {
+        return None
+    }
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Convert bytes to an uppercase hexadecimal string
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// Convert a hexadecimal string to bytes
+pub fn from_hex(string: &str) -> Option<Vec<u8>> {
+    if !string.len().is_multiple_of(2) {
+        return None
+    }
+    (0..string.len()).step_by(2).map(|i| u8::from_str_radix(string.get(i..i+2)?, 16).ok()).collect()
+}
+
+/// Convert a block element to JSON
+pub fn struct_to_json(definitions: &Definitions, layout: &StructLayout, data: &TagStruct) -> Value {
+    let mut map = Map::new();
+    for (e, value) in layout.entries.iter().zip(data.values.iter()) {
+        if let Some(n) = value_to_json(definitions, e, value) {
+            map.insert(e.identifier.clone(), n);
+        }
+    }
+    Value::Object(map)
+}
+
+/// Convert the value of a field to JSON, returning `None` for padding
+pub fn value_to_json(definitions: &Definitions, entry: &LayoutEntry, value: &FieldValue) -> Option<Value> {
+    Some(match (value, &entry.field.block_type) {
+        (FieldValue::Integer(n), BlockFieldType::Enum(options)) => match option_identifiers(options, "option").get(*n as usize) {
+            Some(name) if *n >= 0 => Value::from(name.to_owned()),
+            _ => Value::from(*n)
+        },
+        (FieldValue::Integer(n), BlockFieldType::Flags(_, bits)) => {
+            let mut map = Map::new();
+            let identifiers = option_identifiers(bits, "bit");
+            for (i, name) in identifiers.iter().enumerate() {
+                map.insert(name.to_owned(), Value::from(n & (1 << i) != 0));
+            }
+            for i in identifiers.len()..(entry.size * 8) {
+                if n & (1 << i) != 0 {
+                    map.insert(format!("bit_{}", i), Value::from(true));
+                }
+            }
+            Value::Object(map)
+        },
+        (FieldValue::String(n), _) => Value::from(n.to_owned()),
+        (FieldValue::Integer(n), _) => Value::from(*n),
+        (FieldValue::Integers(n), _) => Value::from(n.to_owned()),
+        (FieldValue::Float(n), _) => float_to_json(*n),
+        (FieldValue::Floats(n), _) => Value::Array(n.iter().map(|f| float_to_json(*f)).collect()),
+        (FieldValue::FourCC(n), _) => Value::from(fourcc_to_string(*n)),
+        (FieldValue::Reference(reference), _) => {
+            let mut map = Map::new();
+            map.insert("group".to_owned(), reference.group(definitions).map(Value::from).unwrap_or(Value::Null));
+            map.insert("path".to_owned(), Value::from(reference.path.to_owned()));
+            Value::Object(map)
+        },
+        (FieldValue::Block(elements), BlockFieldType::Block(block)) => {
+            let layout = StructLayout::of(block);
+            Value::Array(elements.iter().map(|e| struct_to_json(definitions, &layout, e)).collect())
+        },
+        (FieldValue::Array(elements), _) => {
+            let array = entry.array.as_ref()?;
+            Value::Array(elements.iter().map(|e| struct_to_json(definitions, &array.element, e)).collect())
+        },
+        (FieldValue::Data(n), _) => Value::from(to_hex(n)),
+        _ => return None
+    })
+}
+
+/// Apply a JSON object to a block element, changing only the fields present in the object
+///
+/// `path` is used for error messages.
+pub fn apply_json(definitions: &Definitions, layout: &StructLayout, data: &mut TagStruct, json: &Value, path: &str) -> Option<()> {
+    let map = match json.as_object() {
+        Some(n) => n,
+        None => {
+            eprintln!("Expected an object for {}", if path.is_empty() { "the tag" } else { path });
+            return None
+        }
+    };
+
+    for (key, json_value) in map {
+        let field_path = if path.is_empty() { key.to_owned() } else { format!("{}.{}", path, key) };
+        let (index, entry) = match layout.find(key) {
+            Some(n) => n,
+            None => {
+                eprintln!("No such field {}", field_path);
+                return None
+            }
+        };
+        apply_json_value(definitions, entry, &mut data.values[index], json_value, &field_path)?;
+    }
+
+    Some(())
+}
+
+/// Set the value of a field from JSON
+pub fn apply_json_value(definitions: &Definitions, entry: &LayoutEntry, value: &mut FieldValue, json: &Value, path: &str) -> Option<()> {
+    fn invalid(path: &str, expected: &str) -> Option<()> {
+        eprintln!("Expected {} for {}", expected, path);
+        None
+    }
+
+    fn json_float(json: &Value) -> Option<f32> {
+        json.as_f64().map(|f| f as f32)
+    }
+
+    /// Get a JSON integer if it's from `minimum` to `maximum`
+    fn json_integer(json: &Value, (minimum, maximum): (i64, i64)) -> Option<i64> {
+        json.as_i64().filter(|i| (minimum..=maximum).contains(i))
+    }
+
+    /// Get the range of a signed integer of the given size in bytes
+    fn signed_range(size: usize) -> (i64, i64) {
+        let half = 1i64 << (size * 8 - 1);
+        (-half, half - 1)
+    }
+
+    fn invalid_integer(path: &str, (minimum, maximum): (i64, i64)) -> Option<()> {
+        invalid(path, &format!("an integer from {} to {}", minimum, maximum))
+    }
+
+    match (value, &entry.field.block_type) {
+        (FieldValue::Integer(n), BlockFieldType::Enum(options)) => {
+            *n = match json {
+                Value::String(s) => match option_identifiers(options, "option").iter().position(|o| o == s).or_else(|| options.iter().position(|o| &o.name == s)) {
+                    Some(i) => i as i64,
+                    None => return invalid(path, "an enum option")
+                },
+                _ => match json_integer(json, signed_range(2)) {
+                    Some(i) => i,
+                    None => return invalid(path, "an enum option")
+                }
+            }
+        },
+        (FieldValue::Integer(n), BlockFieldType::Flags(_, bits)) => match json {
+            Value::Object(map) => {
+                let identifiers = option_identifiers(bits, "bit");
+                for (key, set) in map {
+                    let bit = match identifiers.iter().position(|b| b == key).or_else(|| key.strip_prefix("bit_").and_then(|b| b.parse::<usize>().ok())) {
+                        Some(b) if b < entry.size * 8 => b,
+                        _ => {
+                            eprintln!("No such bit {} in {}", key, path);
+                            return None
+                        }
+                    };
+                    match set.as_bool() {
+                        Some(true) => *n |= 1 << bit,
+                        Some(false) => *n &= !(1 << bit),
+                        None => return invalid(path, "booleans")
+                    }
+                }
+            },
+            _ => match json_integer(json, (0, (1i64 << (entry.size * 8)) - 1)) {
+                Some(i) => *n = i,
+                None => return invalid(path, "an object of bits")
+            }
+        },
+        (FieldValue::String(n), _) => match json.as_str() {
+            Some(s) if s.len() < entry.size => *n = s.to_owned(),
+            _ => return invalid(path, "a string of 31 characters or less")
+        },
+        (FieldValue::Integer(n), block_type) => {
+            // Colors are read unsigned
+            let range = match block_type {
+                BlockFieldType::Primitive("color_rgb_int") | BlockFieldType::Primitive("color_argb_int") => (0, u32::MAX as i64),
+                _ => signed_range(entry.size)
+            };
+            match json_integer(json, range) {
+                Some(i) => *n = i,
+                None => return invalid_integer(path, range)
+            }
+        },
+        (FieldValue::Integers(n), _) => {
+            let range = signed_range(entry.size / n.len());
+            match json.as_array().map(|a| a.iter().map(|i| json_integer(i, range)).collect::<Option<Vec<i64>>>()) {
+                Some(Some(a)) if a.len() == n.len() => *n = a,
+                _ => return invalid(path, &format!("an array of {} integers from {} to {}", n.len(), range.0, range.1))
+            }
+        },
+        (FieldValue::Float(n), _) => match json_float(json) {
+            Some(f) => *n = f,
+            None => return invalid(path, "a number")
+        },
+        (FieldValue::Floats(n), _) => match json.as_array().map(|a| a.iter().map(json_float).collect::<Option<Vec<f32>>>()) {
+            Some(Some(a)) if a.len() == n.len() => *n = a,
+            _ => return invalid(path, &format!("an array of {} numbers", n.len()))
+        },
+        (FieldValue::FourCC(n), _) => match json.as_str().and_then(string_to_fourcc) {
+            Some(f) => *n = f,
+            None => return invalid(path, "a four character string")
+        },
+        (FieldValue::Reference(reference), _) => {
+            let (group, tag_path) = match json {
+                Value::Null => (None, String::new()),
+                Value::Object(map) => (map.get("group").and_then(|g| g.as_str()), map.get("path").and_then(|p| p.as_str()).unwrap_or("").to_owned()),
+                _ => return invalid(path, "an object with a group and path")
+            };
+            reference.fourcc = match group {
+                Some(g) => match definitions.groups.get(g) {
+                    Some(g) => g.fourcc,
+                    None => {
+                        eprintln!("No such group {} for {}", g, path);
+                        return None
+                    }
+                },
+                None => 0xFFFFFFFF
+            };
+            reference.path = tag_path;
+        },
+        (FieldValue::Block(elements), BlockFieldType::Block(block)) => {
+            let array = match json.as_array() {
+                Some(n) => n,
+                None => return invalid(path, "an array")
+            };
+            let layout = StructLayout::of(block);
+            elements.truncate(array.len());
+            while elements.len() < array.len() {
+                elements.push(TagStruct::new(definitions, &layout));
+            }
+            for (i, (element, json)) in elements.iter_mut().zip(array.iter()).enumerate() {
+                apply_json(definitions, &layout, element, json, &format!("{}[{}]", path, i))?;
+            }
+        },
+        (FieldValue::Array(elements), _) => {
+            let array = entry.array.as_ref()?;
+            match json.as_array() {
+                Some(a) if a.len() == elements.len() => {
+                    for (i, (element, json)) in elements.iter_mut().zip(a.iter()).enumerate() {
+                        apply_json(definitions, &array.element, element, json, &format!("{}[{}]", path, i))?;
+                    }
+                },
+                _ => return invalid(path, &format!("an array of {} objects", elements.len()))
+            }
+        },
+        (FieldValue::Data(n), BlockFieldType::TagData(_, max_length)) => match json.as_str().and_then(from_hex) {
+            Some(d) if d.len() <= *max_length => *n = d,
+            Some(_) => return invalid(path, &format!("no more than {} bytes", max_length)),
+            None => return invalid(path, "a hexadecimal string")
+        },
+        _ => {
+            eprintln!("{} can't be set", path);
+            return None
+        }
+    }
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use self::serde_json::json;
+
+    use super::*;
+    use tag_file::TagFile;
+    use tag_file::tests::definitions;
+
+    #[test]
+    fn integer_ranges() {
+        let definitions = definitions();
+        let layout = StructLayout::of(&definitions.groups["weapon"].block);
+        let apply = |values: Value| {
+            let mut tag = TagFile::new(&definitions, "weapon").unwrap();
+            apply_json(&definitions, &layout, &mut tag.data, &values, "").map(|_| struct_to_json(&definitions, &layout, &tag.data))
+        };
+
+        let tag = apply(json!({"triggers": [{"count": 32767}, {"count": -32768}], "trigger_index": -1, "flags": 3})).unwrap();
+        assert_eq!(tag["triggers"][0]["count"], 32767);
+        assert_eq!(tag["triggers"][1]["count"], -32768);
+        assert_eq!(tag["flags"], json!({"a": true, "b_c": true}));
+
+        assert!(apply(json!({"triggers": [{"count": 32768}]})).is_none());
+        assert!(apply(json!({"triggers": [{"count": 70000}]})).is_none());
+        assert!(apply(json!({"trigger_index": -32769})).is_none());
+        assert!(apply(json!({"type": 40000})).is_none());
+        assert!(apply(json!({"flags": 0x100000000i64})).is_none());
+        assert!(apply(json!({"flags": -1})).is_none());
+    }
+}
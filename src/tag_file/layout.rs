@@ -0,0 +1,93 @@
+//! Layout of block elements as they are stored in tag files
+
+use def_dumper::{Block, BlockFieldType, Field};
+
+/// Fields of a block element (or array element) with their offsets
+pub struct StructLayout<'a> {
+    pub entries: Vec<LayoutEntry<'a>>,
+    pub size: usize
+}
+
+/// A field that takes up space in a block element
+pub struct LayoutEntry<'a> {
+    pub field: &'a Field,
+    pub identifier: String,
+    pub offset: usize,
+    pub size: usize,
+    pub array: Option<ArrayLayout<'a>>
+}
+
+/// The fields between an array start and its array end, repeated `count` times
+pub struct ArrayLayout<'a> {
+    pub count: usize,
+    pub element: StructLayout<'a>
+}
+
+impl<'a> StructLayout<'a> {
+    /// Lay out the fields of a block
+    ///
+    /// Fields that take up no space (sections, array ends, etc.) are left out. Fields inside an array are put in an
+    /// [`ArrayLayout`] attached to the array start.
+    pub fn of(block: &'a Block) -> StructLayout<'a> {
+        Self::of_fields(&block.fields)
+    }
+
+    /// Lay out a list of fields, stopping at the end of the array if they're in one
+    pub fn of_fields(fields: &'a [Field]) -> StructLayout<'a> {
+        Self::parse(&mut fields.iter())
+    }
+
+    fn parse(fields: &mut std::slice::Iter<'a, Field>) -> StructLayout<'a> {
+        let mut entries = Vec::<LayoutEntry<'a>>::new();
+        let mut offset = 0;
+
+        while let Some(field) = fields.next() {
+            let (size, array) = match &field.block_type {
+                BlockFieldType::ArrayEnd => break,
+                BlockFieldType::ArrayStart(count) => {
+                    let element = Self::parse(fields);
+                    (element.size * count, Some(ArrayLayout { count: *count, element }))
+                },
+                n => (n.size(), None)
+            };
+
+            if size == 0 && array.is_none() {
+                continue
+            }
+
+            entries.push(LayoutEntry {
+                field,
+                identifier: String::new(),
+                offset,
+                size,
+                array
+            });
+            offset += size;
+        }
+
+        // Give everything a unique identifier. Duplicates get a number appended in order of appearance.
+        let mut used = Vec::<String>::with_capacity(entries.len());
+        for e in &mut entries {
+            let base = match (&e.field.name, &e.field.block_type) {
+                (Some(n), _) if !n.identifier().is_empty() => n.identifier(),
+                (_, BlockFieldType::Padding(_, _)) => "padding".to_owned(),
+                _ => "unnamed".to_owned()
+            };
+            let mut identifier = base.clone();
+            let mut number = 2;
+            while used.contains(&identifier) {
+                identifier = format!("{}_{}", base, number);
+                number += 1;
+            }
+            used.push(identifier.clone());
+            e.identifier = identifier;
+        }
+
+        StructLayout { entries, size: offset }
+    }
+
+    /// Find an entry by its identifier
+    pub fn find(&self, identifier: &str) -> Option<(usize, &LayoutEntry<'a>)> {
+        self.entries.iter().enumerate().find(|(_,e)| e.identifier == identifier)
+    }
+}
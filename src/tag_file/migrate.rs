@@ -0,0 +1,396 @@
+//! Upgrading tags from the definitions of one guerilla build to another
+//!
+//! Groups are matched by FourCC. Fields are matched by name first and then by position, and only ever with a field
+//! holding the same kind of value. Enum options and bits are matched by name, too. Fields in the new definitions that
+//! nothing maps to are left as their defaults, as are enums set to an option that isn't in the new definitions, and bits
+//! that aren't in the new definitions are cleared.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use def_dumper::{BlockFieldType, Definitions, option_identifiers};
+use super::directory::TagDirectory;
+use super::{FieldValue, LayoutEntry, StructLayout, TagFile, TagStruct, default_value};
+
+/// How the fields of a group were mapped between two builds
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct MigrationReport {
+    /// Fields in the old definitions that have nowhere to go, so their values are dropped
+    pub dropped: Vec<String>,
+
+    /// Fields in the new definitions that nothing maps to, so they are left as their defaults
+    pub defaulted: Vec<String>,
+
+    /// Fields that were mapped by position to a field with a different name, as `old -> new`
+    pub renamed: Vec<String>,
+
+    /// Enum options and bits in the old definitions that aren't in the new definitions, as `field:option`
+    ///
+    /// Enums set to one of these options are reset to their defaults, and these bits are cleared.
+    pub unmatched_options: Vec<String>
+}
+
+impl MigrationReport {
+    /// Returns true if every field was mapped to a field of the same name
+    pub fn is_clean(&self) -> bool {
+        self.dropped.is_empty() && self.defaulted.is_empty() && self.renamed.is_empty() && self.unmatched_options.is_empty()
+    }
+}
+
+/// Get what kind of value a field holds, or `None` if it's padding and isn't worth mapping
+fn field_kind(entry: &LayoutEntry) -> Option<String> {
+    if entry.array.is_some() {
+        return Some("array".to_owned())
+    }
+
+    Some(match &entry.field.block_type {
+        BlockFieldType::Primitive(type_name) => type_name.to_string(),
+        BlockFieldType::Range(type_name) => format!("range {}", type_name),
+        BlockFieldType::Enum(_) => "enum".to_owned(),
+        BlockFieldType::Flags(_, _) => "flags".to_owned(),
+        BlockFieldType::Index(_, size) => format!("index {}", size),
+        BlockFieldType::Reference(_) => "reference".to_owned(),
+        BlockFieldType::TagData(_, _) => "data".to_owned(),
+        BlockFieldType::Block(_) => "block".to_owned(),
+        BlockFieldType::Padding(_, _) => return None,
+        _ => format!("raw {}", entry.size)
+    })
+}
+
+/// Returns true if the field has a name of its own (rather than a fallback identifier)
+fn is_named(entry: &LayoutEntry) -> bool {
+    entry.field.name.as_ref().is_some_and(|n| !n.identifier().is_empty())
+}
+
+/// Match up the fields of an old and new block element
+///
+/// Returns the index of the old entry that maps to each new entry.
+pub fn map_fields(old: &StructLayout, new: &StructLayout) -> Vec<Option<usize>> {
+    let old_kinds: Vec<Option<String>> = old.entries.iter().map(field_kind).collect();
+    let new_kinds: Vec<Option<String>> = new.entries.iter().map(field_kind).collect();
+    let mut used = vec![false; old.entries.len()];
+    let mut mapping = vec![None; new.entries.len()];
+
+    // By name
+    for (n, entry) in new.entries.iter().enumerate() {
+        if new_kinds[n].is_none() || !is_named(entry) {
+            continue
+        }
+        let found = old.entries.iter().enumerate().position(|(o, e)| !used[o] && old_kinds[o] == new_kinds[n] && e.identifier == entry.identifier);
+        if let Some(o) = found {
+            used[o] = true;
+            mapping[n] = Some(o);
+        }
+    }
+
+    // By position
+    for n in 0..new.entries.len() {
+        if mapping[n].is_none() && new_kinds[n].is_some() && n < old.entries.len() && !used[n] && old_kinds[n] == new_kinds[n] {
+            used[n] = true;
+            mapping[n] = Some(n);
+        }
+    }
+
+    mapping
+}
+
+/// Get the layouts of the elements of a block or array mapped from one to another
+fn element_layouts<'a>(old: &'a LayoutEntry, new: &'a LayoutEntry) -> Option<(StructLayout<'a>, StructLayout<'a>)> {
+    match (&old.field.block_type, &new.field.block_type) {
+        (BlockFieldType::Block(old_block), BlockFieldType::Block(new_block)) => Some((StructLayout::of(old_block), StructLayout::of(new_block))),
+        _ => None
+    }
+}
+
+fn report_struct(old: &StructLayout, new: &StructLayout, prefix: &str, report: &mut MigrationReport) {
+    let mapping = map_fields(old, new);
+
+    for (n, entry) in new.entries.iter().enumerate() {
+        let path = format!("{}{}", prefix, entry.identifier);
+        let o = match mapping[n] {
+            Some(n) => n,
+            None => {
+                if field_kind(entry).is_some() {
+                    report.defaulted.push(path);
+                }
+                continue
+            }
+        };
+
+        let old_entry = &old.entries[o];
+        if old_entry.identifier != entry.identifier {
+            report.renamed.push(format!("{}{} -> {}", prefix, old_entry.identifier, path));
+        }
+        if let Some((old_identifiers, new_identifiers)) = option_identifier_pairs(old_entry, entry) {
+            for i in old_identifiers.iter().filter(|i| !new_identifiers.contains(i)) {
+                report.unmatched_options.push(format!("{}:{}", path, i));
+            }
+        }
+        if let (Some(old_array), Some(new_array)) = (&old_entry.array, &entry.array) {
+            report_struct(&old_array.element, &new_array.element, &format!("{}[].", path), report);
+        }
+        else if let Some((old_layout, new_layout)) = element_layouts(old_entry, entry) {
+            report_struct(&old_layout, &new_layout, &format!("{}[].", path), report);
+        }
+    }
+
+    for (o, entry) in old.entries.iter().enumerate() {
+        if field_kind(entry).is_some() && !mapping.contains(&Some(o)) {
+            report.dropped.push(format!("{}{}", prefix, entry.identifier));
+        }
+    }
+}
+
+/// Find the group in the new definitions that a group in the old definitions becomes
+pub fn find_group(old_definitions: &Definitions, new_definitions: &Definitions, group: &str) -> Option<String> {
+    let fourcc = old_definitions.groups.get(group)?.fourcc;
+    match new_definitions.group_by_fourcc(fourcc) {
+        Some((name, _)) => Some(name.to_owned()),
+        None => new_definitions.groups.get(group).map(|_| group.to_owned())
+    }
+}
+
+/// Work out how the fields of a group are mapped from the old definitions to the new definitions
+pub fn migration_report(old_definitions: &Definitions, new_definitions: &Definitions, group: &str) -> Option<MigrationReport> {
+    let new_group = match find_group(old_definitions, new_definitions, group) {
+        Some(n) => n,
+        None => {
+            eprintln!("Group {} isn't in the new definitions", group);
+            return None
+        }
+    };
+
+    let mut report = MigrationReport::default();
+    report_struct(&StructLayout::of(&old_definitions.groups[group].block), &StructLayout::of(&new_definitions.groups[&new_group].block), "", &mut report);
+    Some(report)
+}
+
+/// Get the identifiers of the old and new options of an enum or the bits of a bitfield
+fn option_identifier_pairs(old: &LayoutEntry, new: &LayoutEntry) -> Option<(Vec<String>, Vec<String>)> {
+    match (&old.field.block_type, &new.field.block_type) {
+        (BlockFieldType::Enum(old_options), BlockFieldType::Enum(new_options)) => Some((option_identifiers(old_options, "option"), option_identifiers(new_options, "option"))),
+        (BlockFieldType::Flags(_, old_bits), BlockFieldType::Flags(_, new_bits)) => Some((option_identifiers(old_bits, "bit"), option_identifiers(new_bits, "bit"))),
+        _ => None
+    }
+}
+
+/// Find the index of an option in the new options with the same identifier as one in the old options
+///
+/// Returns `None` if the option isn't in the new options. Values past the end of the old options can't be matched by
+/// name, so they keep their index.
+fn map_option(old_identifiers: &[String], new_identifiers: &[String], index: usize) -> Option<usize> {
+    match old_identifiers.get(index) {
+        Some(i) => new_identifiers.iter().position(|n| n == i),
+        None => Some(index)
+    }
+}
+
+fn migrate_value(definitions: &Definitions, old: &LayoutEntry, new: &LayoutEntry, value: &FieldValue) -> FieldValue {
+    match (value, &old.field.block_type, &new.field.block_type) {
+        (FieldValue::Array(elements), _, _) => {
+            let (old_array, new_array) = (old.array.as_ref().unwrap(), new.array.as_ref().unwrap());
+            let mapping = map_fields(&old_array.element, &new_array.element);
+            FieldValue::Array((0..new_array.count).map(|i| match elements.get(i) {
+                Some(e) => migrate_struct(definitions, &old_array.element, &new_array.element, &mapping, e),
+                None => TagStruct::new(definitions, &new_array.element)
+            }).collect())
+        },
+        (FieldValue::Block(elements), _, _) => match element_layouts(old, new) {
+            Some((old_layout, new_layout)) => {
+                let mapping = map_fields(&old_layout, &new_layout);
+                FieldValue::Block(elements.iter().map(|e| migrate_struct(definitions, &old_layout, &new_layout, &mapping, e)).collect())
+            },
+            None => value.clone()
+        },
+        (FieldValue::Integer(n), BlockFieldType::Enum(old_options), BlockFieldType::Enum(new_options)) if *n >= 0 => {
+            match map_option(&option_identifiers(old_options, "option"), &option_identifiers(new_options, "option"), *n as usize) {
+                Some(option) => FieldValue::Integer(option as i64),
+                None => default_value(definitions, new)
+            }
+        },
+        (FieldValue::Integer(n), BlockFieldType::Flags(_, old_bits), BlockFieldType::Flags(size, new_bits)) => {
+            let (old_identifiers, new_identifiers) = (option_identifiers(old_bits, "bit"), option_identifiers(new_bits, "bit"));
+            let mut bits = 0u32;
+            for i in (0..32).filter(|i| *n as u32 & (1 << i) != 0) {
+                if let Some(bit) = map_option(&old_identifiers, &new_identifiers, i).filter(|b| *b < 32) {
+                    bits |= 1 << bit;
+                }
+            }
+
+            // Keep the sign the same as if it were read from a tag
+            FieldValue::Integer(match *size {
+                "int8" => bits as i8 as i64,
+                "int16" => bits as i16 as i64,
+                _ => bits as i32 as i64
+            })
+        },
+        _ => value.clone()
+    }
+}
+
+fn migrate_struct(definitions: &Definitions, old: &StructLayout, new: &StructLayout, mapping: &[Option<usize>], data: &TagStruct) -> TagStruct {
+    let mut result = TagStruct::new(definitions, new);
+    for (n, o) in mapping.iter().enumerate() {
+        if let Some(o) = o {
+            result.values[n] = migrate_value(definitions, &old.entries[*o], &new.entries[n], &data.values[*o]);
+        }
+    }
+    result
+}
+
+/// Rewrite a tag read with the old definitions so it can be written with the new definitions
+pub fn migrate_tag(old_definitions: &Definitions, new_definitions: &Definitions, tag: &TagFile) -> Option<TagFile> {
+    let new_group = match find_group(old_definitions, new_definitions, &tag.group) {
+        Some(n) => n,
+        None => {
+            eprintln!("Group {} isn't in the new definitions", tag.group);
+            return None
+        }
+    };
+    let new_definition = &new_definitions.groups[&new_group];
+
+    let old_layout = StructLayout::of(tag.block(old_definitions)?);
+    let new_layout = StructLayout::of(&new_definition.block);
+    let mapping = map_fields(&old_layout, &new_layout);
+
+    Some(TagFile {
+        group: new_group,
+        version: new_definition.version,
+        data: migrate_struct(new_definitions, &old_layout, &new_layout, &mapping, &tag.data)
+    })
+}
+
+/// Migrate every tag in a tags directory, writing them to the same paths in another directory
+///
+/// Returns the groups of the tags that were migrated.
+pub fn migrate_directory(old_definitions: &Definitions, new_definitions: &Definitions, directory: &TagDirectory, output: &Path) -> Option<BTreeSet<String>> {
+    let mut groups = BTreeSet::new();
+
+    for file in directory.tags.values() {
+        let data = match std::fs::read(file) {
+            Ok(n) => n,
+            Err(e) => {
+                eprintln!("Can't read {}: {}", file.display(), e);
+                return None
+            }
+        };
+        let tag = TagFile::read(old_definitions, &data).or_else(|| {
+            eprintln!("Failed to read {}", file.display());
+            None
+        })?;
+        let migrated = migrate_tag(old_definitions, new_definitions, &tag)?;
+        let data = migrated.to_bytes(new_definitions)?;
+
+        let destination = output.join(file.strip_prefix(&directory.root).ok()?).with_extension(&migrated.group);
+        if let Err(e) = destination.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| std::fs::write(&destination, data)) {
+            eprintln!("Can't write {}: {}", destination.display(), e);
+            return None
+        }
+        groups.insert(tag.group);
+    }
+
+    Some(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+    use self::serde_json::{Value, json};
+
+    use super::*;
+    use def_dumper::read_definitions;
+    use tag_file::tests::{DEFINITIONS, definitions, tag_with};
+
+    /// The test definitions with a renamed field, changed options and bits, a field inserted into a block, a longer
+    /// array and an index that's a different size
+    fn new_definitions() -> Definitions {
+        let mut definitions: Value = serde_json::from_str(DEFINITIONS).unwrap();
+        let fields = definitions["groups"]["weapon"]["block"]["fields"].as_array_mut().unwrap();
+        fields[1]["fields"] = json!([{"name": "b c"}, {"name": "d"}]);
+        fields[2]["options"] = json!([{"name": "shotgun"}, {"name": "pistol"}]);
+        fields[4]["name"] = json!("fire rate");
+        fields[7]["block"]["fields"].as_array_mut().unwrap().insert(0, json!({"name": "charge", "type": "float"}));
+        fields[7]["block"]["length"] = json!(28);
+        fields[9]["count"] = json!(3);
+        fields[16]["size"] = json!("int8");
+        fields[17]["count"] = json!(3);
+        definitions["groups"]["weapon"]["block"]["length"] = json!(140);
+        read_definitions(&serde_json::to_vec(&definitions).unwrap()).unwrap()
+    }
+
+    fn old_tag(definitions: &Definitions, weapon_type: &str) -> TagFile {
+        tag_with(definitions, "weapon", json!({
+            "name": "my gun",
+            "flags": {"a": true, "b_c": true},
+            "type": weapon_type,
+            "rate": 2.5,
+            "triggers": [{"rounds_per_second": 8, "count": 3}, {"count": 4}],
+            "vals": [{"x": 1.5, "y": 2}, {"x": 3, "y": 4}],
+            "trigger_index": 1
+        }))
+    }
+
+    #[test]
+    fn map_fields_by_name_and_position() {
+        let (old_definitions, new_definitions) = (definitions(), new_definitions());
+        let old = StructLayout::of(&old_definitions.groups["weapon"].block);
+        let new = StructLayout::of(&new_definitions.groups["weapon"].block);
+        let mapping = map_fields(&old, &new);
+
+        let position = |layout: &StructLayout, identifier: &str| layout.entries.iter().position(|e| e.identifier == identifier).unwrap();
+        assert_eq!(mapping[position(&new, "name")], Some(position(&old, "name")));
+        assert_eq!(mapping[position(&new, "fire_rate")], Some(position(&old, "rate")));
+        assert_eq!(mapping[position(&new, "trigger_index")], None);
+
+        let triggers = |definitions: &Definitions| match &definitions.groups["weapon"].block.fields[7].block_type {
+            BlockFieldType::Block(block) => block.clone(),
+            _ => unreachable!()
+        };
+        let (old_triggers, new_triggers) = (triggers(&old_definitions), triggers(&new_definitions));
+        assert_eq!(map_fields(&StructLayout::of(&old_triggers), &StructLayout::of(&new_triggers)), vec![None, Some(0), Some(1), Some(2), None]);
+    }
+
+    #[test]
+    fn report() {
+        let report = migration_report(&definitions(), &new_definitions(), "weapon").unwrap();
+        assert_eq!(report, MigrationReport {
+            dropped: vec!["trigger_index".to_owned()],
+            defaulted: vec!["triggers[].charge".to_owned(), "trigger_index".to_owned()],
+            renamed: vec!["rate -> fire_rate".to_owned()],
+            unmatched_options: vec!["flags:a".to_owned(), "type:rifle".to_owned()]
+        });
+        assert!(!report.is_clean());
+        assert!(migration_report(&definitions(), &definitions(), "weapon").unwrap().is_clean());
+    }
+
+    #[test]
+    fn migrate() {
+        let (old_definitions, new_definitions) = (definitions(), new_definitions());
+
+        let migrated = migrate_tag(&old_definitions, &new_definitions, &old_tag(&old_definitions, "pistol")).unwrap();
+        assert_eq!(migrated, tag_with(&new_definitions, "weapon", json!({
+            "name": "my gun",
+            "flags": {"b_c": true},
+            "type": "pistol",
+            "fire_rate": 2.5,
+            "triggers": [{"rounds_per_second": 8, "count": 3}, {"count": 4}],
+            "vals": [{"x": 1.5, "y": 2}, {"x": 3, "y": 4}, {}]
+        })));
+        assert!(migrated.to_bytes(&new_definitions).is_some());
+
+        // An option that isn't in the new definitions is reset to the default
+        let migrated = migrate_tag(&old_definitions, &new_definitions, &old_tag(&old_definitions, "rifle")).unwrap();
+        assert_eq!(migrated.data, tag_with(&new_definitions, "weapon", json!({
+            "name": "my gun",
+            "flags": {"b_c": true},
+            "type": "shotgun",
+            "fire_rate": 2.5,
+            "triggers": [{"rounds_per_second": 8, "count": 3}, {"count": 4}],
+            "vals": [{"x": 1.5, "y": 2}, {"x": 3, "y": 4}, {}]
+        })).data);
+
+        // Migrating to the same definitions changes nothing
+        let tag = old_tag(&old_definitions, "pistol");
+        assert_eq!(migrate_tag(&old_definitions, &old_definitions, &tag).unwrap(), tag);
+    }
+}
@@ -0,0 +1,297 @@
+//! Reading and writing HEK tag files using dumped definitions
+
+extern crate byteorder;
+use self::byteorder::{ByteOrder, BigEndian};
+
+use def_dumper::{Block, Definitions};
+
+mod layout;
+pub use self::layout::*;
+
+mod value;
+pub use self::value::*;
+
+pub mod json;
+pub mod diff;
+pub mod directory;
+pub mod dependencies;
+pub mod rename;
+pub mod field_path;
+pub mod csv;
+pub mod migrate;
+
+/// Size of the header at the start of every tag file
+pub const TAG_HEADER_SIZE: usize = 0x40;
+
+/// FourCC at the end of the tag file header
+const BLAM_FOURCC: u32 = 0x626C616D;
+
+/// Calculate the CRC32 used for tag file checksums
+///
+/// This is a standard CRC32 without the final XOR.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// Read a null-terminated string from a fixed-size buffer
+fn read_fixed_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// A tag file
+#[derive(Clone, PartialEq, Debug)]
+pub struct TagFile {
+    pub group: String,
+    pub version: u16,
+    pub data: TagStruct
+}
+
+impl TagFile {
+    /// Make a new tag of the given group with default values
+    pub fn new(definitions: &Definitions, group: &str) -> Option<TagFile> {
+        let group_definition = match definitions.groups.get(group) {
+            Some(n) => n,
+            None => {
+                eprintln!("No such group {}", group);
+                return None
+            }
+        };
+
+        Some(TagFile {
+            group: group.to_owned(),
+            version: group_definition.version,
+            data: TagStruct::new(definitions, &StructLayout::of(&group_definition.block))
+        })
+    }
+
+    /// Read a tag file
+    pub fn read(definitions: &Definitions, data: &[u8]) -> Option<TagFile> {
+        if data.len() < TAG_HEADER_SIZE || BigEndian::read_u32(&data[0x3C..]) != BLAM_FOURCC {
+            eprintln!("Not a tag file");
+            return None
+        }
+
+        let fourcc = BigEndian::read_u32(&data[0x24..]);
+        let (group, group_definition) = match definitions.group_by_fourcc(fourcc) {
+            Some(n) => n,
+            None => {
+                eprintln!("Unknown tag group 0x{:08X}", fourcc);
+                return None
+            }
+        };
+
+        let version = BigEndian::read_u16(&data[0x38..]);
+        if version != group_definition.version && group_definition.version != 0 {
+            eprintln!("Warning: {} tag is version {}, but the definitions are for version {}", group, version, group_definition.version);
+        }
+
+        let mut cursor = Cursor { data: &data[TAG_HEADER_SIZE..] };
+        let mut elements = match read_block_elements(&mut cursor, &group_definition.block, 1) {
+            Some(n) => n,
+            None => {
+                eprintln!("Tag data is truncated or doesn't match the definitions");
+                return None
+            }
+        };
+
+        Some(TagFile {
+            group: group.to_owned(),
+            version,
+            data: elements.pop()?
+        })
+    }
+
+    /// Get the definition of the tag's main block
+    pub fn block<'a>(&self, definitions: &'a Definitions) -> Option<&'a Block> {
+        definitions.groups.get(&self.group).map(|g| &g.block)
+    }
+
+    /// Get every tag reference in the tag
+    pub fn references<'a>(&'a self, definitions: &Definitions) -> Vec<&'a TagReference> {
+        match self.block(definitions) {
+            Some(block) => self.data.references(&StructLayout::of(block)),
+            None => Vec::new()
+        }
+    }
+
+    /// Write the tag file, calculating its checksum
+    pub fn to_bytes(&self, definitions: &Definitions) -> Option<Vec<u8>> {
+        let group_definition = definitions.groups.get(&self.group)?;
+
+        let mut output = vec![0u8; TAG_HEADER_SIZE];
+        write_block_elements(definitions, &group_definition.block, std::slice::from_ref(&self.data), &mut output)?;
+
+        let checksum = crc32(&output[TAG_HEADER_SIZE..]);
+        BigEndian::write_u32(&mut output[0x24..], group_definition.fourcc);
+        BigEndian::write_u32(&mut output[0x28..], checksum);
+        BigEndian::write_u32(&mut output[0x2C..], TAG_HEADER_SIZE as u32);
+        BigEndian::write_u16(&mut output[0x38..], self.version);
+        BigEndian::write_u16(&mut output[0x3A..], 0x00FF);
+        BigEndian::write_u32(&mut output[0x3C..], BLAM_FOURCC);
+
+        Some(output)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    extern crate serde_json;
+
+    use std::path::PathBuf;
+
+    use super::*;
+    use super::directory::TagDirectory;
+    use def_dumper::{BlockFieldType, read_definitions};
+
+    /// A small weapon group that has one of almost every kind of field, and a model group it can reference
+    pub const DEFINITIONS: &str = r#"{
+        "exe_sha256sum": "",
+        "exe_pe_file_version": null,
+        "exe_pe_checksum": 0,
+        "groups": {
+            "model": {"supergroup": null, "fourcc": 1836016741, "version": 4, "block": {"name": "model_block", "maximum": 1, "length": 4, "fields": [
+                {"name": "scale", "type": "float"}
+            ]}},
+            "weapon": {"supergroup": null, "fourcc": 2003134832, "version": 2, "block": {"name": "weapon_block", "maximum": 1, "length": 132, "fields": [
+                {"name": "name", "type": "string"},
+                {"name": "flags", "type": "bitfield", "size": "int32", "fields": [{"name": "a"}, {"name": "b c"}]},
+                {"name": "type", "type": "enum", "options": [{"name": "rifle"}, {"name": "pistol"}]},
+                {"type": "padding", "size": "int8", "count": 2},
+                {"name": "rate", "type": "float"},
+                {"type": "section", "text": "stuff"},
+                {"name": "model", "type": "tag_reference", "allowed_groups": ["model"]},
+                {"name": "triggers", "type": "block", "block": {"name": "weapon_trigger_block", "maximum": 2, "length": 24, "fields": [
+                    {"name": "rounds per second", "type": "float"},
+                    {"name": "projectile", "type": "tag_reference", "allowed_groups": ["model", "weapon"]},
+                    {"name": "count", "type": "int16"},
+                    {"type": "padding", "size": "int8", "count": 2}
+                ]}},
+                {"name": "data", "type": "tag_data", "data_type": "bytes", "max_length": 1024},
+                {"name": "vals", "type": "array_start", "count": 2},
+                {"name": "x", "type": "float"},
+                {"name": "y", "type": "int16"},
+                {"type": "padding", "size": "int8", "count": 2},
+                {"type": "array_end"},
+                {"name": "bounds", "type": "float", "bounds": true},
+                {"name": "color", "type": "color_rgb"},
+                {"name": "trigger index", "type": "index", "reference": "weapon_trigger_block", "size": "int16"},
+                {"type": "padding", "size": "int8", "count": 2}
+            ]}}
+        }
+    }"#;
+
+    /// Read [`DEFINITIONS`]
+    pub fn definitions() -> Definitions {
+        read_definitions(DEFINITIONS.as_bytes()).unwrap()
+    }
+
+    /// Make a tag with values set from JSON
+    pub fn tag_with(definitions: &Definitions, group: &str, values: serde_json::Value) -> TagFile {
+        let mut tag = TagFile::new(definitions, group).unwrap();
+        let layout = StructLayout::of(&definitions.groups[group].block);
+        json::apply_json(definitions, &layout, &mut tag.data, &values, "").unwrap();
+        tag
+    }
+
+    /// Make an empty directory for a test
+    pub fn temporary_directory(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("gorilla-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    /// Make a tags directory for a test with the given tags in it
+    pub fn tags_directory(definitions: &Definitions, name: &str, tags: &[(&str, &TagFile)]) -> TagDirectory {
+        let root = temporary_directory(name);
+        for (tag_path, tag) in tags {
+            let file = tag_path.split('\\').fold(root.clone(), |path, c| path.join(c));
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, tag.to_bytes(definitions).unwrap()).unwrap();
+        }
+        TagDirectory::scan(definitions, &root).unwrap()
+    }
+
+    #[test]
+    fn default_tag() {
+        let definitions = definitions();
+        let tag = TagFile::new(&definitions, "weapon").unwrap();
+        let bytes = tag.to_bytes(&definitions).unwrap();
+
+        // One element with empty blocks, a null model reference and no tag data, so nothing follows it
+        assert_eq!(bytes.len(), TAG_HEADER_SIZE + 132);
+        assert_eq!(BigEndian::read_u32(&bytes[0x24..]), 2003134832);
+        assert_eq!(BigEndian::read_u32(&bytes[0x28..]), crc32(&bytes[TAG_HEADER_SIZE..]));
+        assert_eq!(BigEndian::read_u16(&bytes[0x38..]), 2);
+        assert_eq!(BigEndian::read_u32(&bytes[0x3C..]), BLAM_FOURCC);
+
+        // The model reference defaults to its only group, and the trigger index to none
+        let body = &bytes[TAG_HEADER_SIZE..];
+        assert_eq!(BigEndian::read_u32(&body[0x2C..]), 1836016741);
+        assert_eq!(BigEndian::read_i32(&body[0x38..]), -1);
+        assert_eq!(BigEndian::read_i16(&body[0x80..]), -1);
+
+        assert_eq!(TagFile::read(&definitions, &bytes).unwrap(), tag);
+    }
+
+    #[test]
+    fn round_trip() {
+        let definitions = definitions();
+        let mut tag = TagFile::new(&definitions, "weapon").unwrap();
+        let overrides = serde_json::json!({
+            "name": "my gun",
+            "flags": {"b_c": true},
+            "type": "pistol",
+            "rate": 2.5,
+            "model": {"group": "model", "path": "weapons\\gun\\gun"},
+            "triggers": [
+                {"rounds_per_second": 8, "projectile": {"group": "weapon", "path": "weapons\\gun\\bullet"}, "count": 3},
+                {"rounds_per_second": 1}
+            ],
+            "data": "DEADBEEF",
+            "vals": [{"x": 1.5, "y": 2}, {"x": 3, "y": 4}],
+            "bounds": [0.1, 0.2],
+            "color": [1, 0.5, 0],
+            "trigger_index": 1
+        });
+        let layout = StructLayout::of(&definitions.groups["weapon"].block);
+        json::apply_json(&definitions, &layout, &mut tag.data, &overrides, "").unwrap();
+
+        let bytes = tag.to_bytes(&definitions).unwrap();
+        let read = TagFile::read(&definitions, &bytes).unwrap();
+        assert_eq!(read, tag);
+        assert_eq!(read.to_bytes(&definitions).unwrap(), bytes);
+        assert_eq!(json::struct_to_json(&definitions, &layout, &read.data)["triggers"][0]["count"], 3);
+    }
+
+    #[test]
+    fn empty_block_count() {
+        let mut definitions = definitions();
+        if let BlockFieldType::Block(triggers) = &mut definitions.groups.get_mut("weapon").unwrap().block.fields[7].block_type {
+            triggers.fields.clear();
+            triggers.length = 0;
+        }
+        let mut bytes = TagFile::new(&definitions, "weapon").unwrap().to_bytes(&definitions).unwrap();
+
+        BigEndian::write_u32(&mut bytes[TAG_HEADER_SIZE + 0x3C..], 2);
+        assert!(TagFile::read(&definitions, &bytes).is_some());
+        BigEndian::write_u32(&mut bytes[TAG_HEADER_SIZE + 0x3C..], 0xFFFFFFFF);
+        assert!(TagFile::read(&definitions, &bytes).is_none());
+    }
+
+    #[test]
+    fn truncated_tag() {
+        let definitions = definitions();
+        let bytes = TagFile::new(&definitions, "weapon").unwrap().to_bytes(&definitions).unwrap();
+        assert!(TagFile::read(&definitions, &bytes[..bytes.len() - 1]).is_none());
+        assert!(TagFile::read(&definitions, &bytes[..TAG_HEADER_SIZE - 1]).is_none());
+    }
+}
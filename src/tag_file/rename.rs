@@ -0,0 +1,271 @@
+//! Renaming and moving tags while keeping references to them intact
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use def_dumper::Definitions;
+use super::directory::{TagDirectory, normalize_tag_path};
+
+/// A tag that is moved or has references rewritten
+#[derive(Clone, PartialEq, Debug)]
+pub struct TagUpdate {
+    /// Normalized path of the tag
+    pub tag: String,
+
+    /// Path the tag is moved to (if it's moved)
+    pub moved_to: Option<String>,
+
+    /// Number of references to moved tags that are rewritten
+    pub references: usize
+}
+
+/// Split a tag path into its path and extension
+fn split_extension(tag_path: &str) -> Option<(&str, &str)> {
+    let dot = tag_path.rfind('.')?;
+    if tag_path[dot..].contains('\\') {
+        return None
+    }
+    Some((&tag_path[..dot], &tag_path[dot + 1..]))
+}
+
+/// Get the file path of a tag path in a tags directory
+fn file_path(directory: &TagDirectory, tag_path: &str) -> PathBuf {
+    let mut path = directory.root.clone();
+    for c in tag_path.split('\\') {
+        path.push(c);
+    }
+    path
+}
+
+/// Work out where each tag goes when moving `from` to `to`
+///
+/// `from` can either be a tag (with its extension) or a folder of tags, in which case `to` is the folder to move them
+/// to. Tags can't change groups. The returned map is from normalized tag paths to new tag paths.
+pub fn plan_moves(directory: &TagDirectory, from: &str, to: &str) -> Option<BTreeMap<String, String>> {
+    let from = normalize_tag_path(from).trim_end_matches('\\').to_owned();
+    let to = to.replace('/', "\\").trim_end_matches('\\').to_owned();
+    let mut moves = BTreeMap::new();
+
+    if directory.tags.contains_key(&from) {
+        let from_group = split_extension(&from).map(|s| s.1);
+        let to_group = split_extension(&to).map(|s| s.1.to_lowercase());
+        if from_group != to_group.as_deref() {
+            eprintln!("{} and {} must have the same extension, as tags can't change groups", from, to);
+            return None
+        }
+        moves.insert(from, to);
+    }
+    else {
+        let prefix = format!("{}\\", from);
+        for tag in directory.tags.keys().filter(|t| t.starts_with(&prefix)) {
+            moves.insert(tag.to_owned(), format!("{}\\{}", to, &tag[prefix.len()..]));
+        }
+        if moves.is_empty() {
+            eprintln!("There is no tag or folder of tags called {}", from);
+            return None
+        }
+    }
+
+    for (old, new) in &moves {
+        let normalized = normalize_tag_path(new);
+        if directory.tags.contains_key(&normalized) && !moves.contains_key(&normalized) {
+            eprintln!("Can't move {} to {}, as a tag is already there", old, new);
+            return None
+        }
+    }
+
+    Some(moves)
+}
+
+/// Move tags and rewrite every reference to them in the tags directory
+///
+/// If `dry_run` is set, nothing is changed, but the tags that would be changed are still returned.
+pub fn rename_tags(definitions: &Definitions, directory: &TagDirectory, from: &str, to: &str, dry_run: bool) -> Option<Vec<TagUpdate>> {
+    let moves = plan_moves(directory, from, to)?;
+
+    // Read everything first so nothing is written if a tag being moved can't be read
+    let mut changed = Vec::new();
+    for tag_path in directory.tags.keys() {
+        let moved_to = moves.get(tag_path).cloned();
+
+        let mut tag = match directory.read(definitions, tag_path) {
+            Some(n) => n,
+            None => {
+                if moved_to.is_some() {
+                    return None
+                }
+                eprintln!("Warning: can't update references in {}", tag_path);
+                continue
+            }
+        };
+
+        let layout = super::StructLayout::of(tag.block(definitions)?);
+        let mut references = 0;
+        tag.data.visit_references_mut(&layout, &mut |reference| {
+            let new_path = directory.resolve(definitions, reference).and_then(|r| moves.get(&r)).and_then(|n| split_extension(n));
+            if let Some((new_path, _)) = new_path {
+                reference.path = new_path.to_owned();
+                references += 1;
+            }
+        });
+
+        if references > 0 || moved_to.is_some() {
+            changed.push((TagUpdate { tag: tag_path.to_owned(), moved_to, references }, tag));
+        }
+    }
+
+    if !dry_run {
+        // Tags whose path only changes case are written to a temporary file first, as the old file may be the same file
+        let mut case_changes = Vec::new();
+        for (update, tag) in &changed {
+            let data = tag.to_bytes(definitions)?;
+            let old_file = &directory.tags[&update.tag];
+            let mut destination = match &update.moved_to {
+                Some(n) => file_path(directory, n),
+                None => old_file.clone()
+            };
+            if update.moved_to.as_ref().is_some_and(|n| normalize_tag_path(n) == update.tag) && &destination != old_file {
+                let mut temporary = destination.clone().into_os_string();
+                temporary.push(".gorilla-rename");
+                let temporary = PathBuf::from(temporary);
+                case_changes.push((old_file, temporary.clone(), destination));
+                destination = temporary;
+            }
+            if let Err(e) = destination.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| std::fs::write(&destination, data)) {
+                eprintln!("Can't write {}: {}", destination.display(), e);
+                return None
+            }
+        }
+
+        // Remove the old files once everything has been written, unless a tag was just written there
+        let destinations: BTreeSet<String> = moves.values().map(|n| normalize_tag_path(n)).collect();
+        for old in moves.keys().filter(|old| !destinations.contains(*old)) {
+            let old_file = &directory.tags[old];
+            if let Err(e) = std::fs::remove_file(old_file) {
+                eprintln!("Can't remove {}: {}", old_file.display(), e);
+            }
+        }
+        for (old_file, temporary, destination) in case_changes {
+            if let Err(e) = std::fs::remove_file(old_file).and_then(|_| std::fs::rename(&temporary, &destination)) {
+                eprintln!("Can't move {} to {}: {}", temporary.display(), destination.display(), e);
+            }
+        }
+    }
+
+    Some(changed.into_iter().map(|(update, _)| update).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use std::path::Path;
+
+    use super::*;
+    use tag_file::{TAG_HEADER_SIZE, TagFile, crc32};
+    use tag_file::tests::{definitions, tag_with, tags_directory};
+
+    /// Read a tag file, checking its checksum
+    fn read(definitions: &Definitions, file: &Path) -> TagFile {
+        let data = std::fs::read(file).unwrap();
+        assert_eq!(u32::from_be_bytes([data[0x28], data[0x29], data[0x2A], data[0x2B]]), crc32(&data[TAG_HEADER_SIZE..]));
+        TagFile::read(definitions, &data).unwrap()
+    }
+
+    fn model_path(definitions: &Definitions, tag: &TagFile) -> String {
+        tag.references(definitions)[0].path.clone()
+    }
+
+    fn gun(definitions: &Definitions, model: &str) -> TagFile {
+        tag_with(definitions, "weapon", serde_json::json!({"model": {"group": "model", "path": model}}))
+    }
+
+    fn model(definitions: &Definitions, scale: f32) -> TagFile {
+        tag_with(definitions, "model", serde_json::json!({"scale": scale}))
+    }
+
+    #[test]
+    fn move_tag() {
+        let definitions = definitions();
+        let directory = tags_directory(&definitions, "rename_move_tag", &[
+            ("weapons\\gun\\gun.weapon", &gun(&definitions, "weapons\\gun\\gun")),
+            ("weapons\\gun\\gun.model", &model(&definitions, 1.0))
+        ]);
+
+        let updates = rename_tags(&definitions, &directory, "weapons/gun/gun.model", "weapons\\rifle\\rifle.model", true).unwrap();
+        assert_eq!(updates, vec![
+            TagUpdate { tag: "weapons\\gun\\gun.model".to_owned(), moved_to: Some("weapons\\rifle\\rifle.model".to_owned()), references: 0 },
+            TagUpdate { tag: "weapons\\gun\\gun.weapon".to_owned(), moved_to: None, references: 1 }
+        ]);
+        assert_eq!(TagDirectory::scan(&definitions, &directory.root).unwrap().tags, directory.tags);
+
+        assert_eq!(rename_tags(&definitions, &directory, "weapons/gun/gun.model", "weapons\\rifle\\rifle.model", false).unwrap(), updates);
+        let moved = TagDirectory::scan(&definitions, &directory.root).unwrap();
+        assert_eq!(moved.tags.keys().collect::<Vec<_>>(), vec!["weapons\\gun\\gun.weapon", "weapons\\rifle\\rifle.model"]);
+        assert_eq!(read(&definitions, &moved.tags["weapons\\rifle\\rifle.model"]), model(&definitions, 1.0));
+        assert_eq!(model_path(&definitions, &read(&definitions, &moved.tags["weapons\\gun\\gun.weapon"])), "weapons\\rifle\\rifle");
+
+        // Tags can't change groups
+        assert!(plan_moves(&moved, "weapons\\rifle\\rifle.model", "weapons\\rifle\\rifle.weapon").is_none());
+        assert!(plan_moves(&moved, "weapons\\rifle\\rifle.model", "weapons\\gun\\gun.model").is_some());
+    }
+
+    #[test]
+    fn move_folder() {
+        let definitions = definitions();
+        let directory = tags_directory(&definitions, "rename_move_folder", &[
+            ("weapons\\gun\\gun.weapon", &gun(&definitions, "weapons\\gun\\gun")),
+            ("weapons\\gun\\gun.model", &model(&definitions, 1.0)),
+            ("other.weapon", &gun(&definitions, "Weapons\\Gun\\Gun"))
+        ]);
+
+        // Tags can't be moved onto a tag that stays where it is
+        assert!(plan_moves(&directory, "other.weapon", "Weapons\\Gun\\Gun.weapon").is_none());
+        assert!(plan_moves(&directory, "nothing", "something").is_none());
+
+        rename_tags(&definitions, &directory, "weapons", "old\\weapons\\", false).unwrap();
+        let moved = TagDirectory::scan(&definitions, &directory.root).unwrap();
+        assert_eq!(moved.tags.keys().collect::<Vec<_>>(), vec!["old\\weapons\\gun\\gun.model", "old\\weapons\\gun\\gun.weapon", "other.weapon"]);
+        assert_eq!(model_path(&definitions, &read(&definitions, &moved.tags["old\\weapons\\gun\\gun.weapon"])), "old\\weapons\\gun\\gun");
+        assert_eq!(model_path(&definitions, &read(&definitions, &moved.tags["other.weapon"])), "old\\weapons\\gun\\gun");
+        assert!(!directory.root.join("weapons").join("gun").join("gun.model").exists());
+    }
+
+    #[test]
+    fn move_onto_moved_tag() {
+        let definitions = definitions();
+        let directory = tags_directory(&definitions, "rename_move_onto_moved_tag", &[
+            ("a\\x.model", &model(&definitions, 1.0)),
+            ("a\\b\\x.model", &model(&definitions, 2.0)),
+            ("a\\x.weapon", &gun(&definitions, "a\\x"))
+        ]);
+
+        let moves = plan_moves(&directory, "a", "a\\b").unwrap();
+        assert_eq!(moves["a\\x.model"], "a\\b\\x.model");
+        assert_eq!(moves["a\\b\\x.model"], "a\\b\\b\\x.model");
+
+        rename_tags(&definitions, &directory, "a", "a\\b", false).unwrap();
+        let moved = TagDirectory::scan(&definitions, &directory.root).unwrap();
+        assert_eq!(moved.tags.keys().collect::<Vec<_>>(), vec!["a\\b\\b\\x.model", "a\\b\\x.model", "a\\b\\x.weapon"]);
+        assert_eq!(read(&definitions, &moved.tags["a\\b\\x.model"]), model(&definitions, 1.0));
+        assert_eq!(read(&definitions, &moved.tags["a\\b\\b\\x.model"]), model(&definitions, 2.0));
+        assert_eq!(model_path(&definitions, &read(&definitions, &moved.tags["a\\b\\x.weapon"])), "a\\b\\x");
+    }
+
+    #[test]
+    fn change_case() {
+        let definitions = definitions();
+        let directory = tags_directory(&definitions, "rename_change_case", &[
+            ("gun.model", &model(&definitions, 1.0)),
+            ("gun.weapon", &gun(&definitions, "gun"))
+        ]);
+
+        rename_tags(&definitions, &directory, "gun.model", "GUN.model", false).unwrap();
+        let moved = TagDirectory::scan(&definitions, &directory.root).unwrap();
+        assert_eq!(moved.tags.keys().collect::<Vec<_>>(), vec!["gun.model", "gun.weapon"]);
+        assert_eq!(moved.tags["gun.model"], directory.root.join("GUN.model"));
+        assert_eq!(read(&definitions, &moved.tags["gun.model"]), model(&definitions, 1.0));
+        assert_eq!(model_path(&definitions, &read(&definitions, &moved.tags["gun.weapon"])), "GUN");
+        assert_eq!(std::fs::read_dir(&directory.root).unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,417 @@
+//! Values of fields in tag files
+
+extern crate byteorder;
+use self::byteorder::{ByteOrder, BigEndian};
+
+use def_dumper::{Block, BlockFieldType, Definitions};
+use super::layout::{StructLayout, LayoutEntry};
+
+/// A tag reference
+#[derive(Clone, PartialEq, Debug)]
+pub struct TagReference {
+    /// FourCC of the referenced group (or 0xFFFFFFFF if none)
+    pub fourcc: u32,
+    pub path: String
+}
+
+impl TagReference {
+    /// Get the name of the referenced group (or `None` if there isn't one)
+    ///
+    /// Groups that aren't in the definitions are given as their FourCC.
+    pub fn group(&self, definitions: &Definitions) -> Option<String> {
+        if self.fourcc == 0xFFFFFFFF {
+            return None
+        }
+        Some(match definitions.group_by_fourcc(self.fourcc) {
+            Some((name, _)) => name.to_owned(),
+            None => super::json::fourcc_to_string(self.fourcc)
+        })
+    }
+
+    /// Get the path of the referenced tag with its group as an extension (or `None` if nothing is referenced)
+    pub fn file_path(&self, definitions: &Definitions) -> Option<String> {
+        if self.path.is_empty() {
+            return None
+        }
+        Some(match self.group(definitions) {
+            Some(group) => format!("{}.{}", self.path, group),
+            None => self.path.to_owned()
+        })
+    }
+}
+
+/// A value of a field
+#[derive(Clone, PartialEq, Debug)]
+pub enum FieldValue {
+    String(String),
+    Integer(i64),
+    Integers(Vec<i64>),
+    Float(f32),
+    Floats(Vec<f32>),
+    FourCC(u32),
+    Reference(TagReference),
+    Block(Vec<TagStruct>),
+    Array(Vec<TagStruct>),
+    Data(Vec<u8>),
+
+    /// Padding and anything else we can't interpret, kept as-is
+    Raw(Vec<u8>)
+}
+
+/// A block element, holding one value for each entry in its [`StructLayout`]
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct TagStruct {
+    pub values: Vec<FieldValue>
+}
+
+/// Describes how a primitive is stored: how many components, how big each one is, and whether they're floats
+fn primitive_components(type_name: &str) -> (usize, usize, bool) {
+    match type_name {
+        "int8" => (1, 1, false),
+        "int16" => (1, 2, false),
+        "int32" => (1, 4, false),
+        "point2d_int" => (2, 2, false),
+        "rectangle" => (4, 2, false),
+        "float" | "float_clamped" | "float_angle" => (1, 4, true),
+        "point2d" | "vector2d" | "euler2d" => (2, 4, true),
+        "point3d" | "vector3d" | "euler3d" | "plane2d" | "color_rgb" | "color_hsv" => (3, 4, true),
+        "quaternion" | "plane3d" | "color_argb" | "color_ahsv" => (4, 4, true),
+        _ => (0, 0, false)
+    }
+}
+
+fn read_int(data: &[u8], size: usize) -> i64 {
+    match size {
+        1 => data[0] as i8 as i64,
+        2 => BigEndian::read_i16(data) as i64,
+        _ => BigEndian::read_i32(data) as i64
+    }
+}
+
+fn write_int(data: &mut [u8], size: usize, value: i64) {
+    match size {
+        1 => data[0] = value as u8,
+        2 => BigEndian::write_u16(data, value as u16),
+        _ => BigEndian::write_u32(data, value as u32)
+    }
+}
+
+/// Read a number of primitive components, returning them as one value
+fn read_components(data: &[u8], count: usize, size: usize, float: bool, bounds: bool) -> FieldValue {
+    let count = if bounds { count * 2 } else { count };
+    if float {
+        let floats: Vec<f32> = (0..count).map(|i| BigEndian::read_f32(&data[i*4..])).collect();
+        if count == 1 { FieldValue::Float(floats[0]) } else { FieldValue::Floats(floats) }
+    }
+    else {
+        let ints: Vec<i64> = (0..count).map(|i| read_int(&data[i*size..], size)).collect();
+        if count == 1 { FieldValue::Integer(ints[0]) } else { FieldValue::Integers(ints) }
+    }
+}
+
+/// Get the components of a primitive (or the bounds of a range) as the tuple returned by `primitive_components`
+fn components_of(block_type: &BlockFieldType) -> Option<(usize, usize, bool, bool)> {
+    match block_type {
+        BlockFieldType::Primitive(type_name) if primitive_components(type_name).0 > 0 => {
+            let (count, size, float) = primitive_components(type_name);
+            Some((count, size, float, false))
+        },
+        BlockFieldType::Range(type_name) => {
+            let (count, size, float) = primitive_components(type_name);
+            Some((count, size, float, true))
+        },
+        _ => None
+    }
+}
+
+impl TagStruct {
+    /// Make a new block element with default values
+    ///
+    /// Everything is zeroed, except block indices which are set to none (-1) and tag references which default to the
+    /// referenced group if only one is allowed.
+    pub fn new(definitions: &Definitions, layout: &StructLayout) -> TagStruct {
+        TagStruct {
+            values: layout.entries.iter().map(|e| default_value(definitions, e)).collect()
+        }
+    }
+
+    /// Get every tag reference in the element, including ones in nested blocks
+    pub fn references<'a>(&'a self, layout: &StructLayout) -> Vec<&'a TagReference> {
+        let mut references = Vec::new();
+        for (e, value) in layout.entries.iter().zip(self.values.iter()) {
+            match (value, &e.field.block_type) {
+                (FieldValue::Reference(reference), _) => references.push(reference),
+                (FieldValue::Block(elements), BlockFieldType::Block(block)) => {
+                    let layout = StructLayout::of(block);
+                    for element in elements {
+                        references.append(&mut element.references(&layout));
+                    }
+                },
+                (FieldValue::Array(elements), _) => if let Some(array) = &e.array {
+                    for element in elements {
+                        references.append(&mut element.references(&array.element));
+                    }
+                },
+                _ => ()
+            }
+        }
+        references
+    }
+
+    /// Call `f` for every tag reference in the element, including ones in nested blocks
+    pub fn visit_references_mut(&mut self, layout: &StructLayout, f: &mut dyn FnMut(&mut TagReference)) {
+        for (e, value) in layout.entries.iter().zip(self.values.iter_mut()) {
+            match (value, &e.field.block_type) {
+                (FieldValue::Reference(reference), _) => f(reference),
+                (FieldValue::Block(elements), BlockFieldType::Block(block)) => {
+                    let layout = StructLayout::of(block);
+                    for element in elements {
+                        element.visit_references_mut(&layout, f);
+                    }
+                },
+                (FieldValue::Array(elements), _) => if let Some(array) = &e.array {
+                    for element in elements {
+                        element.visit_references_mut(&array.element, f);
+                    }
+                },
+                _ => ()
+            }
+        }
+    }
+
+    /// Read the fixed-size part of an element (i.e. not anything stored after the block's elements)
+    fn read_fixed(layout: &StructLayout, data: &[u8]) -> Option<TagStruct> {
+        let mut values = Vec::<FieldValue>::with_capacity(layout.entries.len());
+
+        for e in &layout.entries {
+            let field_data = data.get(e.offset..e.offset + e.size)?;
+
+            if let Some(array) = &e.array {
+                let mut elements = Vec::<TagStruct>::with_capacity(array.count);
+                for i in 0..array.count {
+                    elements.push(TagStruct::read_fixed(&array.element, &field_data[i * array.element.size..])?);
+                }
+                values.push(FieldValue::Array(elements));
+                continue;
+            }
+
+            if let Some((count, size, float, bounds)) = components_of(&e.field.block_type) {
+                values.push(read_components(field_data, count, size, float, bounds));
+                continue;
+            }
+
+            values.push(match &e.field.block_type {
+                BlockFieldType::Primitive("string") => FieldValue::String(super::read_fixed_string(field_data)),
+                BlockFieldType::Primitive("fourcc") => FieldValue::FourCC(BigEndian::read_u32(field_data)),
+                BlockFieldType::Primitive("color_rgb_int") | BlockFieldType::Primitive("color_argb_int") => FieldValue::Integer(BigEndian::read_u32(field_data) as i64),
+                BlockFieldType::Enum(_) => FieldValue::Integer(read_int(field_data, 2)),
+                BlockFieldType::Index(_, _) => FieldValue::Integer(read_int(field_data, e.size)),
+                BlockFieldType::Flags(_, _) => FieldValue::Integer(read_int(field_data, e.size) & ((1i64 << (e.size * 8)) - 1)),
+
+                // These get filled in when reading children
+                BlockFieldType::Reference(_) => FieldValue::Reference(TagReference { fourcc: BigEndian::read_u32(field_data), path: String::new() }),
+                BlockFieldType::Block(_) => FieldValue::Block(Vec::new()),
+                BlockFieldType::TagData(_, _) => FieldValue::Data(Vec::new()),
+
+                _ => FieldValue::Raw(field_data.to_owned())
+            });
+        }
+
+        Some(TagStruct { values })
+    }
+
+    /// Read everything stored after the element (tag reference paths, tag data, and block elements)
+    fn read_children(&mut self, cursor: &mut Cursor, layout: &StructLayout, data: &[u8]) -> Option<()> {
+        for (e, value) in layout.entries.iter().zip(self.values.iter_mut()) {
+            let field_data = &data[e.offset..e.offset + e.size];
+            match (value, &e.field.block_type) {
+                (FieldValue::Array(elements), _) => {
+                    let array = e.array.as_ref().unwrap();
+                    for (i, element) in elements.iter_mut().enumerate() {
+                        element.read_children(cursor, &array.element, &field_data[i * array.element.size..])?;
+                    }
+                },
+                (FieldValue::Reference(reference), _) => {
+                    let length = BigEndian::read_u32(&field_data[8..]) as usize;
+                    if length > 0 {
+                        reference.path = super::read_fixed_string(cursor.take(length + 1)?);
+                    }
+                },
+                (FieldValue::Data(payload), _) => {
+                    let size = BigEndian::read_u32(field_data) as usize;
+                    *payload = cursor.take(size)?.to_owned();
+                },
+                (FieldValue::Block(elements), BlockFieldType::Block(block)) => {
+                    let count = BigEndian::read_u32(field_data) as usize;
+                    *elements = read_block_elements(cursor, block, count)?;
+                },
+                _ => ()
+            }
+        }
+        Some(())
+    }
+
+    /// Write the fixed-size part of an element
+    fn write_fixed(&self, layout: &StructLayout, data: &mut [u8]) -> Option<()> {
+        for (e, value) in layout.entries.iter().zip(self.values.iter()) {
+            let field_data = &mut data[e.offset..e.offset + e.size];
+
+            match value {
+                FieldValue::Array(elements) => {
+                    let array = e.array.as_ref()?;
+                    for (i, element) in elements.iter().enumerate() {
+                        element.write_fixed(&array.element, &mut field_data[i * array.element.size..])?;
+                    }
+                },
+                FieldValue::String(string) => {
+                    let bytes = string.as_bytes();
+                    let length = bytes.len().min(field_data.len() - 1);
+                    field_data[..length].copy_from_slice(&bytes[..length]);
+                },
+                FieldValue::Integer(n) => write_int(field_data, e.size, *n),
+                FieldValue::Integers(n) => {
+                    let size = e.size / n.len().max(1);
+                    for (i, n) in n.iter().enumerate() {
+                        write_int(&mut field_data[i*size..], size, *n);
+                    }
+                },
+                FieldValue::Float(n) => BigEndian::write_f32(field_data, *n),
+                FieldValue::Floats(n) => for (i, n) in n.iter().enumerate() {
+                    BigEndian::write_f32(&mut field_data[i*4..], *n);
+                },
+                FieldValue::FourCC(n) => BigEndian::write_u32(field_data, *n),
+                FieldValue::Reference(reference) => {
+                    BigEndian::write_u32(&mut field_data[0..], reference.fourcc);
+                    BigEndian::write_u32(&mut field_data[8..], reference.path.len() as u32);
+                    BigEndian::write_u32(&mut field_data[12..], 0xFFFFFFFF);
+                },
+                FieldValue::Block(elements) => BigEndian::write_u32(field_data, elements.len() as u32),
+                FieldValue::Data(payload) => BigEndian::write_u32(field_data, payload.len() as u32),
+                FieldValue::Raw(bytes) => field_data.copy_from_slice(bytes)
+            }
+        }
+        Some(())
+    }
+
+    /// Write everything stored after the element
+    fn write_children(&self, definitions: &Definitions, layout: &StructLayout, output: &mut Vec<u8>) -> Option<()> {
+        for (e, value) in layout.entries.iter().zip(self.values.iter()) {
+            match (value, &e.field.block_type) {
+                (FieldValue::Array(elements), _) => {
+                    let array = e.array.as_ref()?;
+                    for element in elements {
+                        element.write_children(definitions, &array.element, output)?;
+                    }
+                },
+                (FieldValue::Reference(reference), _) if !reference.path.is_empty() => {
+                    output.extend_from_slice(reference.path.as_bytes());
+                    output.push(0);
+                },
+                (FieldValue::Data(payload), _) => output.extend_from_slice(payload),
+                (FieldValue::Block(elements), BlockFieldType::Block(block)) => write_block_elements(definitions, block, elements, output)?,
+                _ => ()
+            }
+        }
+        Some(())
+    }
+}
+
+/// Get the default value of a field
+pub fn default_value(definitions: &Definitions, entry: &LayoutEntry) -> FieldValue {
+    if let Some(array) = &entry.array {
+        return FieldValue::Array((0..array.count).map(|_| TagStruct::new(definitions, &array.element)).collect())
+    }
+
+    if let Some((count, _, float, bounds)) = components_of(&entry.field.block_type) {
+        let count = if bounds { count * 2 } else { count };
+        return match (count, float) {
+            (1, true) => FieldValue::Float(0.0),
+            (1, false) => FieldValue::Integer(0),
+            (_, true) => FieldValue::Floats(vec![0.0; count]),
+            (_, false) => FieldValue::Integers(vec![0; count])
+        }
+    }
+
+    match &entry.field.block_type {
+        BlockFieldType::Primitive("string") => FieldValue::String(String::new()),
+        BlockFieldType::Primitive("fourcc") => FieldValue::FourCC(0),
+        BlockFieldType::Primitive("color_rgb_int") | BlockFieldType::Primitive("color_argb_int") => FieldValue::Integer(0),
+        BlockFieldType::Enum(_) | BlockFieldType::Flags(_, _) => FieldValue::Integer(0),
+        BlockFieldType::Index(_, _) => FieldValue::Integer(-1),
+        BlockFieldType::Reference(allowed_groups) => FieldValue::Reference(TagReference {
+            fourcc: match allowed_groups.as_slice() {
+                [group] => definitions.groups.get(group).map(|g| g.fourcc).unwrap_or(0xFFFFFFFF),
+                _ => 0xFFFFFFFF
+            },
+            path: String::new()
+        }),
+        BlockFieldType::Block(_) => FieldValue::Block(Vec::new()),
+        BlockFieldType::TagData(_, _) => FieldValue::Data(Vec::new()),
+        _ => FieldValue::Raw(vec![0; entry.size])
+    }
+}
+
+/// Reads data sequentially
+pub struct Cursor<'a> {
+    pub data: &'a [u8]
+}
+impl<'a> Cursor<'a> {
+    /// Take the next `size` bytes
+    pub fn take(&mut self, size: usize) -> Option<&'a [u8]> {
+        if size > self.data.len() {
+            return None
+        }
+        let (taken, remaining) = self.data.split_at(size);
+        self.data = remaining;
+        Some(taken)
+    }
+}
+
+/// Get the size of each element of a block, making sure it's consistent with its definition
+fn element_length(block: &Block, layout: &StructLayout) -> Option<usize> {
+    if layout.size > block.length {
+        eprintln!("Block {} has fields that add up to 0x{:X} bytes, but it is 0x{:X} bytes", block.name.as_deref().unwrap_or("no-name"), layout.size, block.length);
+        return None
+    }
+    Some(block.length)
+}
+
+/// Read `count` elements of a block
+pub fn read_block_elements(cursor: &mut Cursor, block: &Block, count: usize) -> Option<Vec<TagStruct>> {
+    let layout = StructLayout::of(block);
+    let length = element_length(block, &layout)?;
+
+    // Elements that take up no space can't be checked against how much data is left
+    if length == 0 && count > block.maximum {
+        eprintln!("Block {} has {} elements, but it can only have {}", block.name.as_deref().unwrap_or("no-name"), count, block.maximum);
+        return None
+    }
+    let data = cursor.take(length.checked_mul(count)?)?;
+
+    let mut elements = Vec::<TagStruct>::with_capacity(count);
+    for i in 0..count {
+        elements.push(TagStruct::read_fixed(&layout, &data[i*length..])?);
+    }
+    for (i, element) in elements.iter_mut().enumerate() {
+        element.read_children(cursor, &layout, &data[i*length..])?;
+    }
+
+    Some(elements)
+}
+
+/// Write the elements of a block
+pub fn write_block_elements(definitions: &Definitions, block: &Block, elements: &[TagStruct], output: &mut Vec<u8>) -> Option<()> {
+    let layout = StructLayout::of(block);
+    let length = element_length(block, &layout)?;
+
+    let start = output.len();
+    output.resize(start + length * elements.len(), 0);
+    for (i, element) in elements.iter().enumerate() {
+        element.write_fixed(&layout, &mut output[start + i*length..start + (i+1)*length])?;
+    }
+    for element in elements {
+        element.write_children(definitions, &layout, output)?;
+    }
+
+    Some(())
+}